@@ -0,0 +1,505 @@
+//! Throughput of `LRUCache` under a mixed read/write workload and its hit
+//! rate per eviction policy on a zipfian workload, then with and without
+//! fold rebalancing on a workload skewed onto half the folds, followed by
+//! `ShardedLruCache` with modulo (`new`) and bit-mask (`new_pow2`) shard
+//! routing, `ShardedLruCache` against the read-locked `ClockShardedCache`
+//! on a read-mostly workload, `LRUCache` hits on neighbouring slots and on
+//! one hot key with exact, sampled and striped hit counts, `LRUCache` put
+//! latency as a single fold fills up, `LRUCache` lookup latency by fold
+//! size, and the worst-case `EvictionPolicy::Clock` put by fold size.
+//!
+//! Run with `cargo run --release --example benchmark`. For the per-slot
+//! counter layout, compare that table against a run with
+//! `--features padded-slots`.
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lru_rs::{EvictionPolicy, FibonacciHasher, FoldHasher, LRUCache};
+use solution_2::{ClockShardedCache, ShardedLruCache};
+
+const CAPACITY: usize = 100_000;
+const FOLDS: usize = 64;
+const OPS_PER_THREAD: usize = 500_000;
+/// Keys are drawn from a range larger than the cache so puts keep evicting.
+const KEY_SPACE: usize = 4 * CAPACITY;
+
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// Per-thread xorshift stream; the top bits pick hot (80%) or cold keys.
+fn next_key(state: &mut u64) -> usize {
+    let r = xorshift(state) as usize;
+    if r % 10 < 8 {
+        1 + (r >> 8) % (CAPACITY / 5)
+    } else {
+        1 + (r >> 8) % KEY_SPACE
+    }
+}
+
+/// Zipf(`s`) over the keys `1..=n`, sampled by inverting its CDF.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: usize, s: f64) -> Self {
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = (1..=n)
+            .map(|rank| {
+                total += (rank as f64).powf(-s);
+                total
+            })
+            .collect();
+        for c in &mut cdf {
+            *c /= total;
+        }
+        Self { cdf }
+    }
+
+    fn sample(&self, state: &mut u64) -> usize {
+        let u = xorshift(state) as f64 / u64::MAX as f64;
+        1 + self.cdf.partition_point(|&c| c < u).min(self.cdf.len() - 1)
+    }
+}
+
+/// Hit rate of a single thread reading zipfian keys through `policy`,
+/// putting each missed key.
+fn zipf_hit_rate(policy: EvictionPolicy, zipf: &Zipf) -> f64 {
+    let cache: LRUCache<AtomicUsize, AtomicUsize, _> =
+        LRUCache::with_hasher(CAPACITY, FOLDS, FibonacciHasher::default(), policy);
+    replay_zipf(&cache, zipf)
+}
+
+/// Like [`zipf_hit_rate`] under LRU, with every key hashed onto an even
+/// fold so the odd ones only fill by rebalancing at `percent`.
+fn skewed_hit_rate(percent: usize, zipf: &Zipf) -> f64 {
+    let cache: LRUCache<AtomicUsize, AtomicUsize, _> =
+        LRUCache::with_hasher(CAPACITY, FOLDS, |k: usize| 2 * k, EvictionPolicy::Lru)
+            .with_rebalancing(percent);
+    replay_zipf(&cache, zipf)
+}
+
+fn replay_zipf<H: FoldHasher>(cache: &LRUCache<AtomicUsize, AtomicUsize, H>, zipf: &Zipf) -> f64 {
+    let mut state = 0x9E37_79B9_7F4A_7C15;
+    let mut hits = 0;
+    for _ in 0..OPS_PER_THREAD {
+        let key = zipf.sample(&mut state);
+        if cache.get(key).is_some() {
+            hits += 1;
+        } else {
+            cache.put(key, key);
+        }
+    }
+    hits as f64 / OPS_PER_THREAD as f64
+}
+
+struct Run {
+    elapsed: Duration,
+    hits: usize,
+    gets: usize,
+}
+
+fn run(policy: EvictionPolicy, threads: usize, read_pct: usize) -> Run {
+    let cache: Arc<LRUCache<AtomicUsize, AtomicUsize, _>> = Arc::new(LRUCache::with_hasher(
+        CAPACITY,
+        FOLDS,
+        FibonacciHasher::default(),
+        policy,
+    ));
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                let mut state = 0x9E37_79B9 ^ ((t as u64 + 1) * 0x2545_F491);
+                let (mut hits, mut gets) = (0, 0);
+                barrier.wait();
+                for i in 0..OPS_PER_THREAD {
+                    let key = next_key(&mut state);
+                    if i % 100 < read_pct {
+                        gets += 1;
+                        match cache.get(key) {
+                            Some(_) => hits += 1,
+                            None => {
+                                cache.put(key, key);
+                            }
+                        }
+                    } else {
+                        cache.put(key, key);
+                    }
+                }
+                (hits, gets)
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    let (mut hits, mut gets) = (0, 0);
+    for h in handles {
+        let (h_, g) = h.join().unwrap();
+        hits += h_;
+        gets += g;
+    }
+    Run {
+        elapsed: start.elapsed(),
+        hits,
+        gets,
+    }
+}
+
+/// Time for `threads` threads to run the 50% read workload on `cache`.
+fn run_sharded(cache: ShardedLruCache<usize, usize>, threads: usize) -> Duration {
+    run_mixed(
+        cache,
+        threads,
+        50,
+        |c, k| c.get(&k).is_some(),
+        |c, k| {
+            c.put(k, k);
+        },
+    )
+}
+
+/// Time for `threads` threads to run a workload with `read_pct`% reads on
+/// `cache`, through `get` and `put`. A missed read is followed by a put.
+fn run_mixed<C: Send + Sync + 'static>(
+    cache: C,
+    threads: usize,
+    read_pct: usize,
+    get: fn(&C, usize) -> bool,
+    put: fn(&C, usize),
+) -> Duration {
+    let cache = Arc::new(cache);
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                let mut state = 0x9E37_79B9 ^ ((t as u64 + 1) * 0x2545_F491);
+                barrier.wait();
+                for i in 0..OPS_PER_THREAD {
+                    let key = next_key(&mut state);
+                    if i % 100 < read_pct && get(&cache, key) {
+                        continue;
+                    }
+                    put(&cache, key);
+                }
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    for h in handles {
+        h.join().unwrap();
+    }
+    start.elapsed()
+}
+
+/// Time for `threads` threads to each read their own key `OPS_PER_THREAD`
+/// times. The keys fill consecutive slots of one fold, so every hit writes
+/// the counters of a slot next to another thread's.
+fn run_neighbours(threads: usize) -> Duration {
+    let cache: Arc<LRUCache<AtomicUsize, AtomicUsize>> = Arc::new(LRUCache::new(threads, 1, |k| k));
+    for key in 0..threads {
+        cache.put(key, key);
+    }
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|key| {
+            let cache = Arc::clone(&cache);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..OPS_PER_THREAD {
+                    assert_eq!(cache.get(key), Some(key));
+                }
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    for h in handles {
+        h.join().unwrap();
+    }
+    start.elapsed()
+}
+
+/// Time for `threads` threads to each read one shared key `OPS_PER_THREAD`
+/// times under [`EvictionPolicy::Lfu`], counting 1 in `1 << shift` hits
+/// over `stripes` counters per slot.
+fn run_hot_key(shift: u32, stripes: usize, threads: usize) -> Duration {
+    let cache: Arc<LRUCache<AtomicUsize, AtomicUsize>> = Arc::new(
+        LRUCache::with_policy(64, 1, |k| k, EvictionPolicy::Lfu)
+            .with_hit_sampling(shift)
+            .with_hit_stripes(stripes),
+    );
+    cache.put(7, 7);
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let cache = Arc::clone(&cache);
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..OPS_PER_THREAD {
+                    assert_eq!(cache.get(7), Some(7));
+                }
+            })
+        })
+        .collect();
+    barrier.wait();
+    let start = Instant::now();
+    for h in handles {
+        h.join().unwrap();
+    }
+    start.elapsed()
+}
+
+/// Slots of the one fold [`put_latency`] fills.
+const FOLD_SLOTS: usize = 4096;
+const LATENCY_PUTS: usize = 20_000;
+
+/// Mean time of a put of a new key into a fold of [`FOLD_SLOTS`] slots
+/// that is `fill_pct` percent full. Below 100% each new key is removed
+/// again, outside the timing, so the fill level holds; at 100% every put
+/// evicts.
+fn put_latency(fill_pct: usize) -> Duration {
+    let cache: LRUCache<AtomicUsize, AtomicUsize> = LRUCache::new(FOLD_SLOTS, 1, |k| k);
+    for key in 0..FOLD_SLOTS * fill_pct / 100 {
+        cache.put(key, key);
+    }
+    let mut total = Duration::ZERO;
+    for key in KEY_SPACE..KEY_SPACE + LATENCY_PUTS {
+        let start = Instant::now();
+        cache.put(key, key);
+        total += start.elapsed();
+        if fill_pct < 100 {
+            cache.remove(key);
+        }
+    }
+    total / LATENCY_PUTS as u32
+}
+
+const CLOCK_ROUNDS: u32 = 3;
+
+/// Mean time of a put into a full [`EvictionPolicy::Clock`] fold of
+/// `fold_slots` slots right after every entry was hit, the worst case for
+/// the hand: every reference bit is set.
+fn clock_put_latency(fold_slots: usize) -> Duration {
+    let mut total = Duration::ZERO;
+    for _ in 0..CLOCK_ROUNDS {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> =
+            LRUCache::with_policy(fold_slots, 1, |k| k, EvictionPolicy::Clock);
+        for key in 0..fold_slots {
+            cache.put(key, key);
+        }
+        for key in 0..fold_slots {
+            cache.touch(key);
+        }
+        let start = Instant::now();
+        cache.put(KEY_SPACE, KEY_SPACE);
+        total += start.elapsed();
+    }
+    total / CLOCK_ROUNDS
+}
+
+const SCAN_GETS: usize = 20_000;
+
+/// Mean time of a `get` that scans a full fold of `fold_slots` slots to its
+/// end: a hit on the key in the last slot, and a miss.
+fn scan_latency(fold_slots: usize) -> (Duration, Duration) {
+    let cache: LRUCache<AtomicUsize, AtomicUsize> = LRUCache::new(fold_slots, 1, |k| k);
+    for key in 1..=fold_slots {
+        cache.put(key, key);
+    }
+    let time = |key| {
+        let start = Instant::now();
+        for _ in 0..SCAN_GETS {
+            std::hint::black_box(cache.get(std::hint::black_box(key)));
+        }
+        start.elapsed() / SCAN_GETS as u32
+    };
+    (time(fold_slots), time(KEY_SPACE))
+}
+
+fn main() {
+    println!(
+        "{:<6} {:>7} {:>6} {:>14} {:>9}",
+        "policy", "threads", "read%", "ops/sec", "hit rate"
+    );
+    for policy in EvictionPolicy::ALL {
+        for threads in [1, 4, 16, 32] {
+            for read_pct in [10, 50, 90] {
+                let r = run(policy, threads, read_pct);
+                let ops = (threads * OPS_PER_THREAD) as f64;
+                println!(
+                    "{:<6} {:>7} {:>6} {:>14.0} {:>8.1}%",
+                    format!("{policy:?}"),
+                    threads,
+                    read_pct,
+                    ops / r.elapsed.as_secs_f64(),
+                    100.0 * r.hits as f64 / r.gets.max(1) as f64
+                );
+            }
+        }
+    }
+
+    println!();
+    println!("{:<6} {:>9}", "policy", "zipf hit");
+    let zipf = Zipf::new(KEY_SPACE, 0.99);
+    for policy in EvictionPolicy::ALL {
+        println!(
+            "{:<6} {:>8.1}%",
+            format!("{policy:?}"),
+            100.0 * zipf_hit_rate(policy, &zipf)
+        );
+    }
+
+    println!();
+    println!("{:<9} {:>9}", "rebalance", "zipf hit");
+    for (label, percent) in [("off", 0), ("50%", 50)] {
+        println!(
+            "{:<9} {:>8.1}%",
+            label,
+            100.0 * skewed_hit_rate(percent, &zipf)
+        );
+    }
+
+    // 63 shards divide on every lookup; 64 mask.
+    println!();
+    println!(
+        "{:<9} {:>7} {:>6} {:>14}",
+        "routing", "threads", "shards", "ops/sec"
+    );
+    for threads in [1, 4, 16, 32] {
+        for (routing, shards, cache) in [
+            ("modulo", 63, ShardedLruCache::new(CAPACITY, 63)),
+            ("mask", 64, ShardedLruCache::new_pow2(CAPACITY, 63)),
+        ] {
+            let elapsed = run_sharded(cache, threads);
+            let ops = (threads * OPS_PER_THREAD) as f64;
+            println!(
+                "{:<9} {:>7} {:>6} {:>14.0}",
+                routing,
+                threads,
+                shards,
+                ops / elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    // Exact LRU relinks on every hit under a mutex; CLOCK sets a bit under
+    // a shared lock.
+    println!();
+    println!(
+        "{:<9} {:>7} {:>6} {:>14}",
+        "shards", "threads", "read%", "ops/sec"
+    );
+    for threads in [1, 4, 16, 32] {
+        let ops = (threads * OPS_PER_THREAD) as f64;
+        let lru = run_mixed(
+            ShardedLruCache::new(CAPACITY, 16),
+            threads,
+            95,
+            |c, k| c.get(&k).is_some(),
+            |c, k| {
+                c.put(k, k);
+            },
+        );
+        let clock = run_mixed(
+            ClockShardedCache::new(CAPACITY, 16),
+            threads,
+            95,
+            |c, k| c.get(&k).is_some(),
+            |c, k| {
+                c.put(k, k);
+            },
+        );
+        for (shards, elapsed) in [("mutex", lru), ("rwlock", clock)] {
+            println!(
+                "{:<9} {:>7} {:>6} {:>14.0}",
+                shards,
+                threads,
+                95,
+                ops / elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    println!();
+    println!("{:<8} {:>7} {:>14}", "counters", "threads", "ops/sec");
+    let layout = if cfg!(feature = "padded-slots") {
+        "padded"
+    } else {
+        "packed"
+    };
+    for threads in [1, 4, 16, 32] {
+        let elapsed = run_neighbours(threads);
+        let ops = (threads * OPS_PER_THREAD) as f64;
+        println!(
+            "{:<8} {:>7} {:>14.0}",
+            layout,
+            threads,
+            ops / elapsed.as_secs_f64()
+        );
+    }
+
+    println!();
+    println!("{:<10} {:>7} {:>14}", "hit counts", "threads", "ops/sec");
+    for (label, shift, stripes) in [("exact", 0, 1), ("1/16", 4, 1), ("8 stripes", 0, 8)] {
+        for threads in [1, 4, 16, 32] {
+            let elapsed = run_hot_key(shift, stripes, threads);
+            let ops = (threads * OPS_PER_THREAD) as f64;
+            println!(
+                "{:<10} {:>7} {:>14.0}",
+                label,
+                threads,
+                ops / elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    println!();
+    println!("{:<5} {:>11}", "fill", "ns per put");
+    for fill_pct in [50, 90, 99, 100] {
+        println!(
+            "{:<5} {:>11}",
+            format!("{fill_pct}%"),
+            put_latency(fill_pct).as_nanos()
+        );
+    }
+
+    println!();
+    println!(
+        "{:<10} {:>11} {:>12}",
+        "fold slots", "ns per hit", "ns per miss"
+    );
+    for fold_slots in [64, 1024, 4096, 16_384] {
+        let (hit, miss) = scan_latency(fold_slots);
+        println!(
+            "{:<10} {:>11} {:>12}",
+            fold_slots,
+            hit.as_nanos(),
+            miss.as_nanos()
+        );
+    }
+
+    println!();
+    println!("{:<10} {:>16}", "fold slots", "ns per clock put");
+    for fold_slots in [1024, 4096, 16_384] {
+        println!(
+            "{:<10} {:>16}",
+            fold_slots,
+            clock_put_latency(fold_slots).as_nanos()
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! A shim with the method names and semantics of the `lru` crate's
+//! `LruCache`, so code migrating off that crate can switch by changing the
+//! type.
+
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+
+use crate::LruCache;
+
+/// [`LruCache`] behind the `lru` crate's API.
+///
+/// Used from a single thread it behaves like `lru::LruCache`, with these
+/// differences that follow from being shareable:
+///
+/// * Every method takes `&self`, so the cache can sit in an `Arc` and be
+///   used from several threads.
+/// * `get`, `peek` and `get_mut` cannot hand out references into the cache,
+///   which would outlive its lock. `get` and `peek` return clones, and
+///   `get_mut` runs a closure on the value in place.
+pub struct LruCacheCompat<K, V> {
+    inner: LruCache<K, V>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruCacheCompat<K, V> {
+    /// Creates a cache holding at most `cap` entries.
+    pub fn new(cap: NonZeroUsize) -> Self {
+        Self {
+            inner: LruCache::new(cap.get()),
+        }
+    }
+
+    /// Inserts or updates `k`, marking it most recently used. Returns the
+    /// old value if `k` was cached; an entry evicted to make room is
+    /// dropped.
+    pub fn put(&self, k: K, v: V) -> Option<V> {
+        self.inner.put(k, v).previous
+    }
+
+    /// Like [`put`](Self::put), but returns the displaced entry: `k` with
+    /// its old value if it was cached, otherwise the evicted entry, if any.
+    pub fn push(&self, k: K, v: V) -> Option<(K, V)> {
+        let outcome = self.inner.put(k.clone(), v);
+        match outcome.previous {
+            Some(old) => Some((k, old)),
+            None => outcome.evicted,
+        }
+    }
+
+    /// A clone of the value for `k`, marking it most recently used.
+    pub fn get(&self, k: &K) -> Option<V> {
+        self.inner.get(k)
+    }
+
+    /// Runs `f` on the value for `k` in place, marking it most recently
+    /// used. Returns `f`'s result, or `None` if `k` is not cached.
+    ///
+    /// `f` runs with the cache locked, so it must not use the cache.
+    pub fn get_mut<R>(&self, k: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        self.inner.update(k, f)
+    }
+
+    /// A clone of the value for `k`, without changing the recency order.
+    pub fn peek(&self, k: &K) -> Option<V> {
+        self.inner.peek(k)
+    }
+
+    /// Whether `k` is cached, without changing the recency order.
+    pub fn contains(&self, k: &K) -> bool {
+        self.inner.contains_key(k)
+    }
+
+    /// Removes `k`, returning its value if it was cached.
+    pub fn pop(&self, k: &K) -> Option<V> {
+        self.inner.remove(k)
+    }
+
+    /// Removes and returns the least recently used entry.
+    pub fn pop_lru(&self) -> Option<(K, V)> {
+        self.inner.pop_lru()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn cap(&self) -> NonZeroUsize {
+        NonZeroUsize::new(self.inner.capacity()).expect("capacity is never zero")
+    }
+
+    /// Changes the capacity, evicting least recently used entries until the
+    /// cache fits.
+    pub fn resize(&self, cap: NonZeroUsize) {
+        self.inner.resize(cap.get());
+    }
+
+    pub fn clear(&self) {
+        self.inner.clear();
+    }
+}
+
+/// Ports of the `lru` crate's documentation examples, with `&V` results
+/// replaced by clones.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cap(n: usize) -> NonZeroUsize {
+        NonZeroUsize::new(n).unwrap()
+    }
+
+    #[test]
+    fn crate_level_example() {
+        let cache = LruCacheCompat::new(cap(2));
+        cache.put("apple", 3);
+        cache.put("banana", 2);
+
+        assert_eq!(cache.get(&"apple"), Some(3));
+        assert_eq!(cache.get(&"banana"), Some(2));
+        assert!(cache.get(&"pear").is_none());
+
+        assert_eq!(cache.put("banana", 4), Some(2));
+        assert_eq!(cache.put("pear", 5), None);
+
+        assert_eq!(cache.get(&"pear"), Some(5));
+        assert_eq!(cache.get(&"banana"), Some(4));
+        assert!(cache.get(&"apple").is_none());
+
+        cache.get_mut(&"banana", |v| *v = 6);
+        assert_eq!(cache.get(&"banana"), Some(6));
+    }
+
+    #[test]
+    fn push_returns_displaced_entry() {
+        let cache = LruCacheCompat::new(cap(2));
+        assert_eq!(cache.push(1, "a"), None);
+        assert_eq!(cache.push(2, "b"), None);
+        // An update returns the key with its old value.
+        assert_eq!(cache.push(2, "beta"), Some((2, "b")));
+        // A new key in a full cache returns the evicted entry.
+        assert_eq!(cache.push(3, "c"), Some((1, "a")));
+        assert_eq!(cache.get(&2), Some("beta"));
+    }
+
+    #[test]
+    fn get_mut_on_missing_key() {
+        let cache: LruCacheCompat<u32, u32> = LruCacheCompat::new(cap(2));
+        assert_eq!(cache.get_mut(&1, |v| *v += 1), None);
+        cache.put(1, 1);
+        assert_eq!(
+            cache.get_mut(&1, |v| {
+                *v += 1;
+                *v
+            }),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn peek_and_contains_keep_order() {
+        let cache = LruCacheCompat::new(cap(2));
+        cache.put(1, "a");
+        cache.put(2, "b");
+        assert_eq!(cache.peek(&1), Some("a"));
+        assert_eq!(cache.peek(&2), Some("b"));
+        // Neither peek nor contains counted as a use of 1.
+        assert!(cache.contains(&1));
+        cache.put(3, "c");
+        assert!(!cache.contains(&1));
+        assert!(cache.contains(&2));
+        assert!(cache.contains(&3));
+    }
+
+    #[test]
+    fn pop_removes_one_key() {
+        let cache = LruCacheCompat::new(cap(2));
+        cache.put(2, "a");
+        assert_eq!(cache.pop(&1), None);
+        assert_eq!(cache.pop(&2), Some("a"));
+        assert_eq!(cache.pop(&2), None);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn pop_lru_takes_the_oldest() {
+        let cache = LruCacheCompat::new(cap(2));
+        cache.put(2, "a");
+        cache.put(3, "b");
+        cache.put(4, "c");
+        cache.get(&3);
+        assert_eq!(cache.pop_lru(), Some((4, "c")));
+        assert_eq!(cache.pop_lru(), Some((3, "b")));
+        assert_eq!(cache.pop_lru(), None);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn resize_grows_and_shrinks() {
+        let cache = LruCacheCompat::new(cap(2));
+        cache.put(1, "a");
+        cache.put(2, "b");
+        cache.resize(cap(4));
+        cache.put(3, "c");
+        cache.put(4, "d");
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.cap(), cap(4));
+        assert_eq!(cache.get(&1), Some("a"));
+        assert_eq!(cache.get(&2), Some("b"));
+        assert_eq!(cache.get(&3), Some("c"));
+        assert_eq!(cache.get(&4), Some("d"));
+
+        cache.resize(cap(2));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3), Some("c"));
+        assert_eq!(cache.get(&4), Some("d"));
+    }
+
+    #[test]
+    fn clear_and_is_empty() {
+        let cache = LruCacheCompat::new(cap(2));
+        assert!(cache.is_empty());
+        cache.put(1, 1);
+        assert!(!cache.is_empty());
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,961 @@
+//! A straightforward thread-safe LRU cache.
+//!
+//! One [`Mutex`] guards a `HashMap` from keys to list nodes and a doubly
+//! linked MRU→LRU list of those nodes. Every operation takes the lock, so the
+//! cache is exactly LRU and easy to reason about, at the cost of serializing
+//! all callers.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+pub mod compat;
+
+type Link<K, V> = Option<Arc<Mutex<Node<K, V>>>>;
+
+struct Node<K, V> {
+    key: K,
+    /// Shared so that [`LruCache::get_arc`] can hand it out without a copy.
+    value: Arc<V>,
+    /// Modification epoch of the last write to this node.
+    modified: u64,
+    prev: Link<K, V>,
+    next: Link<K, V>,
+}
+
+struct Inner<K, V> {
+    map: HashMap<K, Arc<Mutex<Node<K, V>>>>,
+    /// Most recently used node.
+    head: Link<K, V>,
+    /// Least recently used node.
+    tail: Link<K, V>,
+    capacity: usize,
+}
+
+/// Locks a mutex, recovering the data if a previous holder panicked.
+fn lock<T>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<K: Eq + Hash + Clone, V> Inner<K, V> {
+    /// Unlinks `node` from the list, leaving it in the map.
+    fn detach(&mut self, node: &Arc<Mutex<Node<K, V>>>) {
+        let (prev, next) = {
+            let mut n = lock(node);
+            (n.prev.take(), n.next.take())
+        };
+        match &prev {
+            Some(p) => lock(p).next = next.clone(),
+            None => self.head = next.clone(),
+        }
+        match &next {
+            Some(n) => lock(n).prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    /// Links a detached `node` in as the most recently used.
+    fn push_front(&mut self, node: &Arc<Mutex<Node<K, V>>>) {
+        {
+            let mut n = lock(node);
+            n.prev = None;
+            n.next = self.head.clone();
+        }
+        match &self.head {
+            Some(h) => lock(h).prev = Some(Arc::clone(node)),
+            None => self.tail = Some(Arc::clone(node)),
+        }
+        self.head = Some(Arc::clone(node));
+    }
+
+    fn move_to_front(&mut self, node: &Arc<Mutex<Node<K, V>>>) {
+        if self.head.as_ref().is_some_and(|h| Arc::ptr_eq(h, node)) {
+            return;
+        }
+        self.detach(node);
+        self.push_front(node);
+    }
+
+    /// Removes and returns the least recently used entry if the cache is
+    /// over capacity.
+    fn evict_if_needed(&mut self) -> Option<(K, Arc<V>)> {
+        if self.map.len() <= self.capacity {
+            return None;
+        }
+        self.pop_tail()
+    }
+
+    /// Unlinks, unmaps and returns the least recently used entry.
+    fn pop_tail(&mut self) -> Option<(K, Arc<V>)> {
+        let tail = self.tail.clone()?;
+        self.detach(&tail);
+        let key = lock(&tail).key.clone();
+        self.map.remove(&key);
+        Some(into_entry(tail))
+    }
+}
+
+impl<K, V> Inner<K, V> {
+    /// Breaks every list link, leaving `map` as each node's only owner.
+    ///
+    /// Neighbouring nodes hold strong references to each other, so without
+    /// this a list of two or more nodes is a cycle that is never freed.
+    fn unlink_all(&mut self) {
+        self.tail = None;
+        let mut cur = self.head.take();
+        while let Some(node) = cur {
+            cur = {
+                let mut n = lock(&node);
+                n.prev = None;
+                n.next.take()
+            };
+        }
+    }
+}
+
+impl<K, V> Drop for Inner<K, V> {
+    fn drop(&mut self) {
+        self.unlink_all();
+    }
+}
+
+/// Takes the key and value out of a node that is no longer linked or mapped.
+fn into_entry<K, V>(node: Arc<Mutex<Node<K, V>>>) -> (K, Arc<V>) {
+    let node = Arc::try_unwrap(node)
+        .ok()
+        .expect("unlinked node has no other owners")
+        .into_inner()
+        .unwrap_or_else(PoisonError::into_inner);
+    (node.key, node.value)
+}
+
+/// What a [`LruCache::put`] displaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PutOutcome<K, V> {
+    /// The value previously stored under the key, if it was cached.
+    pub previous: Option<V>,
+    /// The least recently used entry evicted to make room, if any.
+    pub evicted: Option<(K, V)>,
+}
+
+/// A thread-safe LRU cache guarded by a single lock.
+pub struct LruCache<K, V> {
+    inner: Mutex<Inner<K, V>>,
+    /// Bumped, with `inner` locked, by every operation that changes contents.
+    epoch: AtomicU64,
+}
+
+impl<K: Eq + Hash + Clone, V> LruCache<K, V> {
+    /// Creates a cache holding at most `capacity` entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be non-zero");
+        Self {
+            inner: Mutex::new(Inner {
+                map: HashMap::with_capacity(capacity),
+                head: None,
+                tail: None,
+                capacity,
+            }),
+            epoch: AtomicU64::new(0),
+        }
+    }
+
+    /// Creates a cache holding at most `capacity` entries and puts `entries`
+    /// in order, so the last one is the most recently used. If there are
+    /// more than `capacity`, the earliest ones are evicted as usual.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn from_entries<I: IntoIterator<Item = (K, V)>>(capacity: usize, entries: I) -> Self {
+        let cache = Self::new(capacity);
+        for (key, value) in entries {
+            cache.put_arc(key, value);
+        }
+        cache
+    }
+
+    /// Like [`put`](Self::put), but the overwritten value and the evicted
+    /// entry come as the cache stored them, behind an `Arc` that a
+    /// [`get_arc`](Self::get_arc) handle may still share, so `V` need not be
+    /// `Clone`.
+    pub fn put_arc(&self, key: K, value: V) -> PutOutcome<K, Arc<V>> {
+        let mut inner = lock(&self.inner);
+        let epoch = self.bump_epoch(&mut inner);
+        if let Some(node) = inner.map.get(&key).cloned() {
+            let old = {
+                let mut n = lock(&node);
+                n.modified = epoch;
+                std::mem::replace(&mut n.value, Arc::new(value))
+            };
+            inner.move_to_front(&node);
+            return PutOutcome {
+                previous: Some(old),
+                evicted: None,
+            };
+        }
+        let node = Arc::new(Mutex::new(Node {
+            key: key.clone(),
+            value: Arc::new(value),
+            modified: epoch,
+            prev: None,
+            next: None,
+        }));
+        inner.push_front(&node);
+        inner.map.insert(key, node);
+        PutOutcome {
+            previous: None,
+            evicted: inner.evict_if_needed(),
+        }
+    }
+
+    /// Like [`remove`](Self::remove), returning the value as shared as the
+    /// ones [`put_arc`](Self::put_arc) returns.
+    pub fn remove_arc(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = lock(&self.inner);
+        let node = inner.map.remove(key)?;
+        inner.detach(&node);
+        self.bump_epoch(&mut inner);
+        Some(into_entry(node).1)
+    }
+
+    /// Like [`pop_lru`](Self::pop_lru), returning the value as shared as the
+    /// ones [`put_arc`](Self::put_arc) returns.
+    pub fn pop_lru_arc(&self) -> Option<(K, Arc<V>)> {
+        let mut inner = lock(&self.inner);
+        let entry = inner.pop_tail()?;
+        self.bump_epoch(&mut inner);
+        Some(entry)
+    }
+
+    /// Changes the capacity, evicting least recently used entries until the
+    /// cache fits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn resize(&self, capacity: usize) {
+        assert!(capacity > 0, "capacity must be non-zero");
+        let mut inner = lock(&self.inner);
+        inner.capacity = capacity;
+        let mut evicted = false;
+        while inner.evict_if_needed().is_some() {
+            evicted = true;
+        }
+        if evicted {
+            self.bump_epoch(&mut inner);
+        }
+    }
+
+    /// Removes every entry.
+    pub fn clear(&self) {
+        let mut inner = lock(&self.inner);
+        if inner.map.is_empty() {
+            return;
+        }
+        inner.unlink_all();
+        inner.map.clear();
+        self.bump_epoch(&mut inner);
+    }
+
+    /// Removes every entry for which `f` returns `false`, visiting them from
+    /// most to least recently used. The survivors keep their relative order.
+    ///
+    /// `f` runs with the cache locked, so it must not call back into it.
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&self, mut f: F) {
+        let mut inner = lock(&self.inner);
+        let mut removed = false;
+        let mut cur = inner.head.clone();
+        while let Some(node) = cur {
+            let (keep, next) = {
+                let n = lock(&node);
+                (f(&n.key, &n.value), n.next.clone())
+            };
+            if !keep {
+                inner.detach(&node);
+                let key = lock(&node).key.clone();
+                inner.map.remove(&key);
+                removed = true;
+            }
+            cur = next;
+        }
+        if removed {
+            self.bump_epoch(&mut inner);
+        }
+    }
+
+    /// Whether `key` is cached. Unlike [`get`](Self::get), this does not
+    /// change the recency order.
+    pub fn contains_key(&self, key: &K) -> bool {
+        lock(&self.inner).map.contains_key(key)
+    }
+
+    /// Current modification epoch.
+    ///
+    /// The epoch starts at 0 and every operation that changes the cache's
+    /// contents (`put`, `remove` and `clear`) advances it; reads never do.
+    /// Once a thread observes an epoch, every change up to it is visible to
+    /// that thread's later operations.
+    pub fn modification_epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.inner).map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        lock(&self.inner).capacity
+    }
+
+    /// Keys in MRU→LRU order, without changing that order. The same walk
+    /// as [`debug_order`](Self::debug_order).
+    pub fn keys(&self) -> Vec<K> {
+        self.debug_order()
+    }
+
+    /// Keys in MRU→LRU order.
+    pub fn debug_order(&self) -> Vec<K> {
+        let inner = lock(&self.inner);
+        let mut out = Vec::with_capacity(inner.map.len());
+        let mut cur = inner.head.clone();
+        while let Some(node) = cur {
+            let n = lock(&node);
+            out.push(n.key.clone());
+            cur = n.next.clone();
+        }
+        out
+    }
+
+    /// Advances the modification epoch. Callers hold the lock, so the new
+    /// epoch is published no earlier than the change it stands for.
+    fn bump_epoch(&self, _inner: &mut Inner<K, V>) -> u64 {
+        self.epoch.fetch_add(1, Ordering::AcqRel) + 1
+    }
+
+    /// Returns the value for `key`, marking it most recently used, as a
+    /// shared handle rather than a clone.
+    ///
+    /// Values are stored behind an `Arc`, so this costs one reference-count
+    /// increment however large the value is. The handle is a snapshot: a
+    /// later `put` or [`with_mut`](Self::with_mut) replaces the cached value
+    /// without changing it.
+    pub fn get_arc(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = lock(&self.inner);
+        let node = Arc::clone(inner.map.get(key)?);
+        inner.move_to_front(&node);
+        let value = Arc::clone(&lock(&node).value);
+        Some(value)
+    }
+
+    /// Marks `key` most recently used without reading its value, and
+    /// returns whether it is cached.
+    pub fn touch(&self, key: &K) -> bool {
+        let mut inner = lock(&self.inner);
+        let Some(node) = inner.map.get(key).cloned() else {
+            return false;
+        };
+        inner.move_to_front(&node);
+        true
+    }
+
+    /// Runs `f` on the value for `key` and returns its result, marking the
+    /// key most recently used, without cloning the value or its handle.
+    ///
+    /// `f` runs with the cache locked: it should be short, and must not
+    /// call back into the cache.
+    pub fn read<R, F: FnOnce(&V) -> R>(&self, key: &K, f: F) -> Option<R> {
+        let mut inner = lock(&self.inner);
+        let node = Arc::clone(inner.map.get(key)?);
+        inner.move_to_front(&node);
+        let result = f(&lock(&node).value);
+        Some(result)
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruCache<K, V> {
+    /// Returns a clone of the value for `key`, marking it most recently used.
+    /// For large values, [`get_arc`](Self::get_arc) avoids the copy.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self.get_arc(key)?;
+        Some(V::clone(&value))
+    }
+
+    /// Inserts or updates `key`, marking it most recently used.
+    ///
+    /// Inserting a new key into a full cache evicts the least recently used
+    /// entry. The outcome reports both the overwritten value and the evicted
+    /// entry so callers can write them back.
+    pub fn put(&self, key: K, value: V) -> PutOutcome<K, V> {
+        let outcome = self.put_arc(key, value);
+        PutOutcome {
+            previous: outcome.previous.map(Arc::unwrap_or_clone),
+            evicted: outcome
+                .evicted
+                .map(|(key, value)| (key, Arc::unwrap_or_clone(value))),
+        }
+    }
+
+    /// Removes `key`, returning its value if it was cached.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.remove_arc(key).map(Arc::unwrap_or_clone)
+    }
+
+    /// Removes and returns the least recently used entry.
+    pub fn pop_lru(&self) -> Option<(K, V)> {
+        let (key, value) = self.pop_lru_arc()?;
+        Some((key, Arc::unwrap_or_clone(value)))
+    }
+
+    /// Empties the cache, returning its entries from most to least recently
+    /// used.
+    pub fn drain(&self) -> Vec<(K, V)> {
+        let mut inner = lock(&self.inner);
+        let mut out = Vec::with_capacity(inner.map.len());
+        while let Some((key, value)) = inner.pop_tail() {
+            out.push((key, Arc::unwrap_or_clone(value)));
+        }
+        if !out.is_empty() {
+            out.reverse();
+            self.bump_epoch(&mut inner);
+        }
+        out
+    }
+
+    /// Returns a clone of the value for `key` without changing the recency
+    /// order.
+    pub fn peek(&self, key: &K) -> Option<V> {
+        let inner = lock(&self.inner);
+        let value = V::clone(&lock(inner.map.get(key)?).value);
+        Some(value)
+    }
+
+    /// Polls `key` for changes since `epoch`.
+    ///
+    /// Returns `None` if nothing about `key` changed since `epoch`, otherwise
+    /// `Some` with its current value (`None` if it is no longer cached). An
+    /// absent key is reported as changed whenever the cache as a whole was.
+    /// Reporting a present value marks it most recently used.
+    pub fn get_if_modified_since(&self, key: &K, epoch: u64) -> Option<Option<V>> {
+        let mut inner = lock(&self.inner);
+        let Some(node) = inner.map.get(key).cloned() else {
+            let changed = self.epoch.load(Ordering::Relaxed) > epoch;
+            return changed.then_some(None);
+        };
+        if lock(&node).modified <= epoch {
+            return None;
+        }
+        inner.move_to_front(&node);
+        let value = V::clone(&lock(&node).value);
+        Some(Some(value))
+    }
+
+    /// Clones of every entry in MRU→LRU order, without changing that order.
+    ///
+    /// The whole list is copied under the lock, so the snapshot is
+    /// consistent, but concurrent writers may change the cache as soon as it
+    /// is returned.
+    pub fn iter(&self) -> Vec<(K, V)> {
+        let inner = lock(&self.inner);
+        let mut out = Vec::with_capacity(inner.map.len());
+        let mut cur = inner.head.clone();
+        while let Some(node) = cur {
+            let n = lock(&node);
+            out.push((n.key.clone(), V::clone(&n.value)));
+            cur = n.next.clone();
+        }
+        out
+    }
+
+    /// Clones of the values in MRU→LRU order, without changing that order.
+    pub fn values(&self) -> Vec<V> {
+        let inner = lock(&self.inner);
+        let mut out = Vec::with_capacity(inner.map.len());
+        let mut cur = inner.head.clone();
+        while let Some(node) = cur {
+            let n = lock(&node);
+            out.push(V::clone(&n.value));
+            cur = n.next.clone();
+        }
+        out
+    }
+
+    /// Runs `f` on the value for `key` in place and marks it most recently
+    /// used. Returns whether `key` was cached.
+    ///
+    /// Unlike a `get` followed by a `put`, no other caller can update the
+    /// value in between, and it is only cloned if a handle from
+    /// [`get_arc`](Self::get_arc) still shares it; that handle keeps the old
+    /// value. `f` runs with the cache locked, so it must not use the cache.
+    pub fn with_mut<F: FnOnce(&mut V)>(&self, key: &K, f: F) -> bool {
+        self.update(key, f).is_some()
+    }
+
+    /// Runs `f` on the value for `key` in place, marking it most recently
+    /// used. Returns `f`'s result, or `None` if `key` is not cached.
+    ///
+    /// See [`with_mut`](Self::with_mut).
+    pub(crate) fn update<R>(&self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        let mut inner = lock(&self.inner);
+        let node = Arc::clone(inner.map.get(key)?);
+        inner.move_to_front(&node);
+        let epoch = self.bump_epoch(&mut inner);
+        let mut n = lock(&node);
+        n.modified = epoch;
+        Some(f(Arc::make_mut(&mut n.value)))
+    }
+}
+
+/// Collects into a cache sized to hold every item, via
+/// [`from_entries`](LruCache::from_entries). An empty iterator gives a cache
+/// of capacity one.
+impl<K: Eq + Hash + Clone, V> FromIterator<(K, V)> for LruCache<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let entries: Vec<_> = iter.into_iter().collect();
+        Self::from_entries(entries.len().max(1), entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn touch_rescues_from_eviction() {
+        let cache = LruCache::new(2);
+        cache.put(1, "one");
+        cache.put(2, "two");
+        assert!(cache.touch(&1));
+        assert!(!cache.touch(&3));
+        assert_eq!(cache.debug_order(), [1, 2]);
+        assert_eq!(cache.put(3, "three").evicted, Some((2, "two")));
+        assert_eq!(cache.debug_order(), [3, 1]);
+    }
+
+    #[test]
+    fn values_need_not_be_clone() {
+        struct Blob(Vec<u8>);
+
+        let cache = LruCache::new(2);
+        cache.put_arc(1, Blob(vec![1, 2, 3]));
+        cache.put_arc(2, Blob(vec![4]));
+        assert_eq!(cache.read(&1, |b| b.0.len()), Some(3));
+        assert_eq!(cache.read(&3, |b| b.0.len()), None);
+        // The read made key 1 most recently used, so key 2 is evicted.
+        let outcome = cache.put_arc(3, Blob(Vec::new()));
+        assert!(outcome.previous.is_none());
+        assert_eq!(outcome.evicted.map(|(k, b)| (k, b.0.len())), Some((2, 1)));
+        let previous = cache.put_arc(1, Blob(vec![5])).previous.unwrap();
+        assert_eq!(previous.0, [1, 2, 3]);
+        assert_eq!(cache.debug_order(), [1, 3]);
+
+        let shared = cache.get_arc(&1).unwrap();
+        let removed = cache.remove_arc(&1).unwrap();
+        assert!(Arc::ptr_eq(&shared, &removed));
+        assert_eq!(cache.pop_lru_arc().map(|(k, _)| k), Some(3));
+        assert!(cache.is_empty());
+        cache.resize(1);
+        cache.retain(|_, b| b.0.is_empty());
+        cache.clear();
+        let collected: LruCache<u32, Blob> = [(1, Blob(vec![]))].into_iter().collect();
+        assert_eq!((collected.len(), collected.capacity()), (1, 1));
+    }
+
+    #[test]
+    fn get_and_put() {
+        let cache = LruCache::new(2);
+        assert_eq!(cache.get(&1), None);
+        cache.put(1, "one");
+        cache.put(2, "two");
+        assert_eq!(cache.get(&1), Some("one"));
+        assert_eq!(cache.get(&2), Some("two"));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "capacity must be non-zero")]
+    fn zero_capacity_panics() {
+        let _ = LruCache::<u32, u32>::new(0);
+    }
+
+    #[test]
+    fn from_entries_orders_by_insertion() {
+        let cache = LruCache::from_entries(3, [(1, 1), (2, 2), (3, 3), (4, 4)]);
+        assert_eq!(cache.debug_order(), vec![4, 3, 2]);
+        let cache: LruCache<_, _> = (0..5).map(|k| (k, k * 10)).collect();
+        assert_eq!(cache.capacity(), 5);
+        assert_eq!(cache.debug_order(), vec![4, 3, 2, 1, 0]);
+        assert_eq!(cache.get(&2), Some(20));
+    }
+
+    #[test]
+    fn drain_empties_in_mru_order() {
+        let cache = LruCache::from_entries(4, (0..4).map(|k| (k, k * 10)));
+        cache.get(&1);
+        let shared = cache.get_arc(&2).unwrap();
+        let len = cache.len();
+        let drained = cache.drain();
+        assert_eq!(drained.len(), len);
+        assert_eq!(drained, vec![(2, 20), (1, 10), (3, 30), (0, 0)]);
+        assert_eq!(*shared, 20);
+        assert!(cache.is_empty());
+        assert!(cache.drain().is_empty());
+    }
+
+    #[test]
+    fn retain_keeps_order_of_survivors() {
+        let cache = LruCache::from_entries(8, (0..8).map(|k| (k, k * 10)));
+        cache.get(&3);
+        let epoch = cache.modification_epoch();
+        cache.retain(|k, v| k % 2 == 0 || *v == 30);
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.debug_order(), vec![3, 6, 4, 2, 0]);
+        assert!(cache.modification_epoch() > epoch);
+        cache.retain(|_, _| true);
+        assert_eq!(cache.debug_order(), vec![3, 6, 4, 2, 0]);
+        cache.retain(|_, _| false);
+        assert!(cache.is_empty());
+        cache.put(1, 1);
+        assert_eq!(cache.debug_order(), vec![1]);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = LruCache::new(3);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        cache.put(3, 3);
+        cache.get(&1);
+        cache.put(4, 4);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.debug_order(), vec![4, 1, 3]);
+    }
+
+    #[test]
+    fn put_returns_previous_value() {
+        let cache = LruCache::new(2);
+        assert_eq!(cache.put(1, "a").previous, None);
+        assert_eq!(cache.put(1, "b").previous, Some("a"));
+        assert_eq!(cache.get(&1), Some("b"));
+    }
+
+    #[test]
+    fn put_returns_evicted_entry() {
+        let cache = LruCache::new(2);
+        assert_eq!(cache.put(1, "a").evicted, None);
+        assert_eq!(cache.put(2, "b").evicted, None);
+        cache.get(&1);
+        let outcome = cache.put(3, "c");
+        assert_eq!(
+            outcome,
+            PutOutcome {
+                previous: None,
+                evicted: Some((2, "b")),
+            }
+        );
+        assert_eq!(cache.put(3, "d").evicted, None);
+    }
+
+    #[test]
+    fn remove_middle_head_and_tail() {
+        let cache = LruCache::new(4);
+        for k in 1..=4 {
+            cache.put(k, k * 10);
+        }
+        assert_eq!(cache.debug_order(), vec![4, 3, 2, 1]);
+        assert_eq!(cache.remove(&3), Some(30));
+        assert_eq!(cache.debug_order(), vec![4, 2, 1]);
+        assert_eq!(cache.remove(&4), Some(40));
+        assert_eq!(cache.debug_order(), vec![2, 1]);
+        assert_eq!(cache.remove(&1), Some(10));
+        assert_eq!(cache.debug_order(), vec![2]);
+        assert_eq!(cache.remove(&1), None);
+        // The list stays usable at both ends.
+        cache.put(5, 50);
+        assert_eq!(cache.debug_order(), vec![5, 2]);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn remove_only_entry() {
+        let cache = LruCache::new(2);
+        cache.put(1, "a");
+        assert_eq!(cache.remove(&1), Some("a"));
+        assert!(cache.is_empty());
+        assert!(cache.debug_order().is_empty());
+        cache.put(2, "b");
+        cache.put(3, "c");
+        cache.put(4, "d");
+        assert_eq!(cache.debug_order(), vec![4, 3]);
+    }
+
+    #[test]
+    fn contains_key_does_not_reorder() {
+        let cache = LruCache::new(2);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&3));
+        assert_eq!(cache.put(3, 3).evicted, Some((1, 1)));
+    }
+
+    #[test]
+    fn peek_does_not_reorder() {
+        let cache = LruCache::new(2);
+        cache.put(1, "a");
+        cache.put(2, "b");
+        assert_eq!(cache.peek(&1), Some("a"));
+        assert_eq!(cache.peek(&3), None);
+        assert_eq!(cache.put(3, "c").evicted, Some((1, "a")));
+    }
+
+    #[test]
+    fn pop_lru_drains_in_recency_order() {
+        let cache = LruCache::new(3);
+        for k in 1..=3 {
+            cache.put(k, k * 10);
+        }
+        cache.get(&1);
+        let seen = cache.modification_epoch();
+        assert_eq!(cache.pop_lru(), Some((2, 20)));
+        assert!(cache.modification_epoch() > seen);
+        assert_eq!(cache.pop_lru(), Some((3, 30)));
+        assert_eq!(cache.pop_lru(), Some((1, 10)));
+        assert_eq!(cache.pop_lru(), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn resize_evicts_down_to_new_capacity() {
+        let cache = LruCache::new(4);
+        for k in 1..=4 {
+            cache.put(k, k);
+        }
+        cache.get(&1);
+        cache.resize(2);
+        assert_eq!(cache.capacity(), 2);
+        assert_eq!(cache.debug_order(), vec![1, 4]);
+        cache.resize(3);
+        cache.put(5, 5);
+        assert_eq!(cache.debug_order(), vec![5, 1, 4]);
+    }
+
+    #[test]
+    fn iter_lists_entries_without_reordering() {
+        let cache = LruCache::new(3);
+        cache.put(1, "a");
+        cache.put(2, "b");
+        cache.put(3, "c");
+        cache.get(&1);
+        assert_eq!(cache.iter(), vec![(1, "a"), (3, "c"), (2, "b")]);
+        assert_eq!(cache.put(4, "d").evicted, Some((2, "b")));
+    }
+
+    #[test]
+    fn keys_and_values_follow_recency() {
+        let cache = LruCache::new(3);
+        for k in 1..=3 {
+            cache.put(k, k * 10);
+        }
+        cache.get(&2);
+        assert_eq!(cache.keys(), cache.debug_order());
+        assert_eq!(cache.keys(), vec![2, 3, 1]);
+        assert_eq!(cache.values(), vec![20, 30, 10]);
+        assert_eq!(cache.put(4, 40).evicted, Some((1, 10)));
+    }
+
+    #[test]
+    fn with_mut_updates_in_place() {
+        let cache: LruCache<&str, u64> = LruCache::new(2);
+        cache.put("hits", 0);
+        cache.put("other", 0);
+        let seen = cache.modification_epoch();
+        for _ in 0..3 {
+            assert!(cache.with_mut(&"hits", |v| *v += 1));
+        }
+        assert_eq!(cache.get(&"hits"), Some(3));
+        assert!(cache.modification_epoch() > seen);
+        assert!(!cache.with_mut(&"missing", |v| *v += 1));
+        assert_eq!(cache.debug_order(), vec!["hits", "other"]);
+    }
+
+    #[test]
+    fn clear_empties_the_cache() {
+        let cache = LruCache::new(3);
+        for k in 1..=3 {
+            cache.put(k, k);
+        }
+        let seen = cache.modification_epoch();
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert!(cache.debug_order().is_empty());
+        assert_eq!(cache.get(&1), None);
+        assert!(cache.modification_epoch() > seen);
+        cache.put(4, 4);
+        assert_eq!(cache.debug_order(), vec![4]);
+    }
+
+    /// Counts its drops, to check that entries are freed.
+    #[derive(Clone)]
+    struct Tracked(Arc<AtomicUsize>);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn dropping_the_cache_frees_every_entry() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let cache = LruCache::new(4);
+        for k in 0..10 {
+            // Evicted entries are returned in the outcome and dropped here.
+            cache.put(k, Tracked(Arc::clone(&drops)));
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), 6);
+        cache.get(&7);
+        let before = drops.load(Ordering::SeqCst);
+        drop(cache);
+        assert_eq!(drops.load(Ordering::SeqCst), before + 4);
+        assert_eq!(Arc::strong_count(&drops), 1);
+    }
+
+    #[test]
+    fn clear_frees_every_entry() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let cache = LruCache::new(4);
+        for k in 0..4 {
+            cache.put(k, Tracked(Arc::clone(&drops)));
+        }
+        cache.clear();
+        assert_eq!(drops.load(Ordering::SeqCst), 4);
+        assert_eq!(Arc::strong_count(&drops), 1);
+    }
+
+    #[test]
+    fn remove_advances_epoch() {
+        let cache = LruCache::new(2);
+        cache.put(1, 1);
+        let seen = cache.modification_epoch();
+        cache.remove(&2);
+        assert_eq!(cache.modification_epoch(), seen);
+        cache.remove(&1);
+        assert!(cache.modification_epoch() > seen);
+        assert_eq!(cache.get_if_modified_since(&1, seen), Some(None));
+    }
+
+    #[test]
+    fn epoch_advances_only_on_writes() {
+        let cache = LruCache::new(2);
+        assert_eq!(cache.modification_epoch(), 0);
+        cache.put(1, 1);
+        let e1 = cache.modification_epoch();
+        cache.get(&1);
+        cache.get(&2);
+        assert_eq!(cache.modification_epoch(), e1);
+        cache.put(1, 2);
+        assert!(cache.modification_epoch() > e1);
+    }
+
+    #[test]
+    fn get_if_modified_since_reports_changes() {
+        let cache = LruCache::new(2);
+        cache.put(1, "a");
+        let seen = cache.modification_epoch();
+        assert_eq!(cache.get_if_modified_since(&1, seen), None);
+        assert_eq!(cache.get_if_modified_since(&1, 0), Some(Some("a")));
+        assert_eq!(cache.get_if_modified_since(&2, seen), None);
+
+        cache.put(2, "b");
+        assert_eq!(cache.get_if_modified_since(&1, seen), None);
+        assert_eq!(cache.get_if_modified_since(&2, seen), Some(Some("b")));
+        cache.put(1, "c");
+        assert_eq!(cache.get_if_modified_since(&1, seen), Some(Some("c")));
+        let seen = cache.modification_epoch();
+        cache.put(3, "d");
+        assert_eq!(cache.get_if_modified_since(&2, seen), Some(None));
+    }
+
+    #[test]
+    fn update_moves_to_front() {
+        let cache = LruCache::new(3);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        cache.put(3, 3);
+        assert_eq!(cache.put(1, 10).previous, Some(1));
+        assert_eq!(cache.debug_order(), vec![1, 3, 2]);
+        assert_eq!(cache.put(4, 4).evicted, Some((2, 2)));
+        assert_eq!(cache.debug_order(), vec![4, 1, 3]);
+    }
+
+    /// A large value that counts how often it is cloned.
+    struct Blob {
+        bytes: Vec<u8>,
+        clones: Arc<AtomicUsize>,
+    }
+
+    impl Clone for Blob {
+        fn clone(&self) -> Self {
+            self.clones.fetch_add(1, Ordering::SeqCst);
+            Blob {
+                bytes: self.bytes.clone(),
+                clones: Arc::clone(&self.clones),
+            }
+        }
+    }
+
+    #[test]
+    fn get_arc_shares_the_value_instead_of_cloning_it() {
+        let clones = Arc::new(AtomicUsize::new(0));
+        let cache = LruCache::new(2);
+        cache.put(
+            1,
+            Blob {
+                bytes: vec![7; 4096],
+                clones: Arc::clone(&clones),
+            },
+        );
+        let first = cache.get_arc(&1).unwrap();
+        for _ in 0..100 {
+            assert!(Arc::ptr_eq(&first, &cache.get_arc(&1).unwrap()));
+        }
+        assert_eq!(clones.load(Ordering::SeqCst), 0);
+        cache.get(&1);
+        assert_eq!(clones.load(Ordering::SeqCst), 1, "get still clones");
+
+        // Writing through a shared handle copies once; the handle keeps the
+        // old bytes.
+        cache.with_mut(&1, |blob| blob.bytes[0] = 0);
+        assert_eq!(clones.load(Ordering::SeqCst), 2);
+        assert_eq!(first.bytes[0], 7);
+        assert_eq!(cache.get_arc(&1).unwrap().bytes[0], 0);
+        drop(first);
+        assert!(cache.remove(&1).is_some());
+        assert_eq!(
+            clones.load(Ordering::SeqCst),
+            2,
+            "an unshared value moves out"
+        );
+    }
+
+    #[test]
+    fn capacity_one() {
+        let cache = LruCache::new(1);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some(2));
+        assert_eq!(cache.debug_order(), vec![2]);
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+use std::thread;
+
+use solution_1::LruCache;
+
+#[test]
+fn concurrent_puts_respect_capacity() {
+    let cache = Arc::new(LruCache::new(64));
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..2_000u64 {
+                    cache.put(t * 10_000 + i, i);
+                    assert!(cache.len() <= 64);
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(cache.len(), 64);
+    assert_eq!(cache.debug_order().len(), 64);
+}
+
+#[test]
+fn concurrent_readers_see_consistent_values() {
+    let cache = Arc::new(LruCache::new(128));
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..5_000u64 {
+                    let key = (i * 13 + t) % 256;
+                    if t % 2 == 0 {
+                        cache.put(key, key + 7);
+                    } else if let Some(v) = cache.get(&key) {
+                        assert_eq!(v, key + 7);
+                    }
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert!(cache.len() <= 128);
+}
+
+#[test]
+fn observed_epoch_implies_visible_write() {
+    let cache = Arc::new(LruCache::new(8));
+    let writer = {
+        let cache = Arc::clone(&cache);
+        thread::spawn(move || {
+            // With a single writer, put number i leaves the epoch at i.
+            for i in 1..=20_000u64 {
+                cache.put(0u64, i);
+            }
+        })
+    };
+    let mut last = 0;
+    while last < 20_000 {
+        let epoch = cache.modification_epoch();
+        assert!(epoch >= last, "epoch went backwards");
+        last = epoch;
+        let value = cache.get(&0).unwrap_or(0);
+        assert!(value >= epoch, "saw epoch {epoch} but value {value}");
+    }
+    writer.join().unwrap();
+}
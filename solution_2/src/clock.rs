@@ -0,0 +1,284 @@
+//! CLOCK (second-chance) variant of the sharded cache.
+//!
+//! Each shard stores its entries in a fixed ring of slots with a per-slot
+//! reference bit. A hit only sets the bit; nothing is relinked. When a shard
+//! is full, the hand sweeps the ring, clearing set bits, and replaces the
+//! first entry whose bit was already clear. This approximates LRU while
+//! keeping `get` down to a map lookup and a flag write.
+//!
+//! The bit is atomic, so `get` only needs a shared lock on its shard, and
+//! reads of one shard run in parallel. Writers take the lock exclusively.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{distribute_capacity, hash_of, Entries, PutOutcome};
+
+struct Slot<K, V> {
+    key: K,
+    value: V,
+    /// Set by hits under the shared lock; cleared by the hand.
+    referenced: AtomicBool,
+}
+
+struct ClockShard<K, V> {
+    /// Key → index into `slots`.
+    map: HashMap<K, usize>,
+    slots: Vec<Slot<K, V>>,
+    hand: usize,
+    capacity: usize,
+}
+
+impl<K: Eq + Hash + Clone, V> ClockShard<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self {
+            map: HashMap::with_capacity(capacity),
+            slots: Vec::with_capacity(capacity),
+            hand: 0,
+            capacity,
+        }
+    }
+
+    /// Advances the hand until it finds an unreferenced slot, clearing
+    /// reference bits on the way. Terminates within two sweeps.
+    fn find_victim(&mut self) -> usize {
+        loop {
+            let idx = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            let referenced = self.slots[idx].referenced.get_mut();
+            if *referenced {
+                *referenced = false;
+            } else {
+                return idx;
+            }
+        }
+    }
+
+    /// Inserts a key that is not yet cached, returning the entry it replaced
+    /// if the shard was full.
+    fn insert(&mut self, key: K, value: V) -> Option<(K, V)> {
+        if self.slots.len() < self.capacity {
+            self.map.insert(key.clone(), self.slots.len());
+            self.slots.push(Slot {
+                key,
+                value,
+                referenced: AtomicBool::new(false),
+            });
+            return None;
+        }
+        let idx = self.find_victim();
+        let slot = &mut self.slots[idx];
+        self.map.remove(&slot.key);
+        let old_key = std::mem::replace(&mut slot.key, key.clone());
+        let old_value = std::mem::replace(&mut slot.value, value);
+        *slot.referenced.get_mut() = false;
+        self.map.insert(key, idx);
+        Some((old_key, old_value))
+    }
+}
+
+/// A thread-safe sharded cache using CLOCK eviction instead of an exact
+/// LRU list.
+///
+/// Unlike [`ShardedLruCache`](crate::ShardedLruCache), a hit does not
+/// reorder anything, so each shard sits behind an `RwLock` and lookups in
+/// the same shard do not block each other. This suits read-mostly
+/// workloads that can accept approximate recency.
+pub struct ClockShardedCache<K, V> {
+    shards: Vec<RwLock<ClockShard<K, V>>>,
+    total_capacity: usize,
+}
+
+impl<K, V> ClockShardedCache<K, V>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+{
+    /// Creates a cache holding at most `total_capacity` entries spread over
+    /// `shard_hint` shards, clamped to `1..=total_capacity`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `total_capacity` is zero.
+    pub fn new(total_capacity: usize, shard_hint: usize) -> Self {
+        assert!(total_capacity > 0, "total_capacity must be non-zero");
+        let shard_count = shard_hint.clamp(1, total_capacity);
+        let shards = distribute_capacity(total_capacity, shard_count)
+            .into_iter()
+            .map(|cap| RwLock::new(ClockShard::new(cap)))
+            .collect();
+        Self {
+            shards,
+            total_capacity,
+        }
+    }
+
+    fn shard_index(&self, key: &K) -> usize {
+        (hash_of(key) as usize) % self.shards.len()
+    }
+
+    fn read_shard(&self, idx: usize) -> RwLockReadGuard<'_, ClockShard<K, V>> {
+        self.shards[idx]
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_shard(&self, idx: usize) -> RwLockWriteGuard<'_, ClockShard<K, V>> {
+        self.shards[idx]
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns a clone of the value for `key` and sets its reference bit.
+    /// Takes the shard's lock shared, so concurrent lookups do not wait for
+    /// each other.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let shard = self.read_shard(self.shard_index(key));
+        let slot = &shard.slots[*shard.map.get(key)?];
+        // Skip the store when the bit is already set, so a hot slot's line
+        // is not written on every hit.
+        if !slot.referenced.load(Ordering::Relaxed) {
+            slot.referenced.store(true, Ordering::Relaxed);
+        }
+        Some(slot.value.clone())
+    }
+
+    /// Inserts or updates `key`. An update sets the reference bit; an insert
+    /// into a full shard replaces the victim chosen by the clock hand.
+    ///
+    /// Reports the overwritten value and the evicted entry, as
+    /// [`ShardedLruCache::put`](crate::ShardedLruCache::put) does.
+    pub fn put(&self, key: K, value: V) -> PutOutcome<K, V> {
+        let mut shard = self.write_shard(self.shard_index(&key));
+        if let Some(&idx) = shard.map.get(&key) {
+            let slot = &mut shard.slots[idx];
+            *slot.referenced.get_mut() = true;
+            return PutOutcome {
+                previous: Some(std::mem::replace(&mut slot.value, value)),
+                evicted: None,
+            };
+        }
+        PutOutcome {
+            previous: None,
+            evicted: shard.insert(key, value),
+        }
+    }
+
+    /// Number of entries across all shards, counted one shard at a time.
+    pub fn len(&self) -> usize {
+        (0..self.shards.len())
+            .map(|idx| self.read_shard(idx).map.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn total_capacity(&self) -> Entries {
+        Entries(self.total_capacity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ShardedLruCache;
+
+    /// Deterministic skewed workload: 80% of accesses go to 20% of keys.
+    fn workload(len: usize) -> Vec<u64> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                if state % 10 < 8 {
+                    state % 200
+                } else {
+                    200 + state % 800
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn get_and_put() {
+        let cache = ClockShardedCache::new(4, 2);
+        assert_eq!(cache.get(&1), None);
+        cache.put(1, "one");
+        cache.put(2, "two");
+        assert_eq!(cache.get(&1), Some("one"));
+        assert_eq!(cache.put(1, "uno").previous, Some("one"));
+        assert_eq!(cache.get(&1), Some("uno"));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn referenced_entry_gets_second_chance() {
+        let cache = ClockShardedCache::new(3, 1);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        cache.put(3, 3);
+        cache.get(&1);
+        assert_eq!(cache.put(4, 4).evicted, Some((2, 2)));
+        assert_eq!(cache.get(&1), Some(1));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn get_shares_the_shard_lock() {
+        let cache = ClockShardedCache::new(4, 1);
+        cache.put(1, 10);
+        let held = cache.read_shard(0);
+        // Would deadlock if `get` needed the shard exclusively.
+        std::thread::scope(|s| {
+            assert_eq!(s.spawn(|| cache.get(&1)).join().unwrap(), Some(10));
+        });
+        assert!(held.slots[0].referenced.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn length_stays_bounded() {
+        let cache = ClockShardedCache::new(100, 8);
+        for (i, k) in workload(20_000).into_iter().enumerate() {
+            if i % 3 == 0 {
+                cache.put(k, k);
+            } else {
+                cache.get(&k);
+            }
+            assert!(cache.len() <= 100);
+        }
+    }
+
+    #[test]
+    fn hit_rate_tracks_lru_on_skewed_workload() {
+        let clock = ClockShardedCache::new(250, 4);
+        let lru = ShardedLruCache::new(250, 4);
+        let (mut clock_hits, mut lru_hits) = (0, 0);
+        let ops = workload(50_000);
+        for &k in &ops {
+            match clock.get(&k) {
+                Some(_) => clock_hits += 1,
+                None => {
+                    clock.put(k, k);
+                }
+            }
+            match lru.get(&k) {
+                Some(_) => lru_hits += 1,
+                None => {
+                    lru.put(k, k);
+                }
+            }
+        }
+        let clock_rate = clock_hits as f64 / ops.len() as f64;
+        let lru_rate = lru_hits as f64 / ops.len() as f64;
+        assert!(
+            (clock_rate - lru_rate).abs() < 0.05,
+            "clock {clock_rate:.3} vs lru {lru_rate:.3}"
+        );
+    }
+}
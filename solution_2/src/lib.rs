@@ -0,0 +1,3134 @@
+//! A sharded LRU cache.
+//!
+//! Keys are hashed onto a fixed number of shards, each an independent LRU
+//! guarded by its own [`Mutex`]. Operations on keys in different shards never
+//! contend, and each shard keeps its recency order as an intrusive doubly
+//! linked list threaded through the entries of its `HashMap` (links are keys,
+//! not pointers, so the whole structure is safe Rust).
+//!
+//! Recency is exact within a shard and only approximate across shards: the
+//! least recently used entry of the shard a key hashes to is evicted, not the
+//! globally least recently used one.
+//!
+//! [`EvictionPolicy::Clock`] keeps the list but stops reordering it on
+//! hits. [`ClockShardedCache`] is a variant with the same sharding that
+//! replaces the per-shard list with a CLOCK ring, so lookups only need a
+//! shared lock.
+//!
+//! Without the default `std` feature, and with `no_std`, the crate needs
+//! only `alloc`. Shards are then guarded by spin locks and their maps come
+//! from `hashbrown`. Constructors must be given a [`BuildHasher`], and TTLs,
+//! `ClockShardedCache` and serde support are unavailable.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(any(feature = "std", feature = "no_std")))]
+compile_error!("solution_2 needs either the `std` or the `no_std` feature");
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::error::Error;
+use core::fmt::{self, Debug};
+use core::hash::{BuildHasher, Hash};
+use core::marker::PhantomData;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+#[cfg(feature = "std")]
+use std::collections::hash_map::{DefaultHasher, RandomState as DefaultHashBuilder};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+#[cfg(not(feature = "std"))]
+use hashbrown::{DefaultHashBuilder, HashMap};
+
+#[cfg(feature = "std")]
+mod clock;
+mod occupancy;
+#[cfg(feature = "serde")]
+mod persist;
+mod sync;
+mod units;
+
+#[cfg(feature = "std")]
+pub use clock::ClockShardedCache;
+pub use occupancy::OccupancyStats;
+pub use units::{CapacityUnit, Entries, Weight};
+
+use occupancy::Occupancy;
+use sync::{Mutex, MutexGuard};
+
+/// When an entry put with a TTL stops being served. Without `std` there is
+/// no clock to put a TTL against, so no entry has a deadline.
+#[cfg(feature = "std")]
+type Deadline = Instant;
+#[cfg(not(feature = "std"))]
+type Deadline = core::convert::Infallible;
+
+/// Clock registered with [`ShardedLruCache::set_time_source`].
+#[cfg(feature = "std")]
+type TimeSource = Arc<dyn Fn() -> Instant + Send + Sync>;
+
+/// Callback registered with [`ShardedLruCache::on_evict`].
+type EvictListener<K, V> = Arc<dyn Fn(&K, &V) + Send + Sync>;
+
+#[derive(Clone)]
+struct Entry<K, V> {
+    value: V,
+    prev: Option<K>,
+    next: Option<K>,
+    /// Value of the cache-wide tick when this entry was last touched.
+    stamp: u64,
+    /// Modification epoch of the last write to this entry.
+    modified: u64,
+    /// When the entry stops being served, if it was put with a TTL.
+    expires: Option<Deadline>,
+    /// Set by accesses under [`EvictionPolicy::Clock`], cleared when the
+    /// entry is given a second chance.
+    referenced: bool,
+    /// Share of the shard's capacity the entry takes up; 1 unless put with
+    /// [`ShardedLruCache::put_weighted`].
+    weight: usize,
+    /// Hash of the key at insertion, re-checked on access to catch keys whose
+    /// `Hash` drifts after insertion.
+    #[cfg(all(debug_assertions, feature = "std"))]
+    hash: u64,
+}
+
+#[derive(Clone)]
+struct Shard<K, V> {
+    map: HashMap<K, Entry<K, V>>,
+    /// Most recently used key.
+    head: Option<K>,
+    /// Least recently used key.
+    tail: Option<K>,
+    /// Maximum summed weight of the entries.
+    capacity: usize,
+    /// Summed weight of the entries.
+    current_weight: usize,
+    /// Modification epoch of the last write to this shard.
+    epoch: u64,
+    /// Position in the cache's shard list, for diagnostics.
+    index: usize,
+    policy: EvictionPolicy,
+    /// The cache's eviction listener, shared by every shard.
+    on_evict: Option<EvictListener<K, V>>,
+}
+
+impl<K: Eq + Hash + Clone, V> Shard<K, V> {
+    fn new(index: usize, capacity: usize, policy: EvictionPolicy) -> Self {
+        Self {
+            map: HashMap::with_capacity(capacity),
+            head: None,
+            tail: None,
+            capacity,
+            current_weight: 0,
+            epoch: 0,
+            index,
+            policy,
+            on_evict: None,
+        }
+    }
+
+    /// Unlinks `key` from the recency list, leaving it in the map.
+    fn detach(&mut self, key: &K) {
+        let (prev, next) = match self.map.get_mut(key) {
+            Some(entry) => (entry.prev.take(), entry.next.take()),
+            None => return,
+        };
+        match &prev {
+            Some(p) => match self.map.get_mut(p) {
+                Some(e) => e.next = next.clone(),
+                None => self.broken_link(),
+            },
+            None => self.head = next.clone(),
+        }
+        match &next {
+            Some(n) => match self.map.get_mut(n) {
+                Some(e) => e.prev = prev,
+                None => self.broken_link(),
+            },
+            None => self.tail = prev,
+        }
+    }
+
+    /// Links an already-present, detached `key` in as the most recently used.
+    fn attach_front(&mut self, key: &K) {
+        let old_head = self.head.replace(key.clone());
+        if let Some(h) = &old_head {
+            match self.map.get_mut(h) {
+                Some(e) => e.prev = Some(key.clone()),
+                None => self.broken_link(),
+            }
+        }
+        if let Some(e) = self.map.get_mut(key) {
+            e.prev = None;
+            e.next = old_head;
+        }
+        if self.tail.is_none() {
+            self.tail = Some(key.clone());
+        }
+    }
+
+    fn move_to_front(&mut self, key: &K) {
+        #[cfg(all(debug_assertions, feature = "std"))]
+        self.verify_hash(key);
+        if self.head.as_ref() == Some(key) {
+            return;
+        }
+        self.detach(key);
+        self.attach_front(key);
+    }
+
+    /// Records an access to a cached `key` at `stamp` and returns its entry.
+    /// Under [`EvictionPolicy::Lru`] the key becomes most recently used;
+    /// under [`EvictionPolicy::Clock`] it is only marked referenced.
+    fn touch(&mut self, key: &K, stamp: u64) -> Option<&mut Entry<K, V>> {
+        if !self.map.contains_key(key) {
+            return None;
+        }
+        if self.policy == EvictionPolicy::Lru {
+            self.move_to_front(key);
+        }
+        let entry = self.map.get_mut(key)?;
+        entry.stamp = stamp;
+        entry.referenced = true;
+        Some(entry)
+    }
+
+    /// Inserts a key that is not cached yet at the head of the list,
+    /// returning the entries evicted to make room for its `weight`. Victims
+    /// are chosen before the key goes in, so they never include the key
+    /// itself.
+    fn insert_new(
+        &mut self,
+        key: K,
+        value: V,
+        weight: usize,
+        stamp: u64,
+        epoch: u64,
+        expires: Option<Deadline>,
+    ) -> Vec<(K, V)> {
+        let mut evicted = Vec::new();
+        while self.current_weight + weight > self.capacity {
+            match self.evict() {
+                Some(victim) => evicted.push(victim),
+                None => break,
+            }
+        }
+        self.current_weight += weight;
+        self.map.insert(
+            key.clone(),
+            Entry {
+                value,
+                prev: None,
+                next: None,
+                stamp,
+                modified: epoch,
+                expires,
+                referenced: false,
+                weight,
+                #[cfg(all(debug_assertions, feature = "std"))]
+                hash: hash_of(&key),
+            },
+        );
+        self.attach_front(&key);
+        evicted
+    }
+
+    /// Removes and returns the policy's victim if the shard's weight is
+    /// over capacity.
+    fn evict_if_needed(&mut self) -> Option<(K, V)> {
+        if self.current_weight <= self.capacity {
+            return None;
+        }
+        self.evict()
+    }
+
+    /// Removes and returns the tail entry, passing it to the eviction
+    /// listener first. Under [`EvictionPolicy::Clock`] referenced tails are
+    /// first moved to the head with their bit cleared, which takes at most
+    /// one pass over the list.
+    fn evict(&mut self) -> Option<(K, V)> {
+        loop {
+            let tail = self.tail.clone()?;
+            let entry = self.map.get_mut(&tail)?;
+            if self.policy == EvictionPolicy::Clock && core::mem::take(&mut entry.referenced) {
+                self.move_to_front(&tail);
+                continue;
+            }
+            if let Some(f) = &self.on_evict {
+                f(&tail, &entry.value);
+            }
+            let value = self.remove(&tail)?;
+            return Some((tail, value));
+        }
+    }
+
+    /// Unlinks and removes `key`, returning its value.
+    fn remove(&mut self, key: &K) -> Option<V> {
+        self.detach(key);
+        let entry = self.map.remove(key)?;
+        self.current_weight -= entry.weight;
+        Some(entry.value)
+    }
+
+    /// A neighbour link names a key the map can no longer find. With a
+    /// well-behaved key type that cannot happen, so it means some key's
+    /// `Hash` changed while it was cached.
+    fn broken_link(&self) {
+        debug_assert!(
+            false,
+            "ShardedLruCache shard {}: linked key not found; a key's Hash \
+             changed after insertion or is inconsistent with Eq",
+            self.index
+        );
+    }
+
+    /// Whether the stored key `key` has become unreachable by lookup. Debug
+    /// builds also compare against the hash recorded at insertion, which
+    /// catches drift that happens to land in the same bucket.
+    fn hash_drifted(&self, key: &K) -> bool {
+        match self.map.get(key) {
+            #[cfg(all(debug_assertions, feature = "std"))]
+            Some(entry) => entry.hash != hash_of(key),
+            #[cfg(not(all(debug_assertions, feature = "std")))]
+            Some(_) => false,
+            None => true,
+        }
+    }
+
+    #[cfg(all(debug_assertions, feature = "std"))]
+    fn verify_hash(&self, key: &K) {
+        if let Some((stored, entry)) = self.map.get_key_value(key) {
+            assert_eq!(
+                hash_of(stored),
+                entry.hash,
+                "ShardedLruCache shard {}: key hash changed since insertion",
+                self.index
+            );
+        }
+    }
+
+    /// Discards every link and rebuilds the list from the entries' access
+    /// stamps, most recent first.
+    fn rebuild(&mut self) -> RebuildReport {
+        let mut order: Vec<(u64, K)> = self.map.iter().map(|(k, e)| (e.stamp, k.clone())).collect();
+        order.sort_unstable_by_key(|&(stamp, _)| core::cmp::Reverse(stamp));
+        let mut links_repaired = 0;
+        for (i, (_, key)) in order.iter().enumerate() {
+            let prev = i.checked_sub(1).map(|j| order[j].1.clone());
+            let next = order.get(i + 1).map(|(_, k)| k.clone());
+            let entry = self.map.get_mut(key).expect("key collected from map");
+            links_repaired += usize::from(entry.prev != prev) + usize::from(entry.next != next);
+            entry.prev = prev;
+            entry.next = next;
+        }
+        let head = order.first().map(|(_, k)| k.clone());
+        let tail = order.last().map(|(_, k)| k.clone());
+        links_repaired += usize::from(self.head != head) + usize::from(self.tail != tail);
+        self.head = head;
+        self.tail = tail;
+        RebuildReport {
+            entries: order.len(),
+            links_repaired,
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Debug, V> Shard<K, V> {
+    /// Checks that the recency list links every mapped entry exactly once
+    /// with consistent back-links, and that the shard is within capacity.
+    fn check(&self) -> Result<(), String> {
+        let summed: usize = self.map.values().map(|entry| entry.weight).sum();
+        if summed != self.current_weight {
+            return Err(format!(
+                "entries weigh {summed} but the shard counts {}",
+                self.current_weight
+            ));
+        }
+        if self.current_weight > self.capacity {
+            return Err(format!(
+                "weight {} exceeds capacity {}",
+                self.current_weight, self.capacity
+            ));
+        }
+        let mut prev: Option<&K> = None;
+        let mut cur = self.head.as_ref();
+        let mut linked = 0;
+        while let Some(k) = cur {
+            let Some(entry) = self.map.get(k) else {
+                return Err(format!("linked key {k:?} is not in the map"));
+            };
+            if entry.prev.as_ref() != prev {
+                return Err(format!(
+                    "{k:?} links back to {:?}, expected {prev:?}",
+                    entry.prev
+                ));
+            }
+            linked += 1;
+            if linked > self.map.len() {
+                return Err("recency list contains a cycle".to_string());
+            }
+            prev = Some(k);
+            cur = entry.next.as_ref();
+        }
+        if self.tail.as_ref() != prev {
+            return Err(format!(
+                "tail is {:?} but the list ends at {prev:?}",
+                self.tail
+            ));
+        }
+        if linked != self.map.len() {
+            return Err(format!("list links {linked} of {} entries", self.map.len()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+fn hash_of<K: Hash>(key: &K) -> u64 {
+    use std::hash::Hasher;
+    let mut h = DefaultHasher::new();
+    key.hash(&mut h);
+    h.finish()
+}
+
+/// Splits `total` slots over `shards` shards, handing the remainder out one
+/// slot at a time starting from shard 0.
+fn distribute_capacity(total: usize, shards: usize) -> Vec<usize> {
+    let base = total / shards;
+    let rem = total % shards;
+    (0..shards).map(|i| base + usize::from(i < rem)).collect()
+}
+
+/// Splits `total` slots over one shard per weight: one slot each, then the
+/// rest in proportion to the weights. Slots lost to rounding go to the
+/// largest fractional shares, ties to the lowest index. `total` is at least
+/// the number of weights, none of which is zero.
+fn weighted_capacity(total: usize, weights: &[usize]) -> Vec<usize> {
+    let spare = (total - weights.len()) as u128;
+    let sum: u128 = weights.iter().map(|&w| w as u128).sum();
+    let shares: Vec<(usize, u128)> = weights
+        .iter()
+        .map(|&w| {
+            let exact = spare * w as u128;
+            (1 + (exact / sum) as usize, exact % sum)
+        })
+        .collect();
+    let mut caps: Vec<usize> = shares.iter().map(|&(cap, _)| cap).collect();
+    let left = total - caps.iter().sum::<usize>();
+    let mut by_fraction: Vec<usize> = (0..caps.len()).collect();
+    by_fraction.sort_by_key(|&i| core::cmp::Reverse(shares[i].1));
+    for &i in &by_fraction[..left] {
+        caps[i] += 1;
+    }
+    caps
+}
+
+/// How a [`ShardedLruCache`] splits its total capacity over its shards,
+/// at construction and on every [`resize`](ShardedLruCache::resize).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DistributionStrategy {
+    /// Equal shares, with the remainder handed out one slot at a time
+    /// starting from shard 0.
+    #[default]
+    Even,
+    /// Shares in proportion to one weight per shard, to give shards known
+    /// to be hot more room. Each shard gets one slot before the rest is
+    /// split, so none is left empty.
+    Weighted(Vec<usize>),
+}
+
+impl DistributionStrategy {
+    /// The capacity of each of `shards` shards out of `total`, which the
+    /// caller has checked is at least `shards`.
+    fn split(&self, total: usize, shards: usize) -> Vec<usize> {
+        match self {
+            Self::Even => distribute_capacity(total, shards),
+            Self::Weighted(weights) => weighted_capacity(total, weights),
+        }
+    }
+}
+
+/// How a [`ShardedLruCache`] shard picks the entry to evict.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EvictionPolicy {
+    /// Exact LRU: every access moves the entry to the head of its shard's
+    /// list, and the tail is evicted.
+    #[default]
+    Lru,
+    /// CLOCK (second chance): an access only marks the entry referenced, so
+    /// the list stays in insertion order. Eviction walks up from the tail,
+    /// moving referenced entries back to the head with the mark cleared, and
+    /// evicts the first unmarked one. Hits do no list surgery, at the cost
+    /// of only approximating recency.
+    Clock,
+}
+
+/// What a `put` displaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PutOutcome<K, V> {
+    /// The value previously stored under the key, if it was cached.
+    pub previous: Option<V>,
+    /// The entry evicted from the key's shard to make room, if any.
+    pub evicted: Option<(K, V)>,
+}
+
+/// What a [`put_weighted`](ShardedLruCache::put_weighted) displaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeightedPutOutcome<K, V> {
+    /// The value previously stored under the key, if it was cached.
+    pub previous: Option<V>,
+    /// The entries evicted from the key's shard to make room, least
+    /// recently used first.
+    pub evicted: Vec<(K, V)>,
+}
+
+/// A broken structural invariant reported by [`ShardedLruCache::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    /// Index of the offending shard.
+    pub shard: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "shard {}: {}", self.shard, self.reason)
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Why [`ShardedLruCache::with_distribution`] rejected its arguments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DistributionError {
+    ZeroCapacity,
+    /// [`DistributionStrategy::Weighted`] was not given one weight per
+    /// shard.
+    WeightCount {
+        weights: usize,
+        shards: usize,
+    },
+    /// The weight of this shard is zero.
+    ZeroWeight(usize),
+    /// Fewer slots than weighted shards, so some shard would get none.
+    TooFewSlots {
+        capacity: usize,
+        shards: usize,
+    },
+}
+
+impl fmt::Display for DistributionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroCapacity => f.write_str("total_capacity must be non-zero"),
+            Self::WeightCount { weights, shards } => {
+                write!(f, "{weights} weights given for {shards} shards")
+            }
+            Self::ZeroWeight(idx) => write!(f, "shard {idx} has weight zero"),
+            Self::TooFewSlots { capacity, shards } => {
+                write!(
+                    f,
+                    "{capacity} slots cannot give each of {shards} shards one"
+                )
+            }
+        }
+    }
+}
+
+impl Error for DistributionError {}
+
+/// Why [`ShardedLruCache::try_get`] gave up without an answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryGetError {
+    /// The key's shard was locked by another thread.
+    WouldBlock,
+}
+
+impl fmt::Display for TryGetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WouldBlock => f.write_str("shard is locked by another thread"),
+        }
+    }
+}
+
+impl Error for TryGetError {}
+
+/// Result of [`ShardedLruCache::rebuild_shard`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RebuildReport {
+    /// Entries relinked; none are ever dropped.
+    pub entries: usize,
+    /// `prev`/`next`/`head`/`tail` links that differed from the rebuilt list.
+    pub links_repaired: usize,
+}
+
+/// Lookup counters of a [`ShardedLruCache`], as returned by
+/// [`stats`](ShardedLruCache::stats).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// `hits / (hits + misses)`, or 0 before the first lookup.
+    pub hit_ratio: f64,
+}
+
+/// A thread-safe LRU cache split into independently locked shards.
+///
+/// `S` hashes keys onto shards, as the [`BuildHasher`] of a `HashMap` does
+/// onto buckets. `U` is the unit of the capacity: [`Entries`] unless the
+/// cache was built with a [`Weight`], in which case entries are put with
+/// [`put_weighted`](Self::put_weighted).
+pub struct ShardedLruCache<K, V, S = DefaultHashBuilder, U = Entries> {
+    shards: Vec<Mutex<Shard<K, V>>>,
+    total_capacity: AtomicUsize,
+    /// Serializes `resize` calls so two of them cannot leave the shards
+    /// split between their capacities.
+    resizing: Mutex<()>,
+    /// Monotonic counter used to stamp entries on every touch.
+    tick: AtomicU64,
+    /// Bumped once by every operation that changes contents.
+    epoch: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    occupancy: Occupancy,
+    hasher: S,
+    distribution: DistributionStrategy,
+    /// `shards.len() - 1` when the shard count is a power of two, so
+    /// `shard_index` can mask instead of dividing.
+    mask: Option<usize>,
+    /// What TTL deadlines are set against and compared with.
+    #[cfg(feature = "std")]
+    time_source: TimeSource,
+    /// Tail entries each lookup or put checks for expiry.
+    tail_cleanup: usize,
+    unit: PhantomData<U>,
+}
+
+#[cfg(feature = "std")]
+impl<K, V> ShardedLruCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    /// Creates a cache holding at most `total_capacity` entries spread over
+    /// `shard_hint` shards, routing keys with a
+    /// [`RandomState`](std::collections::hash_map::RandomState).
+    ///
+    /// The shard count is clamped to `1..=total_capacity` so every shard can
+    /// hold at least one entry.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `total_capacity` is zero.
+    pub fn new(total_capacity: usize, shard_hint: usize) -> Self {
+        Self::with_hasher(total_capacity, shard_hint, DefaultHashBuilder::new())
+    }
+
+    /// Like [`new`](Self::new), with an explicit eviction policy.
+    pub fn with_policy(total_capacity: usize, shard_hint: usize, policy: EvictionPolicy) -> Self {
+        Self::with_hasher_and_policy(
+            total_capacity,
+            shard_hint,
+            DefaultHashBuilder::new(),
+            policy,
+        )
+    }
+
+    /// Like [`new`](Self::new), with the shard count rounded up to a power
+    /// of two so keys are routed by a bit mask rather than a division.
+    ///
+    /// If the rounded count would exceed `total_capacity`, the largest power
+    /// of two that does not is used instead. Any constructor given a power of
+    /// two shard count masks too; this one just picks such a count.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `total_capacity` is zero.
+    pub fn new_pow2(total_capacity: usize, min_shards: usize) -> Self {
+        Self::new(total_capacity, pow2_shard_count(total_capacity, min_shards))
+    }
+
+    /// Like [`new`](Self::new), then puts `entries` in order, so within each
+    /// shard the last one is the most recently used. Entries beyond a
+    /// shard's capacity evict its earlier ones as usual.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `total_capacity` is zero.
+    pub fn from_entries<I: IntoIterator<Item = (K, V)>>(
+        total_capacity: usize,
+        shard_hint: usize,
+        entries: I,
+    ) -> Self {
+        let cache = Self::new(total_capacity, shard_hint);
+        for (key, value) in entries {
+            cache.put(key, value);
+        }
+        cache
+    }
+}
+
+/// Collects into a single-shard cache sized to hold every item, via
+/// [`from_entries`](ShardedLruCache::from_entries), so the LRU order is the
+/// insertion order. An empty iterator gives a cache of capacity one.
+#[cfg(feature = "std")]
+impl<K, V> FromIterator<(K, V)> for ShardedLruCache<K, V>
+where
+    K: Eq + Hash + Clone,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let entries: Vec<_> = iter.into_iter().collect();
+        Self::from_entries(entries.len().max(1), 1, entries)
+    }
+}
+
+/// The power of two at least `min_shards`, or the largest one not above
+/// `total_capacity` if that is smaller.
+#[cfg(feature = "std")]
+fn pow2_shard_count(total_capacity: usize, min_shards: usize) -> usize {
+    let up = min_shards.max(1).checked_next_power_of_two();
+    match up {
+        Some(count) if count <= total_capacity => count,
+        _ => 1 << total_capacity.max(1).ilog2(),
+    }
+}
+
+impl<K, V, S> ShardedLruCache<K, V, S>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    /// Like [`new`](ShardedLruCache::new), routing keys to shards with
+    /// `hasher`. A fast non-cryptographic hasher suits small integer keys
+    /// that need no protection against collision attacks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `total_capacity` is zero.
+    pub fn with_hasher(total_capacity: usize, shard_hint: usize, hasher: S) -> Self {
+        Self::with_hasher_and_policy(total_capacity, shard_hint, hasher, EvictionPolicy::Lru)
+    }
+
+    /// Like [`with_hasher`](Self::with_hasher), with an explicit eviction
+    /// policy.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `total_capacity` is zero.
+    pub fn with_hasher_and_policy(
+        total_capacity: usize,
+        shard_hint: usize,
+        hasher: S,
+        policy: EvictionPolicy,
+    ) -> Self {
+        assert!(total_capacity > 0, "total_capacity must be non-zero");
+        let shard_count = shard_hint.clamp(1, total_capacity);
+        Self::build(
+            total_capacity,
+            shard_count,
+            hasher,
+            policy,
+            DistributionStrategy::Even,
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V, U> ShardedLruCache<K, V, DefaultHashBuilder, U>
+where
+    K: Eq + Hash + Clone,
+    U: CapacityUnit,
+{
+    /// Creates a cache bounded by `capacity` spread over `shard_hint`
+    /// shards. An [`Entries`] capacity gives the same cache as
+    /// [`new`](ShardedLruCache::new); a [`Weight`] bounds the summed weight
+    /// of the entries instead.
+    ///
+    /// The shard count is clamped to `1..=capacity`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity(capacity: U, shard_hint: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, shard_hint, DefaultHashBuilder::new())
+    }
+}
+
+impl<K, V, S, U> ShardedLruCache<K, V, S, U>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+    U: CapacityUnit,
+{
+    /// Like [`with_capacity`](ShardedLruCache::with_capacity), routing keys
+    /// to shards with `hasher`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity_and_hasher(capacity: U, shard_hint: usize, hasher: S) -> Self {
+        let total_capacity = capacity.into();
+        assert!(total_capacity > 0, "total_capacity must be non-zero");
+        let shard_count = shard_hint.clamp(1, total_capacity);
+        Self::build(
+            total_capacity,
+            shard_count,
+            hasher,
+            EvictionPolicy::Lru,
+            DistributionStrategy::Even,
+        )
+    }
+
+    /// Like [`with_capacity_and_hasher`](Self::with_capacity_and_hasher),
+    /// with an explicit eviction policy and the capacity split over the
+    /// shards by `distribution`.
+    ///
+    /// [`Even`](DistributionStrategy::Even) clamps `shard_hint` as the other
+    /// constructors do. [`Weighted`](DistributionStrategy::Weighted) uses
+    /// exactly `shard_hint` shards, and needs one non-zero weight and at
+    /// least one slot for each.
+    pub fn with_distribution(
+        total_capacity: U,
+        shard_hint: usize,
+        hasher: S,
+        policy: EvictionPolicy,
+        distribution: DistributionStrategy,
+    ) -> Result<Self, DistributionError> {
+        let total_capacity = total_capacity.into();
+        if total_capacity == 0 {
+            return Err(DistributionError::ZeroCapacity);
+        }
+        let shard_count = match &distribution {
+            DistributionStrategy::Even => shard_hint.clamp(1, total_capacity),
+            DistributionStrategy::Weighted(weights) => {
+                if weights.is_empty() || weights.len() != shard_hint {
+                    return Err(DistributionError::WeightCount {
+                        weights: weights.len(),
+                        shards: shard_hint,
+                    });
+                }
+                if let Some(idx) = weights.iter().position(|&w| w == 0) {
+                    return Err(DistributionError::ZeroWeight(idx));
+                }
+                if total_capacity < shard_hint {
+                    return Err(DistributionError::TooFewSlots {
+                        capacity: total_capacity,
+                        shards: shard_hint,
+                    });
+                }
+                shard_hint
+            }
+        };
+        Ok(Self::build(
+            total_capacity,
+            shard_count,
+            hasher,
+            policy,
+            distribution,
+        ))
+    }
+
+    /// Creates the shards once the arguments are known to be valid.
+    fn build(
+        total_capacity: usize,
+        shard_count: usize,
+        hasher: S,
+        policy: EvictionPolicy,
+        distribution: DistributionStrategy,
+    ) -> Self {
+        let mask = shard_count.is_power_of_two().then(|| shard_count - 1);
+        let shards = distribution
+            .split(total_capacity, shard_count)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, cap)| Mutex::new(Shard::new(idx, cap, policy)))
+            .collect();
+        Self {
+            shards,
+            total_capacity: AtomicUsize::new(total_capacity),
+            resizing: Mutex::new(()),
+            tick: AtomicU64::new(0),
+            epoch: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            occupancy: Occupancy::default(),
+            hasher,
+            distribution,
+            mask,
+            #[cfg(feature = "std")]
+            time_source: Arc::new(Instant::now),
+            tail_cleanup: 1,
+            unit: PhantomData,
+        }
+    }
+
+    fn shard_index(&self, key: &K) -> usize {
+        let hash = self.hasher.hash_one(key) as usize;
+        match self.mask {
+            Some(mask) => hash & mask,
+            None => hash % self.shards.len(),
+        }
+    }
+
+    /// Locks a shard, recovering the guard if a previous holder panicked.
+    ///
+    /// Every mutation leaves the shard consistent before it can panic (the
+    /// only panics possible are in user `Hash`/`Eq`/`Clone` impls), so the
+    /// data behind a poisoned lock is still usable.
+    fn lock_shard(&self, idx: usize) -> MutexGuard<'_, Shard<K, V>> {
+        sync::lock(&self.shards[idx])
+    }
+
+    fn next_tick(&self) -> u64 {
+        self.tick.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Counts a lookup. Metrics need no ordering, so Relaxed is enough.
+    fn record_lookup(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Advances the modification epoch for a change being made to `shard`.
+    ///
+    /// Must be called with the shard locked, before the lock is released, so
+    /// an observer that sees the new epoch and then locks the shard also sees
+    /// the change.
+    fn bump_epoch(&self, shard: &mut Shard<K, V>) -> u64 {
+        let epoch = self.epoch.fetch_add(1, Ordering::AcqRel) + 1;
+        shard.epoch = epoch;
+        epoch
+    }
+
+    /// Current reading of the [time source](Self::set_time_source).
+    #[cfg(feature = "std")]
+    fn now(&self) -> Instant {
+        (self.time_source)()
+    }
+
+    #[cfg(feature = "std")]
+    fn is_past(&self, deadline: Deadline) -> bool {
+        deadline <= self.now()
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn is_past(&self, deadline: Deadline) -> bool {
+        match deadline {}
+    }
+
+    /// Removes `key` from `shard` if its TTL has run out, so the caller
+    /// treats it as absent. Expiry changes the contents, so it advances the
+    /// epoch like any other removal.
+    fn drop_if_expired(&self, shard: &mut Shard<K, V>, key: &K) {
+        let expired = shard
+            .map
+            .get(key)
+            .and_then(|entry| entry.expires)
+            .is_some_and(|deadline| self.is_past(deadline));
+        if expired {
+            shard.remove(key);
+            self.occupancy.dropped(1);
+            self.bump_epoch(shard);
+        }
+    }
+
+    /// Checks up to [`tail_cleanup`](Self::set_tail_cleanup) entries from
+    /// the tail of `shard`, removing them while they are expired. The first
+    /// live one ends the check. Lookups and puts call it once their own work
+    /// is done, just before releasing the shard lock.
+    fn clean_tail(&self, shard: &mut Shard<K, V>) {
+        let mut dropped = 0;
+        for _ in 0..self.tail_cleanup {
+            let Some(tail) = &shard.tail else { break };
+            let expired = shard
+                .map
+                .get(tail)
+                .and_then(|entry| entry.expires)
+                .is_some_and(|deadline| self.is_past(deadline));
+            if !expired {
+                break;
+            }
+            let tail = tail.clone();
+            shard.remove(&tail);
+            dropped += 1;
+        }
+        if dropped > 0 {
+            self.occupancy.dropped(dropped);
+            self.bump_epoch(shard);
+        }
+    }
+
+    /// Marks `key` most recently used like [`get`](Self::get), without
+    /// reading its value, and returns whether it is cached. Unlike `get` it
+    /// counts neither a hit nor a miss in [`stats`](Self::stats). An expired
+    /// entry is removed and reported absent.
+    pub fn touch(&self, key: &K) -> bool {
+        let mut shard = self.lock_shard(self.shard_index(key));
+        self.drop_if_expired(&mut shard, key);
+        let stamp = self.next_tick();
+        let cached = shard.touch(key, stamp).is_some();
+        self.clean_tail(&mut shard);
+        cached
+    }
+
+    /// Runs `f` on the value for `key` and returns its result, marking the
+    /// key most recently used like [`get`](Self::get) but without cloning
+    /// the value, so `V` need not be `Clone`.
+    ///
+    /// `f` runs with the key's shard locked: it should be short, and must
+    /// not call back into the cache.
+    pub fn read<R, F: FnOnce(&V) -> R>(&self, key: &K, f: F) -> Option<R> {
+        let mut shard = self.lock_shard(self.shard_index(key));
+        self.drop_if_expired(&mut shard, key);
+        let stamp = self.next_tick();
+        let value = shard.touch(key, stamp).map(|entry| f(&entry.value));
+        self.record_lookup(value.is_some());
+        self.clean_tail(&mut shard);
+        value
+    }
+
+    /// Runs `f` on the values for `k1` and `k2` together, with both keys'
+    /// shards locked, so no other operation sees one changed without the
+    /// other. A key that is not cached, or has expired, is passed as `None`.
+    /// Cached keys are marked most recently used, `k2` last, and count as
+    /// written: they advance the epoch like a put.
+    ///
+    /// Two shards are always locked in ascending index order, so concurrent
+    /// calls on the same pair cannot deadlock; keys sharing a shard lock it
+    /// once. `f` runs under the locks: it should be short, and must not call
+    /// back into the cache.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k1 == k2`.
+    pub fn update_pair<F>(&self, k1: &K, k2: &K, f: F)
+    where
+        F: FnOnce(Option<&mut V>, Option<&mut V>),
+    {
+        assert!(k1 != k2, "update_pair needs two distinct keys");
+        let (i1, i2) = (self.shard_index(k1), self.shard_index(k2));
+        if i1 == i2 {
+            let mut shard = self.lock_shard(i1);
+            self.prepare_update(&mut shard, k1);
+            self.prepare_update(&mut shard, k2);
+            let [e1, e2] = shard.map.get_disjoint_mut([k1, k2]);
+            f(e1.map(|e| &mut e.value), e2.map(|e| &mut e.value));
+            self.clean_tail(&mut shard);
+            return;
+        }
+        let mut low = self.lock_shard(i1.min(i2));
+        let mut high = self.lock_shard(i1.max(i2));
+        let (s1, s2) = if i1 < i2 {
+            (&mut *low, &mut *high)
+        } else {
+            (&mut *high, &mut *low)
+        };
+        self.prepare_update(s1, k1);
+        self.prepare_update(s2, k2);
+        f(
+            s1.map.get_mut(k1).map(|e| &mut e.value),
+            s2.map.get_mut(k2).map(|e| &mut e.value),
+        );
+        self.clean_tail(s1);
+        self.clean_tail(s2);
+    }
+
+    /// Readies `key` in its locked `shard` for a write through
+    /// [`update_pair`](Self::update_pair): drops it if expired, otherwise
+    /// marks it most recently used and stamps it with a new epoch.
+    fn prepare_update(&self, shard: &mut Shard<K, V>, key: &K) {
+        self.drop_if_expired(shard, key);
+        if !shard.map.contains_key(key) {
+            return;
+        }
+        let stamp = self.next_tick();
+        let epoch = self.bump_epoch(shard);
+        if let Some(entry) = shard.touch(key, stamp) {
+            entry.modified = epoch;
+        }
+    }
+
+    /// Inserts or updates `key`, marking it most recently used and evicting
+    /// the shard's least recently used entry if the shard overflows.
+    ///
+    /// The outcome reports both the overwritten value and the evicted entry
+    /// so callers can write them back. The entry never expires, even if it
+    /// replaces one put with a TTL.
+    pub fn put(&self, key: K, value: V) -> PutOutcome<K, V> {
+        Self::single_eviction(self.put_until(key, value, 1, None))
+    }
+
+    /// Like [`put`](Self::put), but the entry expires `ttl` from now,
+    /// however often it is read.
+    ///
+    /// Once expired, lookups treat the entry as absent and remove it.
+    /// Traffic on its shard also removes it once it is the tail (see
+    /// [`set_tail_cleanup`](Self::set_tail_cleanup)). Until then, or until
+    /// [`purge_expired`](Self::purge_expired) runs, it still takes up
+    /// capacity and shows up in [`len`](Self::len), [`iter`](Self::iter)
+    /// and the peeks.
+    #[cfg(feature = "std")]
+    pub fn put_with_ttl(&self, key: K, value: V, ttl: Duration) -> PutOutcome<K, V> {
+        Self::single_eviction(self.put_until(key, value, 1, Some(self.now() + ttl)))
+    }
+
+    /// Narrows the outcome of a weight-1 put. Shards never exceed their
+    /// capacity and every entry weighs at least 1, so evicting one entry
+    /// always makes room for such a put.
+    fn single_eviction(outcome: WeightedPutOutcome<K, V>) -> PutOutcome<K, V> {
+        let mut evicted = outcome.evicted;
+        debug_assert!(evicted.len() <= 1, "a weight-1 put evicted several entries");
+        PutOutcome {
+            previous: outcome.previous,
+            evicted: evicted.pop(),
+        }
+    }
+
+    fn put_until(
+        &self,
+        key: K,
+        value: V,
+        weight: usize,
+        expires: Option<Deadline>,
+    ) -> WeightedPutOutcome<K, V> {
+        assert!(weight > 0, "weight must be non-zero");
+        let mut shard = self.lock_shard(self.shard_index(&key));
+        let outcome = self.put_locked(&mut shard, key, value, weight, expires);
+        self.clean_tail(&mut shard);
+        outcome
+    }
+
+    /// Body of [`put_until`](Self::put_until), with `key`'s shard locked.
+    /// Leaves [cleaning the tail](Self::clean_tail) to the caller.
+    fn put_locked(
+        &self,
+        shard: &mut Shard<K, V>,
+        key: K,
+        value: V,
+        weight: usize,
+        expires: Option<Deadline>,
+    ) -> WeightedPutOutcome<K, V> {
+        self.drop_if_expired(shard, &key);
+        if weight > shard.capacity {
+            // Would not fit even in an empty shard.
+            let previous = shard.remove(&key);
+            if previous.is_some() {
+                self.occupancy.dropped(1);
+                self.bump_epoch(shard);
+            }
+            return WeightedPutOutcome {
+                previous,
+                evicted: vec![(key, value)],
+            };
+        }
+        let stamp = self.next_tick();
+        let epoch = self.bump_epoch(shard);
+        if let Some(entry) = shard.touch(&key, stamp) {
+            entry.modified = epoch;
+            entry.expires = expires;
+            let old_weight = core::mem::replace(&mut entry.weight, weight);
+            let previous = Some(core::mem::replace(&mut entry.value, value));
+            shard.current_weight = shard.current_weight - old_weight + weight;
+            // The key was just touched, so it is the last entry the policy
+            // would pick, and it fits on its own.
+            let mut evicted = Vec::new();
+            while let Some(victim) = shard.evict_if_needed() {
+                evicted.push(victim);
+            }
+            self.occupancy.evicted(evicted.len());
+            return WeightedPutOutcome { previous, evicted };
+        }
+        let evicted = shard.insert_new(key, value, weight, stamp, epoch, expires);
+        self.occupancy.inserted(evicted.len());
+        WeightedPutOutcome {
+            previous: None,
+            evicted,
+        }
+    }
+
+    /// Puts every item like [`put`](Self::put), discarding the outcomes.
+    ///
+    /// Items are grouped by shard first, and each shard is locked once for
+    /// its whole group rather than once per item. Within a shard the items
+    /// are put in iteration order, so the last one for a key wins and is the
+    /// most recently used; the interleaving with concurrent writers is the
+    /// only difference from a loop of puts.
+    pub fn extend<I: IntoIterator<Item = (K, V)>>(&self, iter: I) {
+        let mut buckets: Vec<Vec<(K, V)>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (key, value) in iter {
+            buckets[self.shard_index(&key)].push((key, value));
+        }
+        for (idx, bucket) in buckets.into_iter().enumerate() {
+            if bucket.is_empty() {
+                continue;
+            }
+            let mut shard = self.lock_shard(idx);
+            for (key, value) in bucket {
+                self.put_locked(&mut shard, key, value, 1, None);
+                self.clean_tail(&mut shard);
+            }
+        }
+    }
+
+    /// Current modification epoch.
+    ///
+    /// The epoch starts at 0 and every operation that changes the cache's
+    /// contents (inserts, overwrites and the evictions they cause) advances
+    /// it, as does removing an expired entry. Reads otherwise never do. Once
+    /// a thread observes an epoch, every change up to it is visible to that
+    /// thread's subsequent operations.
+    pub fn modification_epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Acquire)
+    }
+
+    /// Epoch of the last change to shard `idx`, or 0 if it was never changed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is not a valid shard index.
+    pub fn shard_epoch(&self, idx: usize) -> u64 {
+        self.lock_shard(idx).epoch
+    }
+
+    /// Evicts from the shard whose tail has the oldest stamp and returns
+    /// the evicted entry. Under [`EvictionPolicy::Lru`] that is the tail
+    /// itself, the entry [`peek_lru`](Self::peek_lru) reports.
+    ///
+    /// A put only ever evicts from its own shard, so a busy shard drops
+    /// entries newer than an idle shard's tail. Trimming with this instead
+    /// follows the global recency order more closely. Under
+    /// [`EvictionPolicy::Clock`] the chosen shard evicts its own victim,
+    /// which may be behind a referenced tail, so the result can differ
+    /// from `peek_lru`. The same cross-shard caveats as `peek_lru` apply.
+    /// The entry reaches the eviction listener.
+    pub fn evict_global(&self) -> Option<(K, V)> {
+        let mut oldest: Option<(u64, usize)> = None;
+        for idx in 0..self.shards.len() {
+            let shard = self.lock_shard(idx);
+            let Some(tail) = &shard.tail else { continue };
+            let stamp = shard.map[tail].stamp;
+            if oldest.is_none_or(|(s, _)| stamp < s) {
+                oldest = Some((stamp, idx));
+            }
+        }
+        let (_, idx) = oldest?;
+        let mut shard = self.lock_shard(idx);
+        let victim = shard.evict()?;
+        self.occupancy.evicted(1);
+        self.bump_epoch(&mut shard);
+        Some(victim)
+    }
+
+    /// Returns every cached key that can no longer be found through the
+    /// cache, because its current hash maps to a different shard or to a
+    /// different bucket within its shard.
+    ///
+    /// Such keys come from a `Hash` implementation that changed after
+    /// insertion (e.g. through interior mutability) or that disagrees with
+    /// `Eq`. The entries still occupy capacity until they are evicted.
+    pub fn check_hash_consistency(&self) -> Vec<K> {
+        let mut stale = Vec::new();
+        for idx in 0..self.shards.len() {
+            let shard = self.lock_shard(idx);
+            stale.extend(
+                shard
+                    .map
+                    .keys()
+                    .filter(|k| self.shard_index(k) != idx || shard.hash_drifted(k))
+                    .cloned(),
+            );
+        }
+        stale
+    }
+
+    /// Number of entries across all shards.
+    ///
+    /// Shards are counted one at a time, so the result may be stale under
+    /// concurrent writes.
+    pub fn len(&self) -> usize {
+        (0..self.shards.len())
+            .map(|idx| self.lock_shard(idx).map.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of entries, read from a counter kept alongside the occupancy
+    /// stats instead of locking every shard.
+    ///
+    /// Writers update the counter after changing their shard, so under
+    /// concurrent writes this may briefly disagree with [`len`](Self::len).
+    /// Once writers are quiet the two match.
+    pub fn approx_len(&self) -> usize {
+        self.occupancy.len()
+    }
+
+    /// Capacity of the whole cache, in its unit.
+    pub fn total_capacity(&self) -> U {
+        U::from(self.total_capacity.load(Ordering::Relaxed))
+    }
+
+    /// Number of shards, fixed at construction.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Number of entries in each shard, by shard index. A shard far fuller
+    /// than the others points at skew in the key hashes.
+    ///
+    /// Shards are counted one at a time, like [`len`](Self::len).
+    pub fn per_shard_len(&self) -> Vec<usize> {
+        (0..self.shards.len())
+            .map(|idx| self.lock_shard(idx).map.len())
+            .collect()
+    }
+
+    /// Capacity of each shard, by shard index, in the cache's unit. They
+    /// sum to [`total_capacity`](Self::total_capacity) outside a
+    /// [`resize`](Self::resize).
+    pub fn per_shard_capacity(&self) -> Vec<U> {
+        (0..self.shards.len())
+            .map(|idx| U::from(self.lock_shard(idx).capacity))
+            .collect()
+    }
+
+    /// Changes the total capacity, redistributing it over the existing
+    /// shards. Shards that shrink evict their least recently used entries
+    /// until they fit.
+    ///
+    /// Shards are resized one at a time, so the rest of the cache stays
+    /// available throughout. While a resize is in progress some shards
+    /// already have their new capacity and others do not;
+    /// [`total_capacity`](Self::total_capacity) reports the larger of the old
+    /// and new totals until it finishes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_total_capacity` is smaller than the shard count.
+    pub fn resize(&self, new_total_capacity: U) {
+        let new_total_capacity = new_total_capacity.into();
+        assert!(
+            new_total_capacity >= self.shards.len(),
+            "new_total_capacity must be at least the shard count"
+        );
+        let _resizing = sync::lock(&self.resizing);
+        let growing = new_total_capacity > self.total_capacity.load(Ordering::Relaxed);
+        if growing {
+            self.total_capacity
+                .store(new_total_capacity, Ordering::Relaxed);
+        }
+        for (idx, cap) in self
+            .distribution
+            .split(new_total_capacity, self.shards.len())
+            .into_iter()
+            .enumerate()
+        {
+            let mut shard = self.lock_shard(idx);
+            shard.capacity = cap;
+            let mut evicted = 0;
+            while shard.evict_if_needed().is_some() {
+                evicted += 1;
+            }
+            if evicted > 0 {
+                self.occupancy.evicted(evicted);
+                self.bump_epoch(&mut shard);
+            }
+        }
+        if !growing {
+            self.total_capacity
+                .store(new_total_capacity, Ordering::Relaxed);
+        }
+    }
+
+    /// Removes every expired entry, locking one shard at a time, and returns
+    /// how many were removed.
+    ///
+    /// Lookups already ignore expired entries; this frees the capacity of
+    /// those nobody looks up again.
+    #[cfg(feature = "std")]
+    pub fn purge_expired(&self) -> usize {
+        let now = self.now();
+        let mut purged = 0;
+        for idx in 0..self.shards.len() {
+            let mut shard = self.lock_shard(idx);
+            let expired: Vec<K> = shard
+                .map
+                .iter()
+                .filter(|(_, entry)| entry.expires.is_some_and(|deadline| deadline <= now))
+                .map(|(key, _)| key.clone())
+                .collect();
+            if expired.is_empty() {
+                continue;
+            }
+            for key in &expired {
+                shard.remove(key);
+            }
+            self.occupancy.dropped(expired.len());
+            self.bump_epoch(&mut shard);
+            purged += expired.len();
+        }
+        purged
+    }
+
+    /// Empties the cache, returning every shard's entries from most to least
+    /// recently used, shard 0 first.
+    ///
+    /// Shards are drained one at a time, so this is not atomic: puts into a
+    /// shard already drained land in the emptied cache and are not returned.
+    pub fn drain(&self) -> Vec<(K, V)> {
+        let mut out = Vec::new();
+        for idx in 0..self.shards.len() {
+            let mut shard = self.lock_shard(idx);
+            if shard.map.is_empty() {
+                continue;
+            }
+            let mut cur = shard.head.take();
+            shard.tail = None;
+            shard.current_weight = 0;
+            let drained = shard.map.len();
+            while let Some(key) = cur {
+                let entry = shard.map.remove(&key).expect("linked key is mapped");
+                cur = entry.next;
+                out.push((key, entry.value));
+            }
+            debug_assert!(shard.map.is_empty());
+            self.occupancy.dropped(drained);
+            self.bump_epoch(&mut shard);
+        }
+        out
+    }
+
+    /// Removes every entry for which `f` returns `false`, locking one shard
+    /// at a time and visiting its entries from most to least recently used.
+    /// The survivors keep their relative order.
+    ///
+    /// `f` runs with a shard locked, so it must not call back into the
+    /// cache.
+    pub fn retain<F: FnMut(&K, &V) -> bool>(&self, mut f: F) {
+        for idx in 0..self.shards.len() {
+            let mut shard = self.lock_shard(idx);
+            let mut rejected = Vec::new();
+            let mut cur = shard.head.as_ref();
+            while let Some(key) = cur {
+                let entry = &shard.map[key];
+                if !f(key, &entry.value) {
+                    rejected.push(key.clone());
+                }
+                cur = entry.next.as_ref();
+            }
+            if rejected.is_empty() {
+                continue;
+            }
+            for key in &rejected {
+                shard.remove(key);
+            }
+            self.occupancy.dropped(rejected.len());
+            self.bump_epoch(&mut shard);
+        }
+    }
+
+    /// Hits and misses since creation or the last
+    /// [`reset_stats`](Self::reset_stats).
+    ///
+    /// `get` and a successful `try_get` count one of either. So does
+    /// `get_or_insert_with`; its miss is the call that runs the closure.
+    /// The counters are read separately, so under concurrent use the pair
+    /// is not an exact snapshot.
+    pub fn stats(&self) -> CacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+        CacheStats {
+            hits,
+            misses,
+            hit_ratio: if lookups == 0 {
+                0.0
+            } else {
+                hits as f64 / lookups as f64
+            },
+        }
+    }
+
+    /// Zeroes the hit and miss counters.
+    pub fn reset_stats(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+    }
+
+    /// Removes every entry, locking one shard at a time.
+    ///
+    /// This is not atomic across shards: a concurrent reader can observe
+    /// some shards already empty and others not yet cleared, and entries
+    /// inserted into an already cleared shard during the call survive it.
+    pub fn clear(&self) {
+        for idx in 0..self.shards.len() {
+            let mut shard = self.lock_shard(idx);
+            if shard.map.is_empty() {
+                continue;
+            }
+            self.occupancy.dropped(shard.map.len());
+            shard.map.clear();
+            shard.head = None;
+            shard.tail = None;
+            shard.current_weight = 0;
+            self.bump_epoch(&mut shard);
+        }
+    }
+
+    /// High-water mark of [`len`](Self::len) and insertion/eviction counts
+    /// since creation or the last
+    /// [`reset_occupancy_stats`](Self::reset_occupancy_stats), with the churn
+    /// rate of the last completed window.
+    pub fn occupancy_stats(&self) -> OccupancyStats {
+        self.occupancy.snapshot()
+    }
+
+    /// Closes the current churn window and starts a new one. Call it on
+    /// whatever schedule the window should span, e.g. from a periodic
+    /// maintenance task.
+    pub fn rotate_occupancy_window(&self) {
+        self.occupancy.rotate();
+    }
+
+    /// Zeroes the occupancy counters and restarts the high-water mark from
+    /// the current length. Independent of [`reset_stats`](Self::reset_stats).
+    pub fn reset_occupancy_stats(&self) {
+        self.occupancy.reset();
+    }
+
+    /// Registers `f` to be called with every entry evicted to keep a shard
+    /// within its capacity, by a put or by [`resize`](Self::resize), and
+    /// with every entry taken by [`evict_global`](Self::evict_global).
+    /// Replaces any listener registered before.
+    ///
+    /// Entries that leave the cache any other way do not reach `f`:
+    /// overwritten values are returned by the put, expired entries are
+    /// dropped silently, and [`clear`](Self::clear) drops everything
+    /// silently. `f` runs with the entry's shard locked, just before the
+    /// entry is removed, so it must not access keys in the same shard of
+    /// this cache.
+    pub fn on_evict<F>(&mut self, f: F)
+    where
+        F: Fn(&K, &V) + Send + Sync + 'static,
+    {
+        let f: EvictListener<K, V> = Arc::new(f);
+        for shard in &mut self.shards {
+            sync::get_mut(shard).on_evict = Some(Arc::clone(&f));
+        }
+    }
+
+    /// Replaces the clock TTLs are measured on, [`Instant::now`] by default.
+    /// Deadlines already set were read from the old clock and are compared
+    /// with the new one as they are. Tests use this to expire entries
+    /// without sleeping.
+    #[cfg(feature = "std")]
+    pub fn set_time_source<F>(&mut self, f: F)
+    where
+        F: Fn() -> Instant + Send + Sync + 'static,
+    {
+        self.time_source = Arc::new(f);
+    }
+
+    /// Sets how many entries from the tail of its shard each lookup and put
+    /// checks for expiry, removing those that have expired. The check runs
+    /// under the shard lock the operation already holds and stops at the
+    /// first live entry, so it costs at most `budget` steps, and reads the
+    /// clock only for tails put with a TTL.
+    ///
+    /// Traffic then clears expired entries nobody looks up again, before
+    /// capacity pressure evicts live ones in their place.
+    ///
+    /// The default budget is 1, so every cache does this unless told
+    /// otherwise: a [`get`](Self::get) or [`put`](Self::put) may remove an
+    /// expired entry of another key, which reaches neither its outcome nor
+    /// the eviction listener. 0 turns the check off, leaving expired
+    /// entries to lookups of their own keys and to
+    /// [`purge_expired`](Self::purge_expired).
+    #[cfg(feature = "std")]
+    pub fn set_tail_cleanup(&mut self, budget: usize) {
+        self.tail_cleanup = budget;
+    }
+
+    /// Rebuilds shard `idx`'s recency list from its map, ordering entries by
+    /// their last-access stamps.
+    ///
+    /// This is a remediation for a shard that [`validate`](Self::validate)
+    /// rejects: no entry is lost, and the list afterwards matches the order
+    /// the shard would have had without the corruption. Under
+    /// [`EvictionPolicy::Clock`], whose list is not kept in access order,
+    /// the rebuilt list is in access order all the same.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `idx` is not a valid shard index.
+    pub fn rebuild_shard(&self, idx: usize) -> RebuildReport {
+        self.lock_shard(idx).rebuild()
+    }
+
+    /// Keys of every shard in MRU→LRU order, shard 0 first, without
+    /// changing that order. The same walk as
+    /// [`debug_order`](Self::debug_order); shards are locked one at a time.
+    pub fn keys(&self) -> Vec<K> {
+        self.debug_order()
+    }
+
+    /// Keys of every shard in MRU→LRU order, shard 0 first.
+    pub fn debug_order(&self) -> Vec<K> {
+        let mut out = Vec::new();
+        for idx in 0..self.shards.len() {
+            let shard = self.lock_shard(idx);
+            let mut cur = shard.head.clone();
+            while let Some(k) = cur {
+                cur = shard.map[&k].next.clone();
+                out.push(k);
+            }
+        }
+        out
+    }
+}
+
+impl<K, V, S> ShardedLruCache<K, V, S, Weight>
+where
+    K: Eq + Hash + Clone,
+    S: BuildHasher,
+{
+    /// Like [`put`](Self::put), but the entry counts `weight` towards its
+    /// shard's capacity instead of 1.
+    ///
+    /// Least recently used entries are evicted until the shard's summed
+    /// weight fits, possibly several of them. An entry heavier than its
+    /// whole shard is not cached: it is returned in `evicted`, and an older
+    /// entry under the key is removed and returned as `previous`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is zero.
+    pub fn put_weighted(&self, key: K, value: V, weight: Weight) -> WeightedPutOutcome<K, V> {
+        self.put_until(key, value, weight.0, None)
+    }
+
+    /// Summed weight of the entries across all shards. Entries put with
+    /// [`put`](Self::put) weigh 1.
+    ///
+    /// Shards are counted one at a time, like [`len`](Self::len).
+    pub fn weight(&self) -> Weight {
+        Weight(
+            (0..self.shards.len())
+                .map(|idx| self.lock_shard(idx).current_weight)
+                .sum(),
+        )
+    }
+}
+
+impl<K, V, S, U> ShardedLruCache<K, V, S, U>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher,
+    U: CapacityUnit,
+{
+    /// Returns a clone of the value for `key`, marking it most recently used.
+    /// An expired entry counts as a miss and is removed.
+    pub fn get(&self, key: &K) -> Option<V> {
+        self.read(key, V::clone)
+    }
+
+    /// Looks up every key in `keys`, locking each shard they touch once
+    /// rather than once per key. Results come back in the order of `keys`,
+    /// and each hit is marked most recently used as [`get`](Self::get) would.
+    ///
+    /// A key repeated in `keys` is looked up each time it appears.
+    pub fn get_many(&self, keys: &[K]) -> Vec<Option<V>> {
+        let mut buckets: Vec<Vec<usize>> = (0..self.shards.len()).map(|_| Vec::new()).collect();
+        for (pos, key) in keys.iter().enumerate() {
+            buckets[self.shard_index(key)].push(pos);
+        }
+        let mut values = vec![None; keys.len()];
+        for (idx, bucket) in buckets.into_iter().enumerate() {
+            if bucket.is_empty() {
+                continue;
+            }
+            let mut shard = self.lock_shard(idx);
+            for pos in bucket {
+                let key = &keys[pos];
+                self.drop_if_expired(&mut shard, key);
+                let stamp = self.next_tick();
+                values[pos] = shard.touch(key, stamp).map(|entry| entry.value.clone());
+                self.record_lookup(values[pos].is_some());
+                self.clean_tail(&mut shard);
+            }
+        }
+        values
+    }
+
+    /// Like [`get`](Self::get), but fails with [`TryGetError::WouldBlock`]
+    /// instead of waiting when the key's shard is locked.
+    ///
+    /// `WouldBlock` says nothing about whether the key is cached; callers
+    /// should treat it as "unknown" and fall back to the source of truth.
+    pub fn try_get(&self, key: &K) -> Result<Option<V>, TryGetError> {
+        let mut shard =
+            sync::try_lock(&self.shards[self.shard_index(key)]).ok_or(TryGetError::WouldBlock)?;
+        self.drop_if_expired(&mut shard, key);
+        let stamp = self.next_tick();
+        let value = shard.touch(key, stamp).map(|entry| entry.value.clone());
+        self.record_lookup(value.is_some());
+        self.clean_tail(&mut shard);
+        Ok(value)
+    }
+
+    /// Returns the value for `key`, computing and inserting it with `f` on a
+    /// miss.
+    ///
+    /// The shard lock is held across the lookup, `f` and the insert, so when
+    /// several threads miss on the same key `f` runs once and the others get
+    /// its result. Because `f` runs under the lock it should be quick, and it
+    /// must not access a key in the same shard of this cache.
+    pub fn get_or_insert_with<F: FnOnce() -> V>(&self, key: K, f: F) -> V {
+        let mut shard = self.lock_shard(self.shard_index(&key));
+        self.drop_if_expired(&mut shard, &key);
+        let stamp = self.next_tick();
+        if let Some(entry) = shard.touch(&key, stamp) {
+            let value = entry.value.clone();
+            self.record_lookup(true);
+            self.clean_tail(&mut shard);
+            return value;
+        }
+        self.record_lookup(false);
+        let value = f();
+        let epoch = self.bump_epoch(&mut shard);
+        let evicted = shard.insert_new(key, value.clone(), 1, stamp, epoch, None);
+        self.occupancy.inserted(evicted.len());
+        self.clean_tail(&mut shard);
+        value
+    }
+
+    /// Inserts `key` only if it is absent. Returns `None` if it inserted,
+    /// or a clone of the cached value, which is left as it was but marked
+    /// most recently used.
+    ///
+    /// The check and the insert happen under one shard lock, so of several
+    /// threads racing to insert the same key exactly one succeeds. An
+    /// expired entry counts as absent.
+    pub fn put_if_absent(&self, key: K, value: V) -> Option<V> {
+        let mut shard = self.lock_shard(self.shard_index(&key));
+        self.drop_if_expired(&mut shard, &key);
+        let stamp = self.next_tick();
+        let cached = match shard.touch(&key, stamp) {
+            Some(entry) => Some(entry.value.clone()),
+            None => {
+                let epoch = self.bump_epoch(&mut shard);
+                let evicted = shard.insert_new(key, value, 1, stamp, epoch, None);
+                self.occupancy.inserted(evicted.len());
+                None
+            }
+        };
+        self.clean_tail(&mut shard);
+        cached
+    }
+
+    /// Polls `key` for changes since `epoch` (as returned by
+    /// [`modification_epoch`](Self::modification_epoch)).
+    ///
+    /// Returns `None` if the key is unchanged since `epoch`, otherwise
+    /// `Some` with its current value, which is `None` if it is no longer
+    /// cached. An absent key is only known by its shard's epoch, so its
+    /// absence may be reported as a change even when some other key in that
+    /// shard was the one modified. A real change is never missed. Reporting
+    /// a present value counts as an access.
+    pub fn get_if_modified_since(&self, key: &K, epoch: u64) -> Option<Option<V>> {
+        let mut shard = self.lock_shard(self.shard_index(key));
+        self.drop_if_expired(&mut shard, key);
+        let change = match shard.map.get(key) {
+            Some(entry) if entry.modified <= epoch => None,
+            Some(_) => {
+                let stamp = self.next_tick();
+                shard
+                    .touch(key, stamp)
+                    .map(|entry| Some(entry.value.clone()))
+            }
+            None if shard.epoch <= epoch => None,
+            None => Some(None),
+        };
+        self.clean_tail(&mut shard);
+        change
+    }
+
+    /// Returns the least recently touched entry across all shards without
+    /// changing any recency order.
+    ///
+    /// Shards have no shared ordering, so each shard's tail is compared by the
+    /// tick it was stamped with on its last access. Shards are inspected one
+    /// at a time, so under concurrent writes the result is a best effort: it
+    /// is always the tail of some shard, but another shard may have changed
+    /// by the time the call returns.
+    ///
+    /// Under [`EvictionPolicy::Lru`] the tail is the next entry its shard
+    /// would evict. [`EvictionPolicy::Clock`] skips a referenced tail, so
+    /// there the result is only the least recently touched entry.
+    pub fn peek_lru(&self) -> Option<(K, V)> {
+        self.peek_by(|shard| shard.tail.as_ref(), |cand, best| cand < best)
+    }
+
+    /// Returns the most recently touched entry across all shards without
+    /// changing any recency order.
+    ///
+    /// See [`peek_lru`](Self::peek_lru) for the cross-shard caveats.
+    pub fn peek_mru(&self) -> Option<(K, V)> {
+        self.peek_by(|shard| shard.head.as_ref(), |cand, best| cand > best)
+    }
+
+    fn peek_by(
+        &self,
+        end: impl Fn(&Shard<K, V>) -> Option<&K>,
+        better: impl Fn(u64, u64) -> bool,
+    ) -> Option<(K, V)> {
+        let mut best: Option<(u64, K, V)> = None;
+        for idx in 0..self.shards.len() {
+            let shard = self.lock_shard(idx);
+            let Some(key) = end(&shard) else { continue };
+            let entry = &shard.map[key];
+            if best
+                .as_ref()
+                .is_none_or(|(s, _, _)| better(entry.stamp, *s))
+            {
+                best = Some((entry.stamp, key.clone(), entry.value.clone()));
+            }
+        }
+        best.map(|(_, k, v)| (k, v))
+    }
+
+    /// Clones of every entry, each shard in MRU→LRU order, shard 0 first.
+    /// Recency order is not changed.
+    ///
+    /// Shards are copied one at a time, so under concurrent writes the
+    /// result may be stale: each shard's part is consistent, but other shards
+    /// can change while it is copied.
+    pub fn iter(&self) -> Vec<(K, V)> {
+        let mut out = Vec::new();
+        for idx in 0..self.shards.len() {
+            let shard = self.lock_shard(idx);
+            let mut cur = shard.head.as_ref();
+            while let Some(k) = cur {
+                let entry = &shard.map[k];
+                out.push((k.clone(), entry.value.clone()));
+                cur = entry.next.as_ref();
+            }
+        }
+        out
+    }
+
+    /// Clones of the values, in the same order as [`keys`](Self::keys).
+    pub fn values(&self) -> Vec<V> {
+        let mut out = Vec::new();
+        for idx in 0..self.shards.len() {
+            let shard = self.lock_shard(idx);
+            let mut cur = shard.head.as_ref();
+            while let Some(k) = cur {
+                let entry = &shard.map[k];
+                out.push(entry.value.clone());
+                cur = entry.next.as_ref();
+            }
+        }
+        out
+    }
+}
+
+impl<K, V, S, U> ShardedLruCache<K, V, S, U>
+where
+    K: Eq + Hash + Clone + Debug,
+    S: BuildHasher,
+    U: CapacityUnit,
+{
+    /// Checks every shard's internal invariants, one shard at a time, and
+    /// reports the first violation found. The only method that needs
+    /// `K: Debug`, to name the offending keys.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        for idx in 0..self.shards.len() {
+            self.lock_shard(idx)
+                .check()
+                .map_err(|reason| ValidationError { shard: idx, reason })?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies the contents, recency order, capacities and counters into an
+/// independent cache, locking one shard at a time, so a clone taken under
+/// concurrent writes is not an atomic snapshot. The eviction listener is
+/// not copied: evictions from the clone go unreported until it gets its own
+/// [`on_evict`](ShardedLruCache::on_evict).
+impl<K, V, S, U> Clone for ShardedLruCache<K, V, S, U>
+where
+    K: Eq + Hash + Clone,
+    V: Clone,
+    S: BuildHasher + Clone,
+    U: CapacityUnit,
+{
+    fn clone(&self) -> Self {
+        let shards: Vec<_> = (0..self.shards.len())
+            .map(|idx| {
+                let mut shard = self.lock_shard(idx).clone();
+                shard.on_evict = None;
+                Mutex::new(shard)
+            })
+            .collect();
+        let len = shards.iter().map(|shard| sync::lock(shard).map.len()).sum();
+        Self {
+            shards,
+            total_capacity: AtomicUsize::new(self.total_capacity.load(Ordering::Relaxed)),
+            resizing: Mutex::new(()),
+            tick: AtomicU64::new(self.tick.load(Ordering::Relaxed)),
+            epoch: AtomicU64::new(self.epoch.load(Ordering::Relaxed)),
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+            misses: AtomicU64::new(self.misses.load(Ordering::Relaxed)),
+            occupancy: self.occupancy.copy_with_len(len),
+            hasher: self.hasher.clone(),
+            distribution: self.distribution.clone(),
+            mask: self.mask,
+            #[cfg(feature = "std")]
+            time_source: Arc::clone(&self.time_source),
+            tail_cleanup: self.tail_cleanup,
+            unit: PhantomData,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use std::hash::Hasher;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::Arc;
+
+    #[test]
+    fn distribute_capacity_spreads_remainder() {
+        assert_eq!(distribute_capacity(10, 3), vec![4, 3, 3]);
+        assert_eq!(distribute_capacity(9, 3), vec![3, 3, 3]);
+        assert_eq!(distribute_capacity(2, 2), vec![1, 1]);
+    }
+
+    fn distributed(
+        total: usize,
+        shards: usize,
+        distribution: DistributionStrategy,
+    ) -> Result<ShardedLruCache<u32, u32>, DistributionError> {
+        ShardedLruCache::with_distribution(
+            Entries(total),
+            shards,
+            DefaultHashBuilder::new(),
+            EvictionPolicy::Lru,
+            distribution,
+        )
+    }
+
+    #[test]
+    fn even_distribution_matches_the_default() {
+        let cache = distributed(10, 3, DistributionStrategy::Even).unwrap();
+        assert_eq!(
+            cache.per_shard_capacity(),
+            [Entries(4), Entries(3), Entries(3)]
+        );
+        let clamped = distributed(2, 5, DistributionStrategy::Even).unwrap();
+        assert_eq!(clamped.per_shard_capacity(), [Entries(1); 2]);
+    }
+
+    #[test]
+    fn weighted_distribution_follows_the_weights() {
+        let weighted = |total, weights: &[usize]| {
+            let strategy = DistributionStrategy::Weighted(weights.to_vec());
+            let caps = distributed(total, weights.len(), strategy)
+                .unwrap()
+                .per_shard_capacity();
+            caps.into_iter().map(usize::from).collect::<Vec<_>>()
+        };
+        assert_eq!(weighted(8, &[3, 1]), [6, 2]);
+        // 7 spare slots: 1.75, 1.75 and 3.5 round down, and the two largest
+        // fractions get the leftovers.
+        assert_eq!(weighted(10, &[1, 1, 2]), [3, 3, 4]);
+        // A tiny weight still gets its one slot.
+        assert_eq!(weighted(4, &[1000, 1]), [3, 1]);
+        assert_eq!(weighted(3, &[5, 5, 5]), [1, 1, 1]);
+
+        let cache = distributed(8, 2, DistributionStrategy::Weighted(vec![3, 1])).unwrap();
+        cache.resize(Entries(16));
+        assert_eq!(cache.per_shard_capacity(), [Entries(12), Entries(4)]);
+        cache.validate().unwrap();
+    }
+
+    #[test]
+    fn weighted_distribution_rejects_bad_weights() {
+        let weighted = |total, shards, weights: Vec<usize>| {
+            distributed(total, shards, DistributionStrategy::Weighted(weights)).err()
+        };
+        assert_eq!(
+            weighted(8, 3, vec![1, 1]),
+            Some(DistributionError::WeightCount {
+                weights: 2,
+                shards: 3
+            })
+        );
+        assert_eq!(
+            weighted(8, 0, vec![]),
+            Some(DistributionError::WeightCount {
+                weights: 0,
+                shards: 0
+            })
+        );
+        assert_eq!(
+            weighted(8, 2, vec![1, 0]),
+            Some(DistributionError::ZeroWeight(1))
+        );
+        assert_eq!(
+            weighted(2, 3, vec![1, 1, 1]),
+            Some(DistributionError::TooFewSlots {
+                capacity: 2,
+                shards: 3
+            })
+        );
+        assert_eq!(
+            distributed(0, 1, DistributionStrategy::Even).err(),
+            Some(DistributionError::ZeroCapacity)
+        );
+    }
+
+    #[test]
+    fn from_entries_orders_by_insertion() {
+        let cache = ShardedLruCache::from_entries(3, 1, [(1, 1), (2, 2), (3, 3), (4, 4)]);
+        assert_eq!(cache.debug_order(), vec![4, 3, 2]);
+        let cache: ShardedLruCache<_, _> = (0..5).map(|k| (k, k * 10)).collect();
+        assert_eq!(cache.total_capacity(), Entries(5));
+        assert_eq!(cache.debug_order(), vec![4, 3, 2, 1, 0]);
+        assert_eq!(cache.get(&2), Some(20));
+    }
+
+    #[test]
+    fn extend_groups_items_by_shard() {
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(4096, 8);
+        cache.extend((0..10_000).map(|k| (k, k)));
+        assert_eq!(cache.len(), 4096);
+        cache.validate().unwrap();
+        assert_eq!(cache.get(&9_999), Some(9_999));
+
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(64, 4);
+        cache.extend([(1, 1), (2, 2), (1, 10)]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&1), Some(10));
+    }
+
+    #[test]
+    fn put_if_absent_keeps_the_cached_value() {
+        let cache = ShardedLruCache::new(2, 1);
+        assert_eq!(cache.put_if_absent(1, 10), None);
+        cache.put(2, 20);
+        assert_eq!(cache.put_if_absent(1, 11), Some(10));
+        assert_eq!(cache.get(&1), Some(10));
+        // Key 1 was marked most recently used, so key 2 makes way.
+        assert_eq!(cache.put_if_absent(3, 30), None);
+        assert_eq!(cache.keys().len(), 2);
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn get_many_matches_single_gets() {
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(64, 4);
+        cache.extend((0..32).map(|k| (k, k * 10)));
+        let keys = [31, 100, 0, 7, 7, 200, 16];
+        let batch = cache.get_many(&keys);
+        let single: Vec<_> = keys.iter().map(|k| cache.get(k)).collect();
+        assert_eq!(batch, single);
+        assert_eq!(batch[..3], [Some(310), None, Some(0)]);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (10, 4));
+
+        let cache = ShardedLruCache::from_entries(3, 1, (0..3).map(|k| (k, k)));
+        cache.get_many(&[0, 1]);
+        cache.put(3, 3);
+        assert_eq!(cache.get(&2), None, "the key left out of the batch aged");
+    }
+
+    #[test]
+    fn drain_empties_in_mru_order() {
+        let cache = ShardedLruCache::from_entries(4, 1, (0..4).map(|k| (k, k * 10)));
+        cache.get(&1);
+        assert_eq!(cache.drain(), vec![(1, 10), (3, 30), (2, 20), (0, 0)]);
+        assert!(cache.is_empty());
+        assert_eq!(cache.lock_shard(0).current_weight, 0);
+
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(64, 4);
+        cache.extend((0..100).map(|k| (k, k)));
+        let len = cache.len();
+        let mut drained = cache.drain();
+        assert_eq!(drained.len(), len);
+        assert!(cache.is_empty());
+        cache.validate().unwrap();
+        drained.sort();
+        drained.dedup();
+        assert_eq!(drained.len(), len);
+    }
+
+    #[test]
+    fn retain_keeps_order_of_survivors() {
+        let cache = ShardedLruCache::from_entries(8, 1, (0..8).map(|k| (k, k * 10)));
+        cache.get(&3);
+        cache.retain(|k, v| k % 2 == 0 || *v == 30);
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.debug_order(), vec![3, 6, 4, 2, 0]);
+        cache.validate().unwrap();
+
+        // Every shard could hold all 200 keys, so random routing never
+        // evicts.
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(800, 4);
+        cache.extend((0..200).map(|k| (k, k)));
+        cache.retain(|k, _| k % 2 == 0);
+        assert_eq!(cache.len(), 100);
+        assert!(cache.keys().iter().all(|k| k % 2 == 0));
+        cache.validate().unwrap();
+    }
+
+    #[test]
+    fn shard_count_is_clamped() {
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(3, 16);
+        assert_eq!(cache.shard_count(), 3);
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(3, 0);
+        assert_eq!(cache.shard_count(), 1);
+    }
+
+    #[test]
+    fn per_shard_len_shows_keys_piling_into_one_shard() {
+        let cache = ShardedLruCache::with_hasher(
+            16,
+            4,
+            std::hash::BuildHasherDefault::<IdentityHasher>::default(),
+        );
+        assert_eq!(cache.per_shard_capacity(), [Entries(4); 4]);
+        // Multiples of 4 all route to shard 0.
+        for k in (0..40u32).step_by(4) {
+            cache.put(k, k);
+        }
+        cache.put(1, 1);
+        assert_eq!(cache.per_shard_len(), [4, 1, 0, 0]);
+        assert_eq!(cache.len(), 5);
+        let summed: usize = cache
+            .per_shard_capacity()
+            .into_iter()
+            .map(usize::from)
+            .sum();
+        assert_eq!(Entries(summed), cache.total_capacity());
+    }
+
+    #[test]
+    fn pow2_shard_count_rounds_up_within_capacity() {
+        assert_eq!(pow2_shard_count(1024, 0), 1);
+        assert_eq!(pow2_shard_count(1024, 5), 8);
+        assert_eq!(pow2_shard_count(1024, 8), 8);
+        assert_eq!(pow2_shard_count(12, 9), 8, "16 shards exceed the capacity");
+        assert_eq!(pow2_shard_count(1, 64), 1);
+        assert_eq!(pow2_shard_count(100, usize::MAX), 64);
+    }
+
+    #[test]
+    fn pow2_shards_route_by_mask() {
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new_pow2(64, 5);
+        assert_eq!(cache.shards.len(), 8);
+        assert_eq!(cache.mask, Some(7));
+        for k in 0..200u32 {
+            let idx = cache.shard_index(&k);
+            assert_eq!(idx, cache.hasher.hash_one(k) as usize % 8);
+            cache.put(k, k);
+        }
+        assert!(cache.len() <= 64);
+        assert!(cache.validate().is_ok());
+        let odd: ShardedLruCache<u32, u32> = ShardedLruCache::new(64, 6);
+        assert_eq!(odd.mask, None);
+    }
+
+    #[test]
+    fn clock_gives_referenced_entries_a_second_chance() {
+        // Key 1 is read before 2 and 3 arrive: least recently used, but
+        // still referenced.
+        let run = |policy| {
+            let cache = ShardedLruCache::with_policy(3, 1, policy);
+            cache.put(1, 1);
+            cache.get(&1);
+            cache.put(2, 2);
+            cache.put(3, 3);
+            let evicted = cache.put(4, 4).evicted;
+            (evicted, cache)
+        };
+        let (evicted, _) = run(EvictionPolicy::Lru);
+        assert_eq!(evicted, Some((1, 1)));
+        let (evicted, clock) = run(EvictionPolicy::Clock);
+        assert_eq!(evicted, Some((2, 2)));
+        assert_eq!(clock.get(&1), Some(1));
+        // 1 went back to the head with its mark cleared, then 4 went in
+        // ahead of it.
+        assert_eq!(clock.debug_order(), vec![4, 1, 3]);
+        assert!(clock.validate().is_ok());
+    }
+
+    #[test]
+    fn clock_hits_leave_the_list_alone() {
+        let cache = ShardedLruCache::with_policy(4, 1, EvictionPolicy::Clock);
+        for k in 1..=4 {
+            cache.put(k, k);
+        }
+        for k in [1, 2, 1, 3] {
+            cache.get(&k);
+        }
+        cache.put(2, 20);
+        assert_eq!(cache.debug_order(), vec![4, 3, 2, 1]);
+        // Every entry but 4 is referenced, so 4 goes first.
+        assert_eq!(cache.put(5, 5).evicted, Some((4, 4)));
+        // Then the sweep has cleared them all and takes the oldest.
+        assert_eq!(cache.put(6, 6).evicted, Some((1, 1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "total_capacity must be non-zero")]
+    fn zero_capacity_panics() {
+        let _ = ShardedLruCache::<u32, u32>::new(0, 1);
+    }
+
+    #[test]
+    fn get_and_put() {
+        let cache = ShardedLruCache::new(4, 2);
+        assert_eq!(cache.get(&1), None);
+        cache.put(1, "one");
+        cache.put(2, "two");
+        assert_eq!(cache.get(&1), Some("one"));
+        assert_eq!(cache.get(&2), Some("two"));
+        cache.put(1, "uno");
+        assert_eq!(cache.get(&1), Some("uno"));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let cache = ShardedLruCache::new(4, 1);
+        for k in 1..=4 {
+            cache.put(k, k * 10);
+        }
+        cache.get(&1);
+        let order = cache.debug_order();
+        assert_eq!(order, [1, 4, 3, 2]);
+
+        let copy = cache.clone();
+        assert_eq!(copy.debug_order(), order);
+        assert_eq!(copy.per_shard_capacity(), cache.per_shard_capacity());
+        assert_eq!(copy.stats(), cache.stats());
+        copy.put(5, 50);
+        copy.put(1, 11);
+        copy.get(&3);
+        copy.resize(Entries(8));
+
+        assert_eq!(cache.debug_order(), order);
+        assert_eq!(cache.get(&1), Some(10));
+        assert_eq!((cache.len(), cache.total_capacity()), (4, Entries(4)));
+        assert_eq!(copy.get(&1), Some(11));
+        assert_eq!(copy.approx_len(), copy.len());
+        copy.validate().unwrap();
+    }
+
+    #[test]
+    fn touch_rescues_from_eviction() {
+        let cache = ShardedLruCache::new(2, 1);
+        cache.put(1, "one");
+        cache.put(2, "two");
+        assert!(cache.touch(&1));
+        assert!(!cache.touch(&3));
+        assert_eq!(cache.debug_order(), [1, 2]);
+        assert_eq!(cache.put(3, "three").evicted, Some((2, "two")));
+        assert_eq!(cache.debug_order(), [3, 1]);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (0, 0));
+    }
+
+    #[test]
+    fn update_pair_sees_both_keys_at_once() {
+        let cache = ShardedLruCache::with_hasher(
+            8,
+            2,
+            std::hash::BuildHasherDefault::<IdentityHasher>::default(),
+        );
+        for k in 0..4u32 {
+            cache.put(k, 10);
+        }
+        // 0 and 2 share a shard, 3 is in the other one, 5 is not cached.
+        for (a, b) in [(0, 2), (3, 0)] {
+            let before = cache.modification_epoch();
+            cache.update_pair(&a, &b, |x, y| {
+                let (x, y) = (x.unwrap(), y.unwrap());
+                *x -= 4;
+                *y += 4;
+            });
+            assert!(cache.get_if_modified_since(&a, before).is_some());
+            assert!(cache.get_if_modified_since(&b, before).is_some());
+        }
+        assert_eq!(cache.iter().into_iter().map(|(_, v)| v).sum::<u32>(), 40);
+        assert_eq!(
+            (cache.get(&0), cache.get(&2), cache.get(&3)),
+            (Some(10), Some(14), Some(6))
+        );
+
+        let epoch = cache.modification_epoch();
+        cache.update_pair(&5, &7, |x, y| assert!(x.is_none() && y.is_none()));
+        assert_eq!(cache.modification_epoch(), epoch);
+        cache.update_pair(&1, &5, |x, y| {
+            assert_eq!(x.copied(), Some(10));
+            assert!(y.is_none());
+        });
+        assert_eq!(cache.peek_mru(), Some((1, 10)));
+    }
+
+    #[test]
+    #[should_panic(expected = "two distinct keys")]
+    fn update_pair_rejects_one_key_twice() {
+        ShardedLruCache::new(4, 1).update_pair(&1, &1, |_: Option<&mut u32>, _| {});
+    }
+
+    #[test]
+    fn read_needs_no_clone() {
+        struct Blob(Vec<u8>);
+
+        let cache = ShardedLruCache::new(2, 1);
+        cache.put(1, Blob(vec![1, 2, 3]));
+        cache.put(2, Blob(vec![4]));
+        assert_eq!(cache.read(&1, |b| b.0.len()), Some(3));
+        assert_eq!(cache.read(&3, |b| b.0.len()), None);
+        // The read made key 1 most recently used, so key 2 is evicted.
+        let evicted = cache.put(3, Blob(Vec::new())).evicted.map(|(k, _)| k);
+        assert_eq!(evicted, Some(2));
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.debug_order(), [3, 1]);
+    }
+
+    #[test]
+    fn keys_and_values_need_not_be_debug() {
+        #[derive(Clone, PartialEq, Eq, Hash)]
+        struct Opaque(u32);
+
+        let cache = ShardedLruCache::new(4, 2);
+        cache.put(Opaque(1), Opaque(10));
+        cache.put(Opaque(2), Opaque(20));
+        assert!(cache.get(&Opaque(1)) == Some(Opaque(10)));
+        assert_eq!(cache.debug_order().len(), 2);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let cache = ShardedLruCache::new(3, 1);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        cache.put(3, 3);
+        assert_eq!(cache.get(&1), Some(1));
+        cache.put(4, 4);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.debug_order(), vec![4, 1, 3]);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn put_returns_previous_value() {
+        let cache = ShardedLruCache::new(4, 2);
+        assert_eq!(cache.put(1, "a").previous, None);
+        assert_eq!(cache.put(1, "b").previous, Some("a"));
+        assert_eq!(cache.put(2, "c").previous, None);
+        assert_eq!(cache.get(&1), Some("b"));
+    }
+
+    #[test]
+    fn put_returns_evicted_entry() {
+        let cache = ShardedLruCache::new(2, 1);
+        cache.put(1, "a");
+        cache.put(2, "b");
+        cache.get(&1);
+        assert_eq!(
+            cache.put(3, "c"),
+            PutOutcome {
+                previous: None,
+                evicted: Some((2, "b")),
+            }
+        );
+        assert_eq!(
+            cache.put(1, "z"),
+            PutOutcome {
+                previous: Some("a"),
+                evicted: None,
+            }
+        );
+    }
+
+    #[test]
+    fn heavy_entry_evicts_several_light_ones() {
+        let cache = ShardedLruCache::with_capacity(Weight(10), 1);
+        for k in 1..=5 {
+            cache.put_weighted(k, k * 10, Weight(2));
+        }
+        assert_eq!(cache.weight(), Weight(10));
+        cache.get(&1);
+        let outcome = cache.put_weighted(6, 60, Weight(5));
+        assert_eq!(outcome.previous, None);
+        assert_eq!(outcome.evicted, [(2, 20), (3, 30), (4, 40)]);
+        assert_eq!(cache.debug_order(), [6, 1, 5]);
+        assert_eq!(cache.weight(), Weight(9));
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.occupancy_stats().evictions, 3);
+        cache.validate().unwrap();
+    }
+
+    #[test]
+    fn growing_an_entry_evicts_others_and_oversized_ones_are_refused() {
+        let cache = ShardedLruCache::with_capacity(Weight(6), 1);
+        for k in 1..=3 {
+            cache.put_weighted(k, k * 10, Weight(2));
+        }
+        let outcome = cache.put_weighted(3, 31, Weight(4));
+        assert_eq!(outcome.previous, Some(30));
+        assert_eq!(outcome.evicted, [(1, 10)]);
+        assert_eq!(cache.weight(), Weight(6));
+
+        let outcome = cache.put_weighted(3, 32, Weight(7));
+        assert_eq!(outcome.previous, Some(31));
+        assert_eq!(outcome.evicted, [(3, 32)]);
+        assert_eq!(cache.debug_order(), [2]);
+
+        // Plain puts weigh 1, so one eviction always makes room.
+        cache.put_weighted(4, 40, Weight(4));
+        assert_eq!(cache.put(5, 50).evicted, Some((2, 20)));
+        assert_eq!(cache.weight(), Weight(5));
+        cache.resize(Weight(4));
+        assert_eq!(cache.debug_order(), [5]);
+        cache.validate().unwrap();
+    }
+
+    #[test]
+    fn capacity_evictions_outside_inserts_count_as_evictions() {
+        let cache = ShardedLruCache::with_capacity(Weight(6), 1);
+        for k in 1..=3 {
+            cache.put_weighted(k, k * 10, Weight(2));
+        }
+        let occupancy = |cache: &ShardedLruCache<u32, u32, DefaultHashBuilder, Weight>| {
+            let stats = cache.occupancy_stats();
+            (stats.insertions, stats.evictions, cache.approx_len())
+        };
+        assert_eq!(occupancy(&cache), (3, 0, 3));
+        // Growing 3 pushes 1 out; the update is not an insertion.
+        assert_eq!(cache.put_weighted(3, 31, Weight(4)).evicted, [(1, 10)]);
+        assert_eq!(occupancy(&cache), (3, 1, 2));
+        cache.resize(Weight(4));
+        assert_eq!(occupancy(&cache), (3, 2, 1));
+        assert_eq!(cache.evict_global(), Some((3, 31)));
+        assert_eq!(occupancy(&cache), (3, 3, 0));
+        cache.rotate_occupancy_window();
+        assert_eq!(cache.occupancy_stats().churn_rate, 1.0);
+    }
+
+    #[test]
+    fn on_evict_sees_every_capacity_eviction() {
+        let evicted: Arc<Mutex<Vec<u32>>> = Arc::default();
+        let log = Arc::clone(&evicted);
+        let mut cache = ShardedLruCache::new(4, 2);
+        cache.on_evict(move |k, v| {
+            assert_eq!(*v, k * 10);
+            log.lock().unwrap().push(*k);
+        });
+        let mut expected = Vec::new();
+        for k in 0..20 {
+            if let Some((key, _)) = cache.put(k, k * 10).evicted {
+                expected.push(key);
+            }
+        }
+        let keys = cache.keys();
+        cache.resize(Entries(2));
+        let kept = cache.keys();
+        expected.extend(keys.into_iter().rev().filter(|k| !kept.contains(k)));
+        cache.clear();
+        assert_eq!(expected.len(), 18);
+        let mut seen = evicted.lock().unwrap().clone();
+        assert_eq!(seen[..16], expected[..16], "puts report in order");
+        seen[16..].sort_unstable();
+        expected[16..].sort_unstable();
+        assert_eq!(seen, expected, "clear is not reported");
+    }
+
+    #[test]
+    #[should_panic(expected = "weight must be non-zero")]
+    fn zero_weight_panics() {
+        ShardedLruCache::with_capacity(Weight(4), 1).put_weighted(1, 1, Weight(0));
+    }
+
+    #[test]
+    fn capacities_report_the_unit_they_were_built_with() {
+        let entries = ShardedLruCache::with_capacity(Entries(8), 2);
+        assert_eq!(entries.total_capacity(), Entries(8));
+        assert_eq!(entries.per_shard_capacity(), [Entries(4); 2]);
+        for k in 0..8u32 {
+            entries.put(k, k);
+        }
+        let len = entries.len();
+        assert_eq!(entries.occupancy_stats().high_water_mark, Entries(len));
+
+        let weighted = ShardedLruCache::with_capacity(Weight(12), 1);
+        weighted.put_weighted(1, 10, Weight(5));
+        weighted.put_weighted(2, 20, Weight(5));
+        weighted.put(3, 30);
+        assert_eq!(weighted.total_capacity(), Weight(12));
+        assert_eq!(weighted.weight(), Weight(11));
+        // The watermark counts entries, not their weight.
+        assert_eq!(weighted.occupancy_stats().high_water_mark, Entries(3));
+        weighted.resize(Weight(6));
+        assert_eq!(weighted.per_shard_capacity(), [Weight(6)]);
+        assert_eq!(weighted.debug_order(), [3, 2]);
+        assert_eq!(weighted.weight(), Weight(6));
+    }
+
+    #[test]
+    fn get_or_insert_with_runs_closure_only_on_miss() {
+        let cache = ShardedLruCache::new(2, 1);
+        assert_eq!(cache.get_or_insert_with(1, || "a"), "a");
+        assert_eq!(cache.get_or_insert_with(1, || unreachable!()), "a");
+        cache.put(2, "b");
+        // The hit moved 1 to the front, so inserting 3 evicts 2.
+        assert_eq!(cache.get_or_insert_with(1, || unreachable!()), "a");
+        assert_eq!(cache.get_or_insert_with(3, || "c"), "c");
+        assert_eq!(cache.debug_order(), vec![3, 1]);
+    }
+
+    #[test]
+    fn stats_count_hits_and_misses() {
+        let cache = ShardedLruCache::new(4, 2);
+        assert_eq!(cache.stats().hit_ratio, 0.0);
+        cache.get(&1);
+        cache.put(1, 1);
+        cache.get(&1);
+        cache.get(&1);
+        assert_eq!(cache.try_get(&2), Ok(None));
+        cache.get_or_insert_with(1, || unreachable!());
+        cache.get_or_insert_with(3, || 3);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (3, 3));
+        assert_eq!(stats.hit_ratio, 0.5);
+
+        cache.reset_stats();
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 0,
+                misses: 0,
+                hit_ratio: 0.0
+            }
+        );
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn occupancy_tracks_fill_and_churn() {
+        let cache = ShardedLruCache::new(4, 1);
+        for k in 0..4 {
+            cache.put(k, k);
+        }
+        cache.put(0, 10);
+        cache.rotate_occupancy_window();
+        let stats = cache.occupancy_stats();
+        assert_eq!(stats.high_water_mark, Entries(4));
+        assert_eq!(stats.churn_rate, 0.0);
+
+        for k in 4..10 {
+            cache.put(k, k);
+        }
+        cache.get_or_insert_with(10, || 10);
+        cache.rotate_occupancy_window();
+        let stats = cache.occupancy_stats();
+        assert_eq!((stats.insertions, stats.evictions), (11, 7));
+        assert_eq!(stats.churn_rate, 1.0);
+        assert_eq!(stats.high_water_mark, Entries(4));
+
+        cache.reset_stats();
+        assert_eq!(cache.occupancy_stats().insertions, 11, "reset separately");
+        cache.reset_occupancy_stats();
+        let stats = cache.occupancy_stats();
+        assert_eq!((stats.high_water_mark, stats.insertions), (Entries(4), 0));
+    }
+
+    #[test]
+    fn clear_empties_every_shard() {
+        let cache = ShardedLruCache::new(32, 4);
+        for k in 0..32u32 {
+            cache.put(k, k);
+        }
+        let filled = cache.len();
+        let seen = cache.modification_epoch();
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert!(cache.debug_order().is_empty());
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get_if_modified_since(&1, seen), Some(None));
+        assert!(cache.validate().is_ok());
+        assert_eq!(cache.occupancy_stats().high_water_mark, Entries(filled));
+        cache.put(1, 1);
+        assert_eq!(cache.debug_order(), vec![1]);
+    }
+
+    #[test]
+    fn resize_grows_without_evicting() {
+        let cache = ShardedLruCache::new(8, 2);
+        for k in 0..8u32 {
+            cache.put(k, k);
+        }
+        let kept = cache.len();
+        cache.resize(Entries(16));
+        assert_eq!(cache.total_capacity(), Entries(16));
+        assert_eq!(cache.len(), kept);
+        for k in 8..16u32 {
+            cache.put(k, k);
+        }
+        assert!(cache.len() > kept);
+        assert!(cache.validate().is_ok());
+    }
+
+    #[test]
+    fn resize_shrinks_by_evicting_least_recent() {
+        let cache = ShardedLruCache::new(8, 1);
+        for k in 0..8u32 {
+            cache.put(k, k);
+        }
+        cache.get(&0);
+        let seen = cache.modification_epoch();
+        cache.resize(Entries(3));
+        assert_eq!(cache.total_capacity(), Entries(3));
+        assert_eq!(cache.debug_order(), vec![0, 7, 6]);
+        assert!(cache.modification_epoch() > seen);
+        assert!(cache.validate().is_ok());
+
+        let cache = ShardedLruCache::new(64, 4);
+        for k in 0..64u32 {
+            cache.put(k, k);
+        }
+        cache.resize(Entries(10));
+        assert!(Entries(cache.len()) <= cache.total_capacity());
+        assert!(cache.validate().is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "at least the shard count")]
+    fn resize_below_shard_count_panics() {
+        ShardedLruCache::<u32, u32>::new(8, 4).resize(Entries(3));
+    }
+
+    #[test]
+    fn try_get_matches_get_when_uncontended() {
+        let cache = ShardedLruCache::new(2, 1);
+        assert_eq!(cache.try_get(&1), Ok(None));
+        cache.put(1, "a");
+        cache.put(2, "b");
+        assert_eq!(cache.try_get(&1), Ok(Some("a")));
+        // The hit counted as a use, so 2 is evicted.
+        cache.put(3, "c");
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn try_get_fails_on_locked_shard() {
+        let cache = ShardedLruCache::new(4, 1);
+        cache.put(1, "a");
+        let guard = cache.lock_shard(0);
+        assert_eq!(cache.try_get(&1), Err(TryGetError::WouldBlock));
+        drop(guard);
+        assert_eq!(cache.try_get(&1), Ok(Some("a")));
+    }
+
+    #[test]
+    fn epoch_advances_only_on_writes() {
+        let cache = ShardedLruCache::new(2, 1);
+        assert_eq!(cache.modification_epoch(), 0);
+        cache.put(1, 1);
+        let e1 = cache.modification_epoch();
+        cache.get(&1);
+        cache.get(&2);
+        assert_eq!(cache.modification_epoch(), e1);
+        cache.put(1, 2);
+        let e2 = cache.modification_epoch();
+        assert!(e2 > e1);
+        cache.put(2, 2);
+        cache.put(3, 3);
+        assert!(cache.modification_epoch() > e2);
+        assert_eq!(cache.shard_epoch(0), cache.modification_epoch());
+    }
+
+    #[test]
+    fn shard_epochs_track_their_own_shard() {
+        let cache = ShardedLruCache::new(64, 4);
+        let key = 7u32;
+        let idx = cache.shard_index(&key);
+        cache.put(key, 0);
+        let before: Vec<_> = (0..4).map(|i| cache.shard_epoch(i)).collect();
+        cache.put(key, 1);
+        for (i, &old) in before.iter().enumerate() {
+            let now = cache.shard_epoch(i);
+            if i == idx {
+                assert!(now > old);
+            } else {
+                assert_eq!(now, old);
+            }
+        }
+    }
+
+    #[test]
+    fn get_if_modified_since_reports_changes() {
+        let cache = ShardedLruCache::new(2, 1);
+        cache.put(1, "a");
+        let seen = cache.modification_epoch();
+        assert_eq!(cache.get_if_modified_since(&1, seen), None);
+        assert_eq!(cache.get_if_modified_since(&1, 0), Some(Some("a")));
+
+        cache.put(1, "b");
+        assert_eq!(cache.get_if_modified_since(&1, seen), Some(Some("b")));
+        let seen = cache.modification_epoch();
+        cache.put(2, "c");
+        // 1 itself was not rewritten.
+        assert_eq!(cache.get_if_modified_since(&1, seen), None);
+        // Evicting 2 is a change to an absent key.
+        cache.get(&1);
+        let seen = cache.modification_epoch();
+        cache.put(3, "d");
+        assert_eq!(cache.get_if_modified_since(&2, seen), Some(None));
+    }
+
+    const TTL: Duration = Duration::from_millis(20);
+
+    /// Points `cache`'s time source at a clock that only moves when the
+    /// returned closure advances it.
+    fn manual_clock<K, V, S>(cache: &mut ShardedLruCache<K, V, S>) -> impl Fn(Duration)
+    where
+        K: Eq + Hash + Clone,
+        S: BuildHasher,
+    {
+        let start = Instant::now();
+        let elapsed = Arc::new(AtomicU64::new(0));
+        let clock = Arc::clone(&elapsed);
+        cache.set_time_source(move || start + Duration::from_nanos(clock.load(Ordering::Relaxed)));
+        move |by| {
+            elapsed.fetch_add(by.as_nanos() as u64, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn ttl_entries_expire_on_lookup() {
+        let mut cache = ShardedLruCache::new(8, 2);
+        let advance = manual_clock(&mut cache);
+        cache.put_with_ttl(1, "a", TTL);
+        cache.put(2, "b");
+        advance(TTL - Duration::from_nanos(1));
+        assert_eq!(cache.get(&1), Some("a"));
+        advance(Duration::from_nanos(1));
+        assert_eq!(cache.len(), 2, "expiry is lazy");
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2), Some("b"));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.occupancy_stats().high_water_mark, Entries(2));
+    }
+
+    #[test]
+    fn expired_entries_are_replaced_as_new() {
+        let mut cache = ShardedLruCache::new(8, 2);
+        let advance = manual_clock(&mut cache);
+        cache.put_with_ttl(1, "a", TTL);
+        advance(TTL);
+        assert_eq!(cache.put(1, "b").previous, None);
+        advance(TTL);
+        // A plain put drops the TTL.
+        assert_eq!(cache.get(&1), Some("b"));
+        assert_eq!(cache.put_with_ttl(1, "c", TTL).previous, Some("b"));
+        assert_eq!(cache.get(&1), Some("c"));
+    }
+
+    #[test]
+    fn purge_expired_drops_only_expired_entries() {
+        let mut cache = ShardedLruCache::new(64, 4);
+        let advance = manual_clock(&mut cache);
+        for k in 0..8u32 {
+            if k % 2 == 0 {
+                cache.put_with_ttl(k, k, TTL);
+            } else {
+                cache.put(k, k);
+            }
+        }
+        cache.put_with_ttl(100, 100, Duration::from_secs(3600));
+        assert_eq!(cache.purge_expired(), 0);
+        advance(TTL);
+        assert_eq!(cache.purge_expired(), 4);
+        let mut keys = cache.keys();
+        keys.sort_unstable();
+        assert_eq!(keys, [1, 3, 5, 7, 100]);
+        assert!(cache.validate().is_ok());
+    }
+
+    #[test]
+    fn reads_reclaim_an_expired_tail() {
+        let mut cache = ShardedLruCache::new(8, 1);
+        let advance = manual_clock(&mut cache);
+        for k in 0..3u32 {
+            cache.put_with_ttl(k, k, TTL);
+        }
+        cache.put(10, 10);
+        advance(TTL);
+        // Each read of the live key takes one expired entry off the tail.
+        for left in [3, 2, 1] {
+            assert_eq!(cache.len(), left + 1);
+            assert_eq!(cache.get(&10), Some(10));
+        }
+        assert_eq!(cache.keys(), [10]);
+        assert_eq!(cache.approx_len(), 1);
+        assert_eq!(cache.purge_expired(), 0);
+
+        cache.put_with_ttl(11, 11, TTL);
+        cache.get(&10);
+        advance(TTL);
+        cache.put(12, 12);
+        assert_eq!(cache.keys(), [12, 10], "puts clean the tail too");
+    }
+
+    #[test]
+    fn tail_cleanup_stops_at_its_budget_and_at_live_entries() {
+        let mut cache = ShardedLruCache::new(16, 1);
+        let advance = manual_clock(&mut cache);
+        cache.set_tail_cleanup(2);
+        for k in 0..5u32 {
+            cache.put_with_ttl(k, k, TTL);
+        }
+        cache.put(10, 10);
+        advance(TTL);
+        cache.get(&10);
+        assert_eq!(cache.keys(), [10, 4, 3, 2]);
+        cache.get(&10);
+        assert_eq!(cache.keys(), [10, 4]);
+
+        // A live tail ends the check, whatever expired above it.
+        let mut cache = ShardedLruCache::new(16, 1);
+        let advance = manual_clock(&mut cache);
+        cache.set_tail_cleanup(2);
+        cache.put(20, 20);
+        cache.put_with_ttl(21, 21, TTL);
+        advance(TTL);
+        let seen = cache.modification_epoch();
+        assert_eq!(cache.get(&99), None);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.modification_epoch(), seen);
+    }
+
+    #[test]
+    fn zero_tail_cleanup_leaves_expired_entries() {
+        let mut cache = ShardedLruCache::new(8, 1);
+        let advance = manual_clock(&mut cache);
+        cache.set_tail_cleanup(0);
+        for k in 0..3u32 {
+            cache.put_with_ttl(k, k, TTL);
+        }
+        cache.put(10, 10);
+        advance(TTL);
+        for _ in 0..5 {
+            cache.get(&10);
+        }
+        cache.put(11, 11);
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.purge_expired(), 3);
+    }
+
+    #[test]
+    fn expiry_counts_as_a_change() {
+        let mut cache = ShardedLruCache::new(4, 1);
+        let advance = manual_clock(&mut cache);
+        cache.put_with_ttl(1, "a", TTL);
+        let seen = cache.modification_epoch();
+        assert_eq!(cache.get_if_modified_since(&1, seen), None);
+        advance(TTL);
+        assert_eq!(cache.get_if_modified_since(&1, seen), Some(None));
+        assert!(cache.modification_epoch() > seen);
+    }
+
+    #[test]
+    fn iter_follows_debug_order_without_touching() {
+        let cache = ShardedLruCache::new(16, 4);
+        for k in 0..12u32 {
+            cache.put(k, k * 10);
+        }
+        let order = cache.debug_order();
+        let entries = cache.iter();
+        assert_eq!(entries.iter().map(|&(k, _)| k).collect::<Vec<_>>(), order);
+        assert!(entries.iter().all(|&(k, v)| v == k * 10));
+        assert_eq!(cache.debug_order(), order);
+    }
+
+    #[test]
+    fn keys_and_values_match_debug_order() {
+        let cache = ShardedLruCache::new(16, 4);
+        for k in 0..12u32 {
+            cache.put(k, k + 100);
+        }
+        cache.get(&5);
+        let keys = cache.keys();
+        assert_eq!(keys, cache.debug_order());
+        let values = cache.values();
+        assert_eq!(values, keys.iter().map(|k| k + 100).collect::<Vec<_>>());
+        assert_eq!(cache.debug_order(), keys, "helpers do not reorder");
+    }
+
+    #[test]
+    fn update_moves_to_front() {
+        let cache = ShardedLruCache::new(3, 1);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        cache.put(3, 3);
+        cache.put(1, 10);
+        assert_eq!(cache.debug_order(), vec![1, 3, 2]);
+        cache.put(4, 4);
+        assert_eq!(cache.debug_order(), vec![4, 1, 3]);
+    }
+
+    /// Key whose hash depends on shared mutable state that `Eq` ignores.
+    #[derive(Clone, Debug)]
+    struct DriftingKey {
+        id: u32,
+        salt: Arc<AtomicU32>,
+    }
+
+    impl DriftingKey {
+        fn new(id: u32) -> Self {
+            Self {
+                id,
+                salt: Arc::new(AtomicU32::new(0)),
+            }
+        }
+    }
+
+    impl PartialEq for DriftingKey {
+        fn eq(&self, other: &Self) -> bool {
+            self.id == other.id
+        }
+    }
+
+    impl Eq for DriftingKey {}
+
+    impl Hash for DriftingKey {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.id.hash(state);
+            self.salt.load(Ordering::Relaxed).hash(state);
+        }
+    }
+
+    /// Hashes an integer key to itself.
+    #[derive(Default)]
+    struct IdentityHasher(u64);
+
+    impl Hasher for IdentityHasher {
+        fn finish(&self) -> u64 {
+            self.0
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            for &b in bytes {
+                self.0 = (self.0 << 8) | u64::from(b);
+            }
+        }
+
+        fn write_u32(&mut self, n: u32) {
+            self.0 = n.into();
+        }
+    }
+
+    #[test]
+    fn with_hasher_routes_keys_by_it() {
+        let cache = ShardedLruCache::with_hasher(
+            16,
+            4,
+            std::hash::BuildHasherDefault::<IdentityHasher>::default(),
+        );
+        for k in 0..16u32 {
+            assert_eq!(cache.shard_index(&k), k as usize % 4);
+            cache.put(k, k);
+        }
+        // Every shard got exactly its share, so nothing was evicted.
+        assert_eq!(cache.len(), 16);
+        let order = cache.debug_order();
+        assert_eq!(&order[..4], [12, 8, 4, 0]);
+        assert!(cache.check_hash_consistency().is_empty());
+    }
+
+    #[test]
+    fn consistent_keys_pass_hash_check() {
+        let cache = ShardedLruCache::new(64, 4);
+        for id in 0..32 {
+            cache.put(DriftingKey::new(id), id);
+        }
+        assert!(cache.check_hash_consistency().is_empty());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn check_hash_consistency_reports_drifted_keys() {
+        let cache = ShardedLruCache::new(64, 4);
+        let keys: Vec<_> = (0..32).map(DriftingKey::new).collect();
+        for k in &keys {
+            cache.put(k.clone(), k.id);
+        }
+        keys[3].salt.store(7, Ordering::Relaxed);
+        keys[20].salt.store(9, Ordering::Relaxed);
+        let mut stale: Vec<u32> = cache
+            .check_hash_consistency()
+            .into_iter()
+            .map(|k| k.id)
+            .collect();
+        stale.sort_unstable();
+        assert_eq!(stale, vec![3, 20]);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "shard 0: key hash changed since insertion")]
+    fn drifted_key_panics_on_access() {
+        let cache = ShardedLruCache::new(8, 1);
+        let key = DriftingKey::new(1);
+        cache.put(key.clone(), 1);
+        cache.put(DriftingKey::new(2), 2);
+        key.salt.store(5, Ordering::Relaxed);
+        // A fresh key with the original salt still finds the entry, whose
+        // stored key now hashes differently.
+        cache.get(&DriftingKey::new(1));
+    }
+
+    impl<K: Eq + Hash + Clone, V> Shard<K, V> {
+        /// Test-only: breaks the list the way a bug would, by swapping the
+        /// endpoints and dropping the head's forward link.
+        fn corrupt_links(&mut self) {
+            std::mem::swap(&mut self.head, &mut self.tail);
+            let head = self.head.clone().unwrap();
+            self.map.get_mut(&head).unwrap().next = None;
+        }
+    }
+
+    #[test]
+    fn validate_accepts_normal_use() {
+        let cache = ShardedLruCache::new(32, 4);
+        assert_eq!(cache.validate(), Ok(()));
+        for k in 0..100 {
+            cache.put(k, k);
+            cache.get(&(k / 2));
+        }
+        assert_eq!(cache.validate(), Ok(()));
+    }
+
+    #[test]
+    fn rebuild_restores_corrupted_shard() {
+        let cache = ShardedLruCache::new(8, 1);
+        for k in 0..8 {
+            cache.put(k, k * 10);
+        }
+        cache.get(&3);
+        let order = cache.debug_order();
+        cache.lock_shard(0).corrupt_links();
+        let err = cache.validate().unwrap_err();
+        assert_eq!(err.shard, 0);
+
+        let report = cache.rebuild_shard(0);
+        assert_eq!(report.entries, 8);
+        assert!(report.links_repaired > 0);
+        assert_eq!(cache.validate(), Ok(()));
+        assert_eq!(cache.debug_order(), order);
+        assert!((0..8).all(|k| cache.get(&k) == Some(k * 10)));
+    }
+
+    #[test]
+    fn rebuild_of_healthy_shard_changes_nothing() {
+        let cache = ShardedLruCache::new(16, 2);
+        for k in 0..16 {
+            cache.put(k, k);
+        }
+        let order = cache.debug_order();
+        for idx in 0..2 {
+            assert_eq!(cache.rebuild_shard(idx).links_repaired, 0);
+        }
+        assert_eq!(cache.debug_order(), order);
+    }
+
+    #[test]
+    fn peek_on_empty_cache() {
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(8, 4);
+        assert_eq!(cache.peek_lru(), None);
+        assert_eq!(cache.peek_mru(), None);
+    }
+
+    #[test]
+    fn peek_does_not_reorder() {
+        let cache = ShardedLruCache::new(3, 1);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        cache.put(3, 3);
+        assert_eq!(cache.peek_lru(), Some((1, 1)));
+        assert_eq!(cache.peek_mru(), Some((3, 3)));
+        assert_eq!(cache.debug_order(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn peek_follows_access_order_across_shards() {
+        let cache = ShardedLruCache::new(256, 8);
+        for k in 0..32 {
+            cache.put(k, k * 10);
+        }
+        assert_eq!(cache.peek_lru(), Some((0, 0)));
+        assert_eq!(cache.peek_mru(), Some((31, 310)));
+        cache.get(&0);
+        assert_eq!(cache.peek_mru(), Some((0, 0)));
+        assert_eq!(cache.peek_lru(), Some((1, 10)));
+    }
+
+    #[test]
+    fn peek_lru_matches_next_eviction_in_its_shard() {
+        let cache = ShardedLruCache::new(16, 4);
+        for k in 0..64u32 {
+            cache.put(k, k);
+        }
+        for k in (0..64).step_by(3) {
+            cache.get(&k);
+        }
+        let (victim, _) = cache.peek_lru().unwrap();
+        let shard = cache.shard_index(&victim);
+        let filler = (1000u32..).find(|k| cache.shard_index(k) == shard).unwrap();
+        cache.put(filler, filler);
+        assert_eq!(cache.get(&victim), None);
+        assert_eq!(cache.get(&filler), Some(filler));
+    }
+
+    #[test]
+    fn evict_global_picks_the_oldest_tail_across_shards() {
+        let cache = ShardedLruCache::with_hasher(
+            4,
+            2,
+            std::hash::BuildHasherDefault::<IdentityHasher>::default(),
+        );
+        // Even keys live in shard 0, odd ones in shard 1.
+        for k in 0..4u32 {
+            cache.put(k, k);
+        }
+        cache.get(&0);
+        // Shard 0 would evict 2 for its next put, but 1 is older.
+        assert_eq!(cache.evict_global(), Some((1, 1)));
+        assert_eq!(cache.evict_global(), Some((2, 2)));
+        assert_eq!(cache.keys().len(), 2);
+        assert_eq!(cache.get(&3), Some(3));
+        assert_eq!(cache.evict_global(), Some((0, 0)));
+        assert_eq!(cache.evict_global(), Some((3, 3)));
+        assert_eq!(cache.evict_global(), None);
+        cache.validate().unwrap();
+    }
+}
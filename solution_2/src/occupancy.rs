@@ -0,0 +1,140 @@
+//! Occupancy high-water mark and churn counters for [`ShardedLruCache`].
+//!
+//! [`ShardedLruCache`]: crate::ShardedLruCache
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use crate::Entries;
+
+fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Occupancy and turnover figures, as returned by
+/// [`ShardedLruCache::occupancy_stats`](crate::ShardedLruCache::occupancy_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OccupancyStats {
+    /// Largest number of entries held at once, whatever their weight.
+    pub high_water_mark: Entries,
+    /// New keys stored.
+    pub insertions: u64,
+    /// Entries evicted to keep a shard within its capacity: by those
+    /// insertions, by updates that made an entry heavier, by
+    /// [`resize`](crate::ShardedLruCache::resize) and by
+    /// [`evict_global`](crate::ShardedLruCache::evict_global).
+    pub evictions: u64,
+    /// Evictions per insertion over the last completed window (see
+    /// [`rotate_occupancy_window`](crate::ShardedLruCache::rotate_occupancy_window)),
+    /// or 0 if it had no insertions.
+    pub churn_rate: f64,
+}
+
+/// Live occupancy counters. They are reset separately from the hit/miss
+/// counters, so a capacity-planning window can outlive several hit-rate
+/// windows.
+#[derive(Default)]
+pub(crate) struct Occupancy {
+    len: AtomicUsize,
+    high_water_mark: AtomicUsize,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
+    window_insertions: AtomicU64,
+    window_evictions: AtomicU64,
+    last_window_insertions: AtomicU64,
+    last_window_evictions: AtomicU64,
+}
+
+impl Occupancy {
+    /// Records a new key, which displaced `evicted` other entries.
+    pub(crate) fn inserted(&self, evicted: usize) {
+        bump(&self.insertions);
+        bump(&self.window_insertions);
+        if evicted == 0 {
+            let len = self.len.fetch_add(1, Ordering::Relaxed) + 1;
+            self.high_water_mark.fetch_max(len, Ordering::Relaxed);
+        } else {
+            let n = evicted as u64;
+            self.evictions.fetch_add(n, Ordering::Relaxed);
+            self.window_evictions.fetch_add(n, Ordering::Relaxed);
+            self.len.fetch_sub(evicted - 1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records `n` entries evicted for capacity outside an insertion.
+    pub(crate) fn evicted(&self, n: usize) {
+        let count = n as u64;
+        self.evictions.fetch_add(count, Ordering::Relaxed);
+        self.window_evictions.fetch_add(count, Ordering::Relaxed);
+        self.len.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    /// Records `n` entries leaving without being replaced.
+    pub(crate) fn dropped(&self, n: usize) {
+        self.len.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    /// Ends the current window: its counts become the ones `churn_rate` is
+    /// computed from, and a new window starts empty.
+    pub(crate) fn rotate(&self) {
+        let insertions = self.window_insertions.swap(0, Ordering::Relaxed);
+        let evictions = self.window_evictions.swap(0, Ordering::Relaxed);
+        self.last_window_insertions
+            .store(insertions, Ordering::Relaxed);
+        self.last_window_evictions
+            .store(evictions, Ordering::Relaxed);
+    }
+
+    /// Live entry count, without locking any shard.
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn snapshot(&self) -> OccupancyStats {
+        let window_insertions = self.last_window_insertions.load(Ordering::Relaxed);
+        let window_evictions = self.last_window_evictions.load(Ordering::Relaxed);
+        OccupancyStats {
+            high_water_mark: Entries(self.high_water_mark.load(Ordering::Relaxed)),
+            insertions: self.insertions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            churn_rate: if window_insertions == 0 {
+                0.0
+            } else {
+                window_evictions as f64 / window_insertions as f64
+            },
+        }
+    }
+
+    /// A copy of every counter, for a cache that holds `len` entries.
+    pub(crate) fn copy_with_len(&self, len: usize) -> Self {
+        let copy = |c: &AtomicU64| AtomicU64::new(c.load(Ordering::Relaxed));
+        Self {
+            len: AtomicUsize::new(len),
+            high_water_mark: AtomicUsize::new(
+                self.high_water_mark.load(Ordering::Relaxed).max(len),
+            ),
+            insertions: copy(&self.insertions),
+            evictions: copy(&self.evictions),
+            window_insertions: copy(&self.window_insertions),
+            window_evictions: copy(&self.window_evictions),
+            last_window_insertions: copy(&self.last_window_insertions),
+            last_window_evictions: copy(&self.last_window_evictions),
+        }
+    }
+
+    /// Zeroes everything except the live entry count; the high-water mark
+    /// restarts from the current occupancy.
+    pub(crate) fn reset(&self) {
+        for c in [
+            &self.insertions,
+            &self.evictions,
+            &self.window_insertions,
+            &self.window_evictions,
+            &self.last_window_insertions,
+            &self.last_window_evictions,
+        ] {
+            c.store(0, Ordering::Relaxed);
+        }
+        let len = self.len.load(Ordering::Relaxed);
+        self.high_water_mark.store(len, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,180 @@
+//! `serde` support for [`ShardedLruCache`], behind the `serde` feature.
+
+use std::cmp::Reverse;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{CapacityUnit, DistributionStrategy, EvictionPolicy, ShardedLruCache};
+
+/// The serialized form: the constructor's arguments, then every live entry
+/// as `(key, value, weight)` from most to least recently used.
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "ShardedLruCache")]
+struct Snapshot<K, V> {
+    total_capacity: usize,
+    shard_count: usize,
+    policy: EvictionPolicy,
+    /// Absent from snapshots written before distributions were
+    /// configurable, which were all even.
+    #[serde(default)]
+    distribution: DistributionStrategy,
+    entries: Vec<(K, V, usize)>,
+}
+
+/// Writes the capacity, shard count, policy, distribution and live
+/// entries. Entries are ordered by their last use across all shards, so the
+/// order survives even if the restored cache hashes keys onto different
+/// shards.
+///
+/// Shards are copied one at a time, so under concurrent writes the snapshot
+/// is not atomic. Expiry deadlines are not written: restored entries never
+/// expire, and ones already expired are left out.
+impl<K, V, S, U> Serialize for ShardedLruCache<K, V, S, U>
+where
+    K: Eq + Hash + Clone + Serialize,
+    V: Clone + Serialize,
+    S: BuildHasher,
+    U: CapacityUnit,
+{
+    fn serialize<Ser: Serializer>(&self, serializer: Ser) -> Result<Ser::Ok, Ser::Error> {
+        let now = self.now();
+        let mut entries = Vec::new();
+        let mut policy = EvictionPolicy::default();
+        for idx in 0..self.shards.len() {
+            let shard = self.lock_shard(idx);
+            policy = shard.policy;
+            entries.extend(
+                shard
+                    .map
+                    .iter()
+                    .filter(|(_, e)| e.expires.is_none_or(|deadline| deadline > now))
+                    .map(|(k, e)| (e.stamp, (k.clone(), e.value.clone(), e.weight))),
+            );
+        }
+        entries.sort_by_key(|&(stamp, _)| Reverse(stamp));
+        Snapshot {
+            total_capacity: self.total_capacity().into(),
+            shard_count: self.shards.len(),
+            policy,
+            distribution: self.distribution.clone(),
+            entries: entries.into_iter().map(|(_, entry)| entry).collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Rebuilds the cache with a fresh [`RandomState`] and puts the entries
+/// from least to most recently used, so the recency order is restored. A
+/// cache counting [`Entries`](crate::Entries) rejects entries weighing more
+/// than 1, which only a [`Weight`](crate::Weight) cache can hold.
+impl<'de, K, V, U> Deserialize<'de> for ShardedLruCache<K, V, RandomState, U>
+where
+    K: Eq + Hash + Clone + Deserialize<'de>,
+    V: Deserialize<'de>,
+    U: CapacityUnit,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = Snapshot::<K, V>::deserialize(deserializer)?;
+        if snapshot.entries.iter().any(|&(_, _, weight)| weight == 0) {
+            return Err(serde::de::Error::custom("weight must be non-zero"));
+        }
+        if !U::WEIGHTED && snapshot.entries.iter().any(|&(_, _, weight)| weight > 1) {
+            return Err(serde::de::Error::custom(
+                "weighted entries need a cache with a Weight capacity",
+            ));
+        }
+        let cache = Self::with_distribution(
+            U::from(snapshot.total_capacity),
+            snapshot.shard_count,
+            RandomState::new(),
+            snapshot.policy,
+            snapshot.distribution,
+        )
+        .map_err(serde::de::Error::custom)?;
+        for (key, value, weight) in snapshot.entries.into_iter().rev() {
+            cache.put_until(key, value, weight, None);
+        }
+        Ok(cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Entries, Weight};
+
+    #[test]
+    fn round_trip_keeps_recency_order() {
+        let cache: ShardedLruCache<u32, String> = ShardedLruCache::new(4, 1);
+        for k in 0..5 {
+            cache.put(k, k.to_string());
+        }
+        cache.get(&2);
+        let json = serde_json::to_string(&cache).unwrap();
+        let restored: ShardedLruCache<u32, String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.debug_order(), vec![2, 4, 3, 1]);
+        assert_eq!(restored.debug_order(), cache.debug_order());
+        assert_eq!(restored.get(&3).as_deref(), Some("3"));
+        assert_eq!(restored.total_capacity(), Entries(4));
+    }
+
+    #[test]
+    fn round_trip_keeps_shape_and_weights() {
+        let cache: ShardedLruCache<u32, u32, RandomState, Weight> =
+            ShardedLruCache::with_distribution(
+                Weight(64),
+                4,
+                RandomState::new(),
+                EvictionPolicy::Clock,
+                DistributionStrategy::Even,
+            )
+            .unwrap();
+        cache.extend((0..10).map(|k| (k, k)));
+        cache.put_weighted(100, 100, Weight(3));
+        let snapshot = serde_json::to_value(&cache).unwrap();
+        let restored: ShardedLruCache<u32, u32, RandomState, Weight> =
+            serde_json::from_value(snapshot.clone()).unwrap();
+        assert_eq!(restored.shards.len(), 4);
+        assert_eq!(restored.lock_shard(0).policy, EvictionPolicy::Clock);
+        assert_eq!(restored.len(), cache.len());
+        assert_eq!(restored.weight(), cache.weight());
+        let mut keys = restored.keys();
+        keys.sort();
+        let mut expected = cache.keys();
+        expected.sort();
+        assert_eq!(keys, expected);
+        restored.validate().unwrap();
+        let err = serde_json::from_value::<ShardedLruCache<u32, u32>>(snapshot).err();
+        assert!(err.unwrap().to_string().contains("Weight capacity"));
+    }
+
+    #[test]
+    fn round_trip_keeps_the_distribution() {
+        let weights = DistributionStrategy::Weighted(vec![3, 1]);
+        let cache: ShardedLruCache<u32, u32> = ShardedLruCache::with_distribution(
+            Entries(8),
+            2,
+            RandomState::new(),
+            EvictionPolicy::Lru,
+            weights.clone(),
+        )
+        .unwrap();
+        let restored: ShardedLruCache<u32, u32> =
+            serde_json::from_value(serde_json::to_value(&cache).unwrap()).unwrap();
+        assert_eq!(restored.distribution, weights);
+        assert_eq!(restored.per_shard_capacity(), [Entries(6), Entries(2)]);
+        let json = r#"{"total_capacity":4,"shard_count":2,"policy":"Lru",
+            "distribution":{"Weighted":[1,0]},"entries":[]}"#;
+        let err = serde_json::from_str::<ShardedLruCache<u32, u32>>(json).err();
+        assert!(err.unwrap().to_string().contains("weight zero"));
+    }
+
+    #[test]
+    fn zero_capacity_is_rejected() {
+        let json = r#"{"total_capacity":0,"shard_count":1,"policy":"Lru","entries":[]}"#;
+        let err = serde_json::from_str::<ShardedLruCache<u32, u32>>(json).err();
+        assert!(err.unwrap().to_string().contains("total_capacity"));
+    }
+}
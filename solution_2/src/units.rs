@@ -0,0 +1,93 @@
+//! Units a [`ShardedLruCache`] capacity is measured in.
+//!
+//! A cache counts either entries or summed weight, fixed by its type. The
+//! examples use the `std` constructors:
+//!
+#![cfg_attr(feature = "std", doc = "```")]
+#![cfg_attr(not(feature = "std"), doc = "```ignore")]
+//! use solution_2::{Entries, ShardedLruCache, Weight};
+//!
+//! let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(64, 4);
+//! cache.resize(Entries(128));
+//!
+//! let bytes = ShardedLruCache::with_capacity(Weight(1 << 20), 4);
+//! bytes.put_weighted(1, vec![0u8; 512], Weight(512));
+//! assert_eq!(bytes.weight(), Weight(512));
+//! ```
+//!
+//! so a byte budget cannot be handed to a cache that counts entries:
+//!
+#![cfg_attr(feature = "std", doc = "```compile_fail,E0308")]
+#![cfg_attr(not(feature = "std"), doc = "```ignore")]
+//! use solution_2::{ShardedLruCache, Weight};
+//!
+//! let cache: ShardedLruCache<u32, u32> = ShardedLruCache::new(64, 4);
+//! cache.resize(Weight(1 << 20));
+//! ```
+//!
+//! [`ShardedLruCache`]: crate::ShardedLruCache
+
+use core::fmt::Debug;
+
+/// A number of entries. Caches built with
+/// [`new`](crate::ShardedLruCache::new) and the other `usize` constructors
+/// count their capacity in entries, each taking up one slot.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Entries(pub usize);
+
+/// A summed weight. A cache built with
+/// [`with_capacity`](crate::ShardedLruCache::with_capacity) from a `Weight`
+/// bounds the total weight of its entries, each set by
+/// [`put_weighted`](crate::ShardedLruCache::put_weighted).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Weight(pub usize);
+
+impl From<usize> for Entries {
+    fn from(n: usize) -> Self {
+        Self(n)
+    }
+}
+
+impl From<Entries> for usize {
+    fn from(n: Entries) -> Self {
+        n.0
+    }
+}
+
+impl From<usize> for Weight {
+    fn from(n: usize) -> Self {
+        Self(n)
+    }
+}
+
+impl From<Weight> for usize {
+    fn from(n: Weight) -> Self {
+        n.0
+    }
+}
+
+mod sealed {
+    pub trait Sealed {
+        /// Whether entries can weigh more than one slot.
+        const WEIGHTED: bool;
+    }
+
+    impl Sealed for super::Entries {
+        const WEIGHTED: bool = false;
+    }
+
+    impl Sealed for super::Weight {
+        const WEIGHTED: bool = true;
+    }
+}
+
+/// The unit of a [`ShardedLruCache`](crate::ShardedLruCache)'s capacity:
+/// [`Entries`] or [`Weight`]. Sealed, as the cache only knows these two.
+pub trait CapacityUnit:
+    Copy + Debug + Ord + From<usize> + Into<usize> + Send + Sync + sealed::Sealed
+{
+}
+
+impl CapacityUnit for Entries {}
+
+impl CapacityUnit for Weight {}
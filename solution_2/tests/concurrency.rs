@@ -0,0 +1,269 @@
+#![cfg(feature = "std")]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use solution_2::{Entries, ShardedLruCache};
+
+#[test]
+fn concurrent_puts_respect_capacity() {
+    let cache = Arc::new(ShardedLruCache::new(128, 8));
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..2_000u64 {
+                    let key = t * 10_000 + i;
+                    cache.put(key, key);
+                    assert!(Entries(cache.len()) <= cache.total_capacity());
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert!(cache.len() <= 128);
+}
+
+#[test]
+fn concurrent_readers_see_consistent_values() {
+    let cache = Arc::new(ShardedLruCache::new(256, 8));
+    for k in 0..256u64 {
+        cache.put(k, k * 2);
+    }
+    let handles: Vec<_> = (0..8)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..5_000u64 {
+                    let key = (i * 7 + t) % 512;
+                    if t % 2 == 0 {
+                        cache.put(key, key * 2);
+                    } else if let Some(v) = cache.get(&key) {
+                        assert_eq!(v, key * 2);
+                    }
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert!(Entries(cache.len()) <= cache.total_capacity());
+}
+
+#[test]
+fn peek_under_contention_returns_cached_entries() {
+    let cache = Arc::new(ShardedLruCache::new(64, 4));
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..5_000u64 {
+                    cache.put(t * 100_000 + i, i);
+                }
+            })
+        })
+        .collect();
+    for _ in 0..1_000 {
+        if let Some((k, v)) = cache.peek_lru() {
+            assert_eq!(k % 100_000, v);
+        }
+        if let Some((k, v)) = cache.peek_mru() {
+            assert_eq!(k % 100_000, v);
+        }
+    }
+    for h in writers {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn get_or_insert_with_agrees_on_one_value() {
+    let cache = Arc::new(ShardedLruCache::new(16, 4));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let barrier = Arc::new(Barrier::new(2));
+    let handles: Vec<_> = (0..2)
+        .map(|t| {
+            let (cache, calls, barrier) =
+                (Arc::clone(&cache), Arc::clone(&calls), Arc::clone(&barrier));
+            thread::spawn(move || {
+                barrier.wait();
+                cache.get_or_insert_with("missing", || {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    t
+                })
+            })
+        })
+        .collect();
+    let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(results[0], results[1]);
+    assert_eq!(cache.get(&"missing"), Some(results[0]));
+}
+
+#[test]
+fn observed_epoch_implies_visible_write() {
+    let cache = Arc::new(ShardedLruCache::new(8, 4));
+    let writer = {
+        let cache = Arc::clone(&cache);
+        thread::spawn(move || {
+            // With a single writer, put number i leaves the epoch at i.
+            for i in 1..=20_000u64 {
+                cache.put(0u64, i);
+            }
+        })
+    };
+    let mut last = 0;
+    while last < 20_000 {
+        let epoch = cache.modification_epoch();
+        assert!(epoch >= last, "epoch went backwards");
+        last = epoch;
+        let value = cache.get(&0).unwrap_or(0);
+        assert!(value >= epoch, "saw epoch {epoch} but value {value}");
+    }
+    writer.join().unwrap();
+}
+
+#[test]
+fn try_get_never_returns_wrong_value() {
+    let cache = Arc::new(ShardedLruCache::new(64, 2));
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..5_000u64 {
+                    let key = (i + t) % 128;
+                    cache.put(key, key * 2);
+                }
+            })
+        })
+        .collect();
+    for i in 0..20_000u64 {
+        let key = i % 128;
+        if let Ok(Some(v)) = cache.try_get(&key) {
+            assert_eq!(v, key * 2);
+        }
+    }
+    for h in writers {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn resize_under_load_keeps_values_consistent() {
+    let cache = Arc::new(ShardedLruCache::new(64, 4));
+    let workers: Vec<_> = (0..4)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..10_000u64 {
+                    let key = (i * 13 + t) % 256;
+                    if t % 2 == 0 {
+                        cache.put(key, key * 2);
+                    } else if let Some(v) = cache.get(&key) {
+                        assert_eq!(v, key * 2);
+                    }
+                }
+            })
+        })
+        .collect();
+    for round in 0..200 {
+        cache.resize(Entries(if round % 2 == 0 { 16 } else { 128 }));
+    }
+    for h in workers {
+        h.join().unwrap();
+    }
+    cache.resize(Entries(16));
+    assert!(Entries(cache.len()) <= cache.total_capacity());
+    assert!(cache.validate().is_ok());
+}
+
+#[test]
+fn approx_len_converges_to_len() {
+    let cache = Arc::new(ShardedLruCache::new(128, 8));
+    let workers: Vec<_> = (0..4)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..5_000u64 {
+                    let key = (i * 7 + t) % 512;
+                    if i % 50 == 0 {
+                        cache.retain(|k, _| k % 4 != t);
+                    } else if i % 3 == 0 {
+                        cache.evict_global();
+                    } else {
+                        cache.put(key, i);
+                    }
+                    assert!(Entries(cache.approx_len()) <= cache.total_capacity());
+                }
+            })
+        })
+        .collect();
+    for h in workers {
+        h.join().unwrap();
+    }
+    assert_eq!(cache.approx_len(), cache.len());
+    cache.resize(Entries(32));
+    assert_eq!(cache.approx_len(), cache.len());
+}
+
+#[test]
+fn racing_put_if_absent_inserts_once() {
+    let cache = Arc::new(ShardedLruCache::new(256, 4));
+    for key in 0..100u64 {
+        let barrier = Arc::new(Barrier::new(2));
+        let racers: Vec<_> = (0..2u64)
+            .map(|t| {
+                let cache = Arc::clone(&cache);
+                let barrier = Arc::clone(&barrier);
+                thread::spawn(move || {
+                    barrier.wait();
+                    cache.put_if_absent(key, t).is_none().then_some(t)
+                })
+            })
+            .collect();
+        let winners: Vec<_> = racers
+            .into_iter()
+            .filter_map(|h| h.join().unwrap())
+            .collect();
+        assert_eq!(winners.len(), 1);
+        assert_eq!(cache.get(&key), Some(winners[0]));
+    }
+}
+
+#[test]
+fn update_pair_conserves_a_total_under_contention() {
+    const ACCOUNTS: u64 = 16;
+    // Room for every account in any one shard, so none is ever evicted.
+    let cache = Arc::new(ShardedLruCache::new(128, 8));
+    for k in 0..ACCOUNTS {
+        cache.put(k, 1_000u64);
+    }
+    let handles: Vec<_> = (0..8u64)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 0..5_000u64 {
+                    let from = (i * 7 + t) % ACCOUNTS;
+                    let to = (from + 1 + (i + t) % (ACCOUNTS - 1)) % ACCOUNTS;
+                    cache.update_pair(&from, &to, |a, b| {
+                        let (a, b) = (a.unwrap(), b.unwrap());
+                        let amount = (*a).min(1 + i % 5);
+                        *a -= amount;
+                        *b += amount;
+                    });
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    let total: u64 = (0..ACCOUNTS).map(|k| cache.get(&k).unwrap()).sum();
+    assert_eq!(total, ACCOUNTS * 1_000);
+}
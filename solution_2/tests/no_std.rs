@@ -0,0 +1,36 @@
+//! Builds under `--no-default-features --features no_std` as well as by
+//! default, so the `alloc`-only configuration is known to link.
+
+use core::hash::{BuildHasherDefault, Hasher};
+
+use solution_2::ShardedLruCache;
+
+/// A hasher with no `std` dependency, as an embedded caller would supply.
+#[derive(Default)]
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+#[test]
+fn put_and_get_with_a_caller_hasher() {
+    let cache = ShardedLruCache::with_hasher(4, 2, BuildHasherDefault::<Fnv>::default());
+    for k in 0..4u32 {
+        cache.put(k, k * 10);
+    }
+    assert_eq!(cache.get(&1), Some(10));
+    assert_eq!(cache.try_get(&3), Ok(Some(30)));
+    cache.put(9, 90);
+    assert_eq!(cache.len(), 4);
+    assert_eq!(cache.get(&9), Some(90));
+    cache.validate().unwrap();
+}
@@ -0,0 +1,346 @@
+//! Configuring an [`LRUCache`] one option at a time.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::cache::{EvictListener, EvictReason, EvictionPolicy, LRUCache, TimeSource};
+use crate::hasher::FoldHasher;
+use crate::stats::{MAX_TAGS, UNTAGGED};
+use crate::storage::AtomicStorage;
+
+/// An invalid [`LRUCacheBuilder`] configuration, as returned by
+/// [`try_build`](LRUCacheBuilder::try_build).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// No capacity was set, or it was zero.
+    ZeroCapacity,
+    /// The fold count was set to zero.
+    ZeroFolds,
+    /// More folds than slots, so some fold would have none.
+    TooManyFolds { capacity: usize, folds: usize },
+    /// More than [`MAX_TAGS`] tags.
+    TooManyTags(usize),
+    /// A tag equal to [`UNTAGGED`].
+    ReservedTag,
+    /// A tag given twice.
+    DuplicateTag(&'static str),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroCapacity => f.write_str("capacity must be non-zero"),
+            Self::ZeroFolds => f.write_str("num_folds must be non-zero"),
+            Self::TooManyFolds { capacity, folds } => write!(
+                f,
+                "num_folds must not exceed capacity ({folds} folds for {capacity} slots)"
+            ),
+            Self::TooManyTags(n) => {
+                write!(f, "at most {MAX_TAGS} tags can be registered, got {n}")
+            }
+            Self::ReservedTag => write!(f, "{UNTAGGED:?} is reserved"),
+            Self::DuplicateTag(tag) => write!(f, "tag {tag:?} registered twice"),
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+/// Checks a capacity and fold count the way every constructor does.
+pub(crate) fn check_shape(capacity: usize, folds: usize) -> Result<(), BuildError> {
+    if capacity == 0 {
+        Err(BuildError::ZeroCapacity)
+    } else if folds == 0 {
+        Err(BuildError::ZeroFolds)
+    } else if folds > capacity {
+        Err(BuildError::TooManyFolds { capacity, folds })
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks the tags passed to [`LRUCache::with_tags`].
+pub(crate) fn check_tags(tags: &[&'static str]) -> Result<(), BuildError> {
+    if tags.len() > MAX_TAGS {
+        return Err(BuildError::TooManyTags(tags.len()));
+    }
+    for (i, tag) in tags.iter().enumerate() {
+        if *tag == UNTAGGED {
+            return Err(BuildError::ReservedTag);
+        }
+        if tags[..i].contains(tag) {
+            return Err(BuildError::DuplicateTag(tag));
+        }
+    }
+    Ok(())
+}
+
+/// Builds an [`LRUCache`] from named options, reporting an invalid
+/// combination as a [`BuildError`] instead of panicking.
+///
+/// Only [`capacity`](Self::capacity) is required. The defaults are one
+/// fold, the identity hasher and every `with_*` option of [`LRUCache`] left
+/// at its own default.
+///
+/// ```
+/// use std::sync::atomic::AtomicUsize;
+/// use lru_rs::{EvictionPolicy, FibonacciHasher, LRUCache};
+///
+/// let cache = LRUCache::<AtomicUsize, AtomicUsize>::builder()
+///     .capacity(1024)
+///     .folds(16)
+///     .hasher(FibonacciHasher::default())
+///     .policy(EvictionPolicy::Lfu)
+///     .build();
+/// cache.put(1, 100);
+/// assert_eq!(cache.get(1), Some(100));
+/// ```
+pub struct LRUCacheBuilder<K: AtomicStorage, V: AtomicStorage, H = fn(usize) -> usize> {
+    capacity: usize,
+    folds: usize,
+    hasher: H,
+    policy: EvictionPolicy,
+    hit_decay: usize,
+    hit_sampling: u32,
+    hit_stripes: usize,
+    max_probe: usize,
+    rebalance: usize,
+    on_evict: Option<EvictListener<K::Value, V::Value>>,
+    time_source: Option<TimeSource>,
+    tags: Vec<&'static str>,
+}
+
+impl<K: AtomicStorage, V: AtomicStorage> LRUCacheBuilder<K, V> {
+    pub fn new() -> Self {
+        Self {
+            capacity: 0,
+            folds: 1,
+            hasher: |key| key,
+            policy: EvictionPolicy::default(),
+            hit_decay: 0,
+            hit_sampling: 0,
+            hit_stripes: 1,
+            max_probe: 0,
+            rebalance: 0,
+            on_evict: None,
+            time_source: None,
+            tags: Vec::new(),
+        }
+    }
+}
+
+impl<K: AtomicStorage, V: AtomicStorage> Default for LRUCacheBuilder<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: AtomicStorage, V: AtomicStorage, H: FoldHasher> LRUCacheBuilder<K, V, H> {
+    /// Total number of slots.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Number of folds the slots are split over.
+    pub fn folds(mut self, folds: usize) -> Self {
+        self.folds = folds;
+        self
+    }
+
+    /// Maps keys to folds; see [`LRUCache::with_hasher`].
+    pub fn hasher<H2: FoldHasher>(self, hasher: H2) -> LRUCacheBuilder<K, V, H2> {
+        LRUCacheBuilder {
+            capacity: self.capacity,
+            folds: self.folds,
+            hasher,
+            policy: self.policy,
+            hit_decay: self.hit_decay,
+            hit_sampling: self.hit_sampling,
+            hit_stripes: self.hit_stripes,
+            max_probe: self.max_probe,
+            rebalance: self.rebalance,
+            on_evict: self.on_evict,
+            time_source: self.time_source,
+            tags: self.tags,
+        }
+    }
+
+    pub fn policy(mut self, policy: EvictionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// See [`LRUCache::with_hit_decay`].
+    pub fn hit_decay(mut self, period: usize) -> Self {
+        self.hit_decay = period;
+        self
+    }
+
+    /// See [`LRUCache::with_hit_sampling`].
+    pub fn hit_sampling(mut self, shift: u32) -> Self {
+        self.hit_sampling = shift;
+        self
+    }
+
+    /// See [`LRUCache::with_hit_stripes`].
+    pub fn hit_stripes(mut self, stripes: usize) -> Self {
+        self.hit_stripes = stripes;
+        self
+    }
+
+    /// See [`LRUCache::with_max_probe`].
+    pub fn max_probe(mut self, max_probe: usize) -> Self {
+        self.max_probe = max_probe;
+        self
+    }
+
+    /// See [`LRUCache::with_rebalancing`].
+    pub fn rebalancing(mut self, percent: usize) -> Self {
+        self.rebalance = percent;
+        self
+    }
+
+    /// See [`LRUCache::with_on_evict`].
+    pub fn on_evict<F>(mut self, f: F) -> Self
+    where
+        F: Fn(K::Value, V::Value, EvictReason) + Send + Sync + 'static,
+    {
+        self.on_evict = Some(Box::new(f));
+        self
+    }
+
+    /// See [`LRUCache::with_time_source`].
+    pub fn time_source<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> u64 + Send + Sync + 'static,
+    {
+        self.time_source = Some(Box::new(f));
+        self
+    }
+
+    /// See [`LRUCache::with_tags`].
+    pub fn tags(mut self, tags: &[&'static str]) -> Self {
+        self.tags = tags.to_vec();
+        self
+    }
+
+    /// Builds the cache.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid; see
+    /// [`try_build`](Self::try_build).
+    pub fn build(self) -> LRUCache<K, V, H> {
+        match self.try_build() {
+            Ok(cache) => cache,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Builds the cache, or reports why the configuration is invalid: a
+    /// missing or zero capacity, zero folds, more folds than slots, or tags
+    /// [`LRUCache::with_tags`] would reject.
+    pub fn try_build(self) -> Result<LRUCache<K, V, H>, BuildError> {
+        check_shape(self.capacity, self.folds)?;
+        check_tags(&self.tags)?;
+        let mut cache = LRUCache::with_hasher(self.capacity, self.folds, self.hasher, self.policy)
+            .with_hit_decay(self.hit_decay)
+            .with_hit_sampling(self.hit_sampling)
+            .with_hit_stripes(self.hit_stripes)
+            .with_max_probe(self.max_probe)
+            .with_rebalancing(self.rebalance)
+            .with_tags(&self.tags);
+        if let Some(f) = self.on_evict {
+            cache.on_evict = Some(f);
+        }
+        if let Some(f) = self.time_source {
+            cache.time_source = f;
+        }
+        Ok(cache)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    type Builder = LRUCacheBuilder<AtomicUsize, AtomicUsize>;
+
+    #[test]
+    fn invalid_configurations_are_errors() {
+        assert_eq!(
+            Builder::new().try_build().err(),
+            Some(BuildError::ZeroCapacity)
+        );
+        assert_eq!(
+            Builder::new().capacity(8).folds(0).try_build().err(),
+            Some(BuildError::ZeroFolds)
+        );
+        assert_eq!(
+            Builder::new().capacity(4).folds(5).try_build().err(),
+            Some(BuildError::TooManyFolds {
+                capacity: 4,
+                folds: 5
+            })
+        );
+        assert_eq!(
+            Builder::new()
+                .capacity(8)
+                .tags(&["a", "a"])
+                .try_build()
+                .err(),
+            Some(BuildError::DuplicateTag("a"))
+        );
+        assert_eq!(
+            Builder::new()
+                .capacity(8)
+                .tags(&[UNTAGGED])
+                .try_build()
+                .err(),
+            Some(BuildError::ReservedTag)
+        );
+        let many = ["a"; MAX_TAGS + 1];
+        assert_eq!(
+            Builder::new().capacity(8).tags(&many).try_build().err(),
+            Some(BuildError::TooManyTags(MAX_TAGS + 1))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "num_folds must not exceed capacity (5 folds for 4 slots)")]
+    fn build_panics_with_the_error() {
+        Builder::new().capacity(4).folds(5).build();
+    }
+
+    #[test]
+    fn options_reach_the_cache() {
+        let now = Arc::new(AtomicU64::new(0));
+        let clock = Arc::clone(&now);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&log);
+        let cache = Builder::new()
+            .capacity(4)
+            .folds(2)
+            .hasher(|k: usize| k / 10)
+            .policy(EvictionPolicy::Fifo)
+            .max_probe(2)
+            .tags(&["web"])
+            .time_source(move || clock.load(Ordering::Relaxed))
+            .on_evict(move |k, _, reason| sink.lock().unwrap().push((k, reason)))
+            .build();
+        assert_eq!((cache.capacity(), cache.num_folds()), (4, 2));
+        assert_eq!(cache.policy(), EvictionPolicy::Fifo);
+        assert_eq!((cache.fold_of(5), cache.fold_of(15)), (0, 1));
+        cache.put_with_ttl(1, 1, Duration::from_millis(5));
+        now.store(5, Ordering::Relaxed);
+        assert_eq!(cache.get(1), None);
+        cache.put_tagged(2, 2, "web");
+        assert_eq!(cache.stats_by_tag()[1].0, "web");
+        cache.remove(2);
+        assert_eq!(*log.lock().unwrap(), [(2, EvictReason::Removed)]);
+    }
+}
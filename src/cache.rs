@@ -0,0 +1,4271 @@
+use std::cell::Cell;
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+use std::sync::PoisonError;
+use std::time::{Duration, Instant};
+
+use crate::builder::{check_shape, check_tags, LRUCacheBuilder};
+use crate::hasher::FoldHasher;
+use crate::snapshot::{self, Snapshot, SnapshotError};
+use crate::stats::{
+    bump, CacheStats, Counters, Occupancy, OccupancyStats, TagCounters, TagStats, UNTAGGED,
+};
+use crate::storage::{AtomicStorage, SeqLockBytes};
+use crate::sync::{
+    fence, hint, AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Mutex, MutexGuard, RwLock,
+    RwLockReadGuard,
+};
+
+/// How `put` picks the slot to repurpose when a key's fold is full, and
+/// what a hit records for it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// Evict the slot touched longest ago (by `get` or `put`).
+    #[default]
+    Lru,
+    /// Evict the slot with the fewest hits since it was filled.
+    Lfu,
+    /// Evict the slot filled longest ago. Hits and updates do not reorder
+    /// entries.
+    Fifo,
+    /// Evict a uniformly random occupied slot.
+    Random,
+    /// CLOCK (second chance): a hit sets the slot's reference bit. A hand
+    /// sweeps the fold from where it last stopped, clearing set bits, and
+    /// evicts the first slot whose bit is already clear.
+    Clock,
+}
+
+impl EvictionPolicy {
+    /// Every policy, in declaration order.
+    pub const ALL: [Self; 5] = [Self::Lru, Self::Lfu, Self::Fifo, Self::Random, Self::Clock];
+
+    /// Whether a cache with this policy keeps a hit count per entry.
+    /// [`Fifo`](Self::Fifo) and [`Random`](Self::Random) never read one, so
+    /// their caches save the word per slot, and report every count as zero.
+    pub const fn counts_hits(self) -> bool {
+        !matches!(self, Self::Fifo | Self::Random)
+    }
+}
+
+/// Why an entry was passed to the eviction listener.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EvictReason {
+    /// Its slot was repurposed for a new key in a full fold, or
+    /// [`LRUCache::resize`] left its fold no room for it.
+    Capacity,
+    /// It was removed with [`LRUCache::remove`].
+    Removed,
+    /// It was dropped by [`LRUCache::clear`].
+    Cleared,
+    /// Its time to live, set with [`LRUCache::put_with_ttl`], ran out, and
+    /// its slot was reclaimed by a `put`, `remove` or
+    /// [`LRUCache::purge_expired`].
+    Expired,
+}
+
+/// Returned by [`LRUCache::try_put`] when the key's fold has no slot to
+/// give it: every slot holds a [pinned](LRUCache::pin) entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheFull;
+
+impl fmt::Display for CacheFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("every slot of the key's fold is pinned")
+    }
+}
+
+impl Error for CacheFull {}
+
+/// Callback registered with [`LRUCache::with_on_evict`].
+pub(crate) type EvictListener<K, V> = Box<dyn Fn(K, V, EvictReason) + Send + Sync>;
+
+/// Clock registered with [`LRUCache::with_time_source`], in milliseconds.
+pub(crate) type TimeSource = Box<dyn Fn() -> u64 + Send + Sync>;
+
+/// Writer-side state of one fold, guarded by the fold lock.
+#[derive(Default)]
+struct FoldState {
+    /// Puts into this fold since the last hit-count decay.
+    puts_since_decay: usize,
+    /// Offset into the fold where the [`EvictionPolicy::Clock`] hand
+    /// resumes.
+    hand: usize,
+    /// xorshift state for [`EvictionPolicy::Random`]; 0 until first used.
+    rng: u64,
+    /// New keys put into this fold in the current rebalancing window, and
+    /// how many of them found it full.
+    window_inserts: usize,
+    window_full: usize,
+    /// Whether enough of the last window's new keys found the fold full that
+    /// it borrows the next fold's free slots.
+    rebalancing: bool,
+    /// Times the fold lock was taken, so tests can check batching.
+    #[cfg(test)]
+    acquisitions: usize,
+}
+
+/// One step of a xorshift stream whose state is `x`. Zero is xorshift's
+/// fixed point, so a zero state is seeded first.
+fn xorshift(mut x: u64) -> u64 {
+    if x == 0 {
+        x = 0x9E37_79B9_7F4A_7C15;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    x
+}
+
+/// Hands out [`HIT_STRIPE`]s.
+static NEXT_HIT_STRIPE: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+thread_local! {
+    /// Per-thread xorshift state for hit sampling, so sampled `get`s share
+    /// no random state; 0 until first used.
+    static HIT_SAMPLER: Cell<u64> = const { Cell::new(0) };
+    /// Which of a slot's [hit stripes](LRUCache::with_hit_stripes) this
+    /// thread increments, modulo their number. Threads take turns in the
+    /// order they first count a hit, which spreads them more evenly than a
+    /// hash of their ids would.
+    static HIT_STRIPE: usize = NEXT_HIT_STRIPE.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Whether this hit is one of the 1 in `1 << shift` that gets counted.
+fn sample_hit(shift: u32) -> bool {
+    HIT_SAMPLER.with(|state| {
+        // Each thread seeds its stream with the address of its own state.
+        let x = match state.get() {
+            0 => xorshift(state as *const Cell<u64> as u64),
+            x => xorshift(x),
+        };
+        state.set(x);
+        x & ((1 << shift) - 1) == 0
+    })
+}
+
+impl FoldState {
+    fn next_random(&mut self) -> u64 {
+        self.rng = xorshift(self.rng);
+        self.rng
+    }
+}
+
+/// The locks a writer of one key holds: its fold's, plus the next fold's
+/// while the key's fold may borrow slots there (see
+/// [`LRUCache::with_rebalancing`]).
+struct FoldLocks<'a> {
+    state: MutexGuard<'a, FoldState>,
+    lender: Option<MutexGuard<'a, FoldState>>,
+}
+
+/// Slot states. Lock-free readers only match [`FULL`] and [`UPDATING`]
+/// slots; every other state is owned by exactly one writer.
+const EMPTY: u8 = 0;
+/// Taken by a writer that is emptying or filling it.
+const CLAIMED: u8 = 1;
+/// Holds a new key whose inserter is checking that no other slot of the
+/// fold took the same key first.
+const PENDING: u8 = 2;
+/// Holds a published entry.
+const FULL: u8 = 3;
+/// Holds a published entry whose value its owner is replacing in place.
+const UPDATING: u8 = 4;
+
+/// Deadline of an entry that never expires.
+const NO_DEADLINE: u64 = 0;
+
+/// Keys a fold scan loads and compares per step; see [`Slots::matching`].
+const SCAN_CHUNK: usize = 8;
+
+/// Slots the [`EvictionPolicy::Clock`] hand passes in one victim search
+/// before it settles for the first candidate it saw, so the search costs
+/// the same whatever the fold size.
+const CLOCK_SWEEP_LIMIT: usize = 64;
+
+/// Whether an entry with `deadline` has expired at time `now`.
+fn is_expired(deadline: u64, now: u64) -> bool {
+    deadline != NO_DEADLINE && now >= deadline
+}
+
+/// What a put did: the value it replaced and the entry it evicted.
+struct PutOutcome<K, V> {
+    previous: Option<V>,
+    evicted: Option<(K, V)>,
+}
+
+/// A [`LRUCache::top_n_by_hits`] candidate. Ordered by hits, then by
+/// preferring the lower slot, so keys need no `Ord` of their own.
+struct HotKey<K> {
+    hits: usize,
+    slot: usize,
+    key: K,
+}
+
+impl<K> Ord for HotKey<K> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        (self.hits, Reverse(self.slot)).cmp(&(other.hits, Reverse(other.slot)))
+    }
+}
+
+impl<K> PartialOrd for HotKey<K> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K> PartialEq for HotKey<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl<K> Eq for HotKey<K> {}
+
+/// A slot's contents, as moved between slots by relocation and `resize`.
+struct SlotEntry<K, V> {
+    key: K,
+    value: V,
+    hits: usize,
+    last_access: usize,
+    deadline: u64,
+    pinned: bool,
+    referenced: bool,
+}
+
+/// Per-slot metadata that hits write, besides the hit count.
+///
+/// A slot's fields share a line, since a hit writes them together. With
+/// the `padded-slots` feature each slot's counters also get a cache line of
+/// their own, so hits on neighbouring slots from different cores do not
+/// contend for one line. That costs 64 bytes per slot instead of 16.
+#[derive(Default)]
+#[cfg_attr(feature = "padded-slots", repr(align(64)))]
+struct SlotCounters {
+    /// Value of the cache's `clock` when the slot was last touched, or
+    /// when it was filled under policies that ignore recency.
+    last_access: AtomicUsize,
+    /// [`EvictionPolicy::Clock`]'s second-chance bit.
+    referenced: AtomicBool,
+}
+
+/// A slot's hit count, padded like [`SlotCounters`]. Kept apart from them
+/// so that policies that never read it need not store it.
+#[derive(Default)]
+#[cfg_attr(feature = "padded-slots", repr(align(64)))]
+struct HitCount(AtomicUsize);
+
+/// The slot arrays, one element per slot, split into `num_folds` contiguous
+/// folds. [`LRUCache::resize`] replaces them wholesale.
+struct Slots<K, V> {
+    keys: Vec<K>,
+    values: Vec<V>,
+    /// Each slot's state. Writers claim slots by compare-and-swap on it, and
+    /// publish an entry by storing [`FULL`] last.
+    state: Vec<AtomicU8>,
+    /// Bumped when a writer claims a slot and again when it gives the slot
+    /// up, so each is odd exactly while its slot is [`CLAIMED`] or
+    /// [`PENDING`]. Readers compare it before and after a read.
+    versions: Vec<AtomicUsize>,
+    /// The metadata that lock-free lookups write.
+    counters: Vec<SlotCounters>,
+    /// Each slot's hit count, split over `hit_stripes` counters and laid
+    /// out stripe by stripe, or `None` for a cache whose policy never reads
+    /// them (see [`EvictionPolicy::counts_hits`]).
+    hits: Option<Vec<HitCount>>,
+    hit_stripes: usize,
+    /// Time source reading at which each entry expires, or [`NO_DEADLINE`].
+    deadlines: Vec<AtomicU64>,
+    /// Whether each entry is exempt from eviction. Read and written only
+    /// under the fold lock.
+    pinned: Vec<AtomicBool>,
+    /// One bit per slot, set while the slot is not [`EMPTY`], so a writer
+    /// looking for a free slot skips a full word of slots with one load. A
+    /// bit is set just after its slot is claimed and cleared just before
+    /// the slot is freed, so a set bit always means a slot in use.
+    occupied: Vec<AtomicU64>,
+    num_folds: usize,
+}
+
+impl<K: AtomicStorage, V: AtomicStorage> Slots<K, V> {
+    /// Slot arrays keeping `hit_stripes` hit counters per slot; 0 keeps
+    /// no hit counts.
+    fn new(capacity: usize, num_folds: usize, hit_stripes: usize) -> Self {
+        Self {
+            keys: (0..capacity).map(|_| K::new(K::ZERO)).collect(),
+            values: (0..capacity).map(|_| V::new(V::ZERO)).collect(),
+            state: (0..capacity).map(|_| AtomicU8::new(EMPTY)).collect(),
+            versions: (0..capacity).map(|_| AtomicUsize::new(0)).collect(),
+            counters: (0..capacity).map(|_| SlotCounters::default()).collect(),
+            hits: (hit_stripes != 0).then(|| {
+                (0..capacity * hit_stripes)
+                    .map(|_| HitCount::default())
+                    .collect()
+            }),
+            hit_stripes: hit_stripes.max(1),
+            deadlines: (0..capacity).map(|_| AtomicU64::new(NO_DEADLINE)).collect(),
+            pinned: (0..capacity).map(|_| AtomicBool::new(false)).collect(),
+            occupied: (0..capacity.div_ceil(64))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            num_folds,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Slots of `fold`. Keys spread evenly over the folds, so the
+    /// remainder of `capacity / num_folds` goes one slot each to the
+    /// lowest folds, keeping fold sizes at most one apart.
+    fn get_fold_range(&self, fold: usize) -> Range<usize> {
+        let base = self.capacity() / self.num_folds;
+        let rem = self.capacity() % self.num_folds;
+        let start = fold * base + fold.min(rem);
+        start..start + base + usize::from(fold < rem)
+    }
+
+    /// Whether slot `i` holds a published entry. Only the fold lock's holder
+    /// unpublishes entries, so under the lock a `true` stays true; without
+    /// it, only a hint.
+    fn is_occupied(&self, i: usize) -> bool {
+        is_published(self.state[i].load(Ordering::Relaxed))
+    }
+
+    /// Takes the empty slot `i` for filling, failing if it is in use.
+    fn claim_empty(&self, i: usize) -> bool {
+        let claimed = self.state[i]
+            .compare_exchange(EMPTY, CLAIMED, Ordering::SeqCst, Ordering::Relaxed)
+            .is_ok();
+        if claimed {
+            self.occupied[i / 64].fetch_or(1 << (i % 64), Ordering::Relaxed);
+            self.begin_write(i);
+        }
+        claimed
+    }
+
+    /// The slots of `range` whose [`occupied`](Self::occupied) bit is clear,
+    /// in order: candidates for [`claim_empty`](Self::claim_empty), which
+    /// still decides. Each word is loaded once, when the scan reaches it.
+    fn vacant(&self, range: Range<usize>) -> impl Iterator<Item = usize> + '_ {
+        (range.start / 64..range.end.div_ceil(64)).flat_map(move |w| {
+            let base = w * 64;
+            let lo = range.start.max(base) - base;
+            let hi = range.end.min(base + 64) - base;
+            let mask = (u64::MAX >> (64 - hi)) & (u64::MAX << lo);
+            let mut free = !self.occupied[w].load(Ordering::Relaxed) & mask;
+            std::iter::from_fn(move || {
+                let bit = free.trailing_zeros() as usize;
+                free &= free.checked_sub(1)?;
+                Some(base + bit)
+            })
+        })
+    }
+
+    /// The slots of `range` whose key reads as `key`, in order. Keys are
+    /// loaded [`SCAN_CHUNK`] at a time with Relaxed loads and compared as a
+    /// batch, so the scan is a run of independent loads rather than a
+    /// chain of Acquire loads per slot. A match is only a candidate: the
+    /// caller re-checks the slot, through [`read_slot`](Self::read_slot)
+    /// when lock-free or its state under the fold lock. Empty slots hold
+    /// [`AtomicStorage::ZERO`], so they are candidates for the zero key.
+    fn matching(&self, range: Range<usize>, key: K::Value) -> impl Iterator<Item = usize> + '_ {
+        let start = range.start;
+        self.keys[range]
+            .chunks(SCAN_CHUNK)
+            .enumerate()
+            .flat_map(move |(c, chunk)| {
+                let mut hits = 0u32;
+                for (bit, k) in chunk.iter().enumerate() {
+                    hits |= u32::from(k.load(Ordering::Relaxed) == key) << bit;
+                }
+                let base = start + c * SCAN_CHUNK;
+                std::iter::from_fn(move || {
+                    let bit = hits.trailing_zeros() as usize;
+                    hits &= hits.checked_sub(1)?;
+                    Some(base + bit)
+                })
+            })
+    }
+
+    /// Number of slots in `range` that are in use, from their
+    /// [`occupied`](Self::occupied) bits.
+    fn count_occupied(&self, range: Range<usize>) -> usize {
+        let free = self.vacant(range.clone()).count();
+        range.len() - free
+    }
+
+    /// Takes slot `i`, which must be empty and out of reach of lock-free
+    /// writers: a fresh array, or a fold whose lookups are bounded.
+    fn claim_free(&self, i: usize) {
+        let claimed = self.claim_empty(i);
+        debug_assert!(claimed, "slot {i} is in use");
+    }
+
+    /// Makes slot `i`'s version odd before its new owner changes it.
+    fn begin_write(&self, i: usize) {
+        self.versions[i].fetch_add(1, Ordering::Relaxed);
+        // Pairs with the fence in `read_slot`: a reader that sees any of
+        // the owner's stores also sees the odd version on its re-check.
+        fence(Ordering::Release);
+    }
+
+    /// Makes slot `i`'s version even again once its owner is done.
+    fn end_write(&self, i: usize) {
+        self.versions[i].fetch_add(1, Ordering::Release);
+    }
+
+    /// Moves slot `i` from [`FULL`] to `to`, waiting out an in-place update.
+    /// Fails if the slot holds no published entry.
+    fn claim_published(&self, i: usize, to: u8) -> bool {
+        loop {
+            match self.state[i].compare_exchange_weak(FULL, to, Ordering::SeqCst, Ordering::Relaxed)
+            {
+                Ok(_) => return true,
+                Err(UPDATING) => hint::spin_loop(),
+                Err(FULL) => {}
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Records an access to slot `i` at logical time `now`, skipping the
+    /// store when the stamp is already current to keep hot slots read-only.
+    fn touch(&self, i: usize, now: usize) {
+        if self.counters[i].last_access.load(Ordering::Relaxed) != now {
+            self.counters[i].last_access.store(now, Ordering::Relaxed);
+        }
+    }
+
+    /// Slot `i`'s counters, one per stripe.
+    fn hit_stripes_of<'a>(
+        &self,
+        counts: &'a [HitCount],
+        i: usize,
+    ) -> impl Iterator<Item = &'a AtomicUsize> {
+        counts.iter().skip(i).step_by(self.capacity()).map(|c| &c.0)
+    }
+
+    /// Slot `i`'s hit count, summed over its stripes, or `None` if the
+    /// cache keeps none.
+    fn hit_count(&self, i: usize) -> Option<usize> {
+        let counts = self.hits.as_ref()?;
+        let sum = self.hit_stripes_of(counts, i).fold(0, |sum: usize, c| {
+            sum.saturating_add(c.load(Ordering::Relaxed))
+        });
+        Some(sum)
+    }
+
+    /// Sets slot `i`'s hit count, all of it in the first stripe.
+    fn set_hit_count(&self, i: usize, hits: usize) {
+        if let Some(counts) = &self.hits {
+            for (stripe, c) in self.hit_stripes_of(counts, i).enumerate() {
+                c.store(if stripe == 0 { hits } else { 0 }, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Adds `n` to slot `i`'s hit count, in the calling thread's stripe.
+    /// The stripe saturates at `usize::MAX`, and so does the sum.
+    fn add_hits(&self, i: usize, n: usize) {
+        if let Some(counts) = &self.hits {
+            let stripe = match self.hit_stripes {
+                1 => 0,
+                stripes => HIT_STRIPE.with(|&s| s) % stripes,
+            };
+            let _ = counts[stripe * self.capacity() + i].0.fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |h| Some(h.saturating_add(n)).filter(|&sum| sum != h),
+            );
+        }
+    }
+
+    /// Splits every slot's hit count over `stripes` counters, keeping the
+    /// counts. Does nothing for a cache that keeps none.
+    fn restripe(&mut self, stripes: usize) {
+        if self.hits.is_none() || stripes == self.hit_stripes {
+            return;
+        }
+        let counts: Vec<usize> = (0..self.capacity())
+            .filter_map(|i| self.hit_count(i))
+            .collect();
+        self.hits = Some(
+            (0..counts.len() * stripes)
+                .map(|_| HitCount::default())
+                .collect(),
+        );
+        self.hit_stripes = stripes;
+        for (i, hits) in counts.into_iter().enumerate() {
+            self.set_hit_count(i, hits);
+        }
+    }
+
+    /// Halves slot `i`'s hit count, stripe by stripe, keeping increments
+    /// that race with the update.
+    fn halve_hit_count(&self, i: usize) {
+        if let Some(counts) = &self.hits {
+            for c in self.hit_stripes_of(counts, i) {
+                let _ = c.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |h| Some(h / 2));
+            }
+        }
+    }
+
+    /// Sets slot `i`'s reference bit, skipping the store when it is already
+    /// set for the same reason as [`touch`](Self::touch).
+    fn reference(&self, i: usize) {
+        if !self.counters[i].referenced.load(Ordering::Relaxed) {
+            self.counters[i].referenced.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Lock-free read of slot `i`: its value and deadline if it holds `key`.
+    ///
+    /// The slot's version is read before and after its key, state and
+    /// value. If it is unchanged and even, no writer emptied, refilled or
+    /// repurposed the slot in between, not even one that put the same key
+    /// back, so the value belongs to `key`. Otherwise the read is retried.
+    /// In-place updates leave the version alone: they keep the key, so the
+    /// old and the new value are both correct answers.
+    fn read_slot(&self, i: usize, key: K::Value) -> Option<(V::Value, u64)> {
+        loop {
+            let before = self.versions[i].load(Ordering::Acquire);
+            if before % 2 == 1
+                || self.keys[i].load(Ordering::Acquire) != key
+                || !is_published(self.state[i].load(Ordering::Acquire))
+            {
+                return None;
+            }
+            let value = self.values[i].load(Ordering::Acquire);
+            let deadline = self.deadlines[i].load(Ordering::Relaxed);
+            // Keeps the loads above from moving past the re-check.
+            fence(Ordering::Acquire);
+            if self.versions[i].load(Ordering::Relaxed) == before {
+                return Some((value, deadline));
+            }
+        }
+    }
+
+    /// Moves the entry in slot `from` to the empty slot `to`, then empties
+    /// `from`. Caller holds the fold lock, and lookups are bounded, so no
+    /// lock-free writer touches the fold; or it holds the slots exclusively.
+    fn relocate(&self, from: usize, to: usize) {
+        let entry = self.take_slot(from).expect("relocated slots are occupied");
+        self.claim_free(to);
+        self.put_slot(to, entry);
+    }
+
+    /// Unpublishes slot `i`, leaving it claimed by the caller, and returns
+    /// its entry. `None` if it held no published entry. Caller holds the
+    /// fold lock.
+    fn claim_for_reuse(&self, i: usize) -> Option<SlotEntry<K::Value, V::Value>> {
+        if !self.claim_published(i, CLAIMED) {
+            return None;
+        }
+        Some(self.empty_claimed(i))
+    }
+
+    /// Like [`claim_for_reuse`](Self::claim_for_reuse), then frees the slot.
+    fn take_slot(&self, i: usize) -> Option<SlotEntry<K::Value, V::Value>> {
+        let entry = self.claim_for_reuse(i)?;
+        self.release(i);
+        Some(entry)
+    }
+
+    /// Like [`take_slot`](Self::take_slot), but only if the entry has
+    /// expired at `now`. The slot is held [`UPDATING`] while its deadline is
+    /// checked, so an in-place update that refreshes the deadline either
+    /// lands before the check or waits for it. Caller holds the fold lock.
+    fn take_if_expired(&self, i: usize, now: u64) -> Option<SlotEntry<K::Value, V::Value>> {
+        if !self.claim_published(i, UPDATING) {
+            return None;
+        }
+        if !is_expired(self.deadlines[i].load(Ordering::Relaxed), now) {
+            self.state[i].store(FULL, Ordering::Release);
+            return None;
+        }
+        self.state[i].store(CLAIMED, Ordering::SeqCst);
+        let entry = self.empty_claimed(i);
+        self.release(i);
+        Some(entry)
+    }
+
+    /// Begins the write to slot `i`, which the caller has just moved to
+    /// [`CLAIMED`], and clears it, returning what it held.
+    fn empty_claimed(&self, i: usize) -> SlotEntry<K::Value, V::Value> {
+        self.begin_write(i);
+        let entry = SlotEntry {
+            key: self.keys[i].load(Ordering::Relaxed),
+            value: self.values[i].load(Ordering::Relaxed),
+            hits: self.hit_count(i).unwrap_or(0),
+            last_access: self.counters[i].last_access.load(Ordering::Relaxed),
+            deadline: self.deadlines[i].load(Ordering::Relaxed),
+            pinned: self.is_pinned(i),
+            referenced: self.counters[i].referenced.load(Ordering::Relaxed),
+        };
+        self.clear_slot(i);
+        entry
+    }
+
+    /// Frees the claimed slot `i`, whose contents have been cleared.
+    fn release(&self, i: usize) {
+        self.end_write(i);
+        self.occupied[i / 64].fetch_and(!(1 << (i % 64)), Ordering::Relaxed);
+        self.state[i].store(EMPTY, Ordering::Release);
+    }
+
+    /// Publishes the entry written into the claimed slot `i`.
+    fn publish_slot(&self, i: usize) {
+        self.end_write(i);
+        self.state[i].store(FULL, Ordering::Release);
+    }
+
+    /// Writes an entry taken with [`take_slot`](Self::take_slot) into slot
+    /// `i`, which the caller has claimed, publishing it last.
+    fn put_slot(&self, i: usize, entry: SlotEntry<K::Value, V::Value>) {
+        self.values[i].store(entry.value, Ordering::Release);
+        self.set_hit_count(i, entry.hits);
+        self.counters[i]
+            .last_access
+            .store(entry.last_access, Ordering::Relaxed);
+        self.counters[i]
+            .referenced
+            .store(entry.referenced, Ordering::Relaxed);
+        self.deadlines[i].store(entry.deadline, Ordering::Relaxed);
+        self.pinned[i].store(entry.pinned, Ordering::Relaxed);
+        self.keys[i].store(entry.key, Ordering::Release);
+        self.publish_slot(i);
+    }
+
+    /// Zeroes a slot's key, value and metadata. The slot must already be
+    /// unpublished.
+    fn clear_slot(&self, i: usize) {
+        self.keys[i].store(K::ZERO, Ordering::Relaxed);
+        // Release: a reader that loads the zeroed value also sees the slot
+        // unpublished on its re-check.
+        self.values[i].store(V::ZERO, Ordering::Release);
+        self.set_hit_count(i, 0);
+        self.counters[i].last_access.store(0, Ordering::Relaxed);
+        self.counters[i].referenced.store(false, Ordering::Relaxed);
+        self.deadlines[i].store(NO_DEADLINE, Ordering::Relaxed);
+        self.pinned[i].store(false, Ordering::Relaxed);
+    }
+
+    /// Whether slot `i`'s entry is pinned. Caller holds the fold lock.
+    fn is_pinned(&self, i: usize) -> bool {
+        self.pinned[i].load(Ordering::Relaxed)
+    }
+}
+
+/// Whether a slot in `state` is visible to lock-free readers.
+fn is_published(state: u8) -> bool {
+    state == FULL || state == UPDATING
+}
+
+/// A fixed-capacity cache over flat arrays of atomics.
+///
+/// The capacity is split into `num_folds` contiguous slot ranges ("folds").
+/// A key lives in the fold chosen by its [`FoldHasher`], and only ever in
+/// that fold unless [rebalancing](Self::with_rebalancing) lends it a slot of
+/// the next one. Readers scan the fold without locking it. A `put` that
+/// updates a key or fills a free slot claims the slot with a
+/// compare-and-swap; evicting puts, `remove` and `clear` serialize per fold
+/// on a mutex. All of them hold a shared guard on the slot arrays, which
+/// only [`resize`](Self::resize) takes exclusively.
+///
+/// Each slot has a state word, published after its key and value, so every
+/// key value, including zero, is storable.
+pub struct LRUCache<K: AtomicStorage, V: AtomicStorage, H = fn(usize) -> usize> {
+    slots: RwLock<Slots<K, V>>,
+    folds: Vec<Mutex<FoldState>>,
+    /// Operation counters, one stripe per fold.
+    stats: Vec<Counters>,
+    /// Tags registered with `with_tags`, in registration order.
+    tags: Vec<&'static str>,
+    /// Per-tag counters, `tags.len()` of them per fold, fold-major.
+    tag_stats: Vec<TagCounters>,
+    occupancy: Occupancy,
+    num_folds: usize,
+    hasher: H,
+    policy: EvictionPolicy,
+    /// Halve a fold's hit counts every this many puts into it; 0 disables.
+    hit_decay_period: usize,
+    /// Hits are counted 1 in `1 << hit_sample_shift` times, each counted
+    /// one adding `1 << hit_sample_shift`; 0 counts every hit.
+    hit_sample_shift: u32,
+    /// Slots `get` scans from the start of a fold; 0 means the whole fold.
+    max_probe: usize,
+    /// Percentage of a window's new keys that must find a fold full for it
+    /// to borrow slots of the next fold; 0 disables rebalancing.
+    rebalance_percent: usize,
+    /// Set once a fold has borrowed a slot of the next fold, so lookups of
+    /// its keys scan that fold too. Cleared by `resize`.
+    borrowed: Vec<AtomicBool>,
+    pub(crate) on_evict: Option<EvictListener<K::Value, V::Value>>,
+    /// Bumped once by every `clear`; entries written before the bump are
+    /// logically gone.
+    generation: AtomicUsize,
+    /// The `generation` each fold was last swept up to. A fold whose entry
+    /// lags holds only entries from before a `clear`.
+    fold_generations: Vec<AtomicUsize>,
+    /// Coarse logical clock, advanced by every `put`. Reads stamp slots with
+    /// its current value so `get` never does a contended read-modify-write.
+    clock: AtomicUsize,
+    /// Fold that the next `pop_least_used` starts its search from.
+    pop_cursor: AtomicUsize,
+    /// Millisecond clock that entry deadlines are read against.
+    pub(crate) time_source: TimeSource,
+}
+
+/// Folds per available thread that [`LRUCache::with_auto_folds`] aims for.
+pub const AUTO_FOLDS_PER_THREAD: usize = 4;
+
+/// Fewest slots per fold that [`auto_fold_count`] settles for, so that the
+/// eviction policy still has candidates to choose between.
+pub const MIN_SLOTS_PER_FOLD: usize = 16;
+
+/// New keys per fold over which [`LRUCache::with_rebalancing`] measures how
+/// often the fold is full.
+pub const REBALANCE_WINDOW: usize = 32;
+
+/// A fold count for `capacity` slots: `folds_per_thread` times
+/// [`std::thread::available_parallelism`] (1 if that is unknown), lowered
+/// until each fold holds at least [`MIN_SLOTS_PER_FOLD`] slots. Never less
+/// than 1. Pass the result to any constructor to tune the factor.
+pub fn auto_fold_count(capacity: usize, folds_per_thread: usize) -> usize {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    fold_count_for(capacity, threads, folds_per_thread)
+}
+
+fn fold_count_for(capacity: usize, threads: usize, folds_per_thread: usize) -> usize {
+    threads
+        .saturating_mul(folds_per_thread)
+        .clamp(1, (capacity / MIN_SLOTS_PER_FOLD).max(1))
+}
+
+/// An [`LRUCache`] from `usize` keys to `N`-byte values, such as small
+/// fixed-size structs in their byte form. Each value sits behind a
+/// [`SeqLockBytes`] seqlock, so a lookup never sees a half-written value.
+pub type LRUCacheBytes<const N: usize, H = fn(usize) -> usize> =
+    LRUCache<AtomicUsize, SeqLockBytes<N>, H>;
+
+impl<K: AtomicStorage, V: AtomicStorage> LRUCache<K, V> {
+    /// Creates a cache with `capacity` slots split over `num_folds` folds,
+    /// using LRU eviction.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` or `num_folds` is zero, or if there are more
+    /// folds than slots.
+    pub fn new(capacity: usize, num_folds: usize, hasher: fn(usize) -> usize) -> Self {
+        Self::with_policy(capacity, num_folds, hasher, EvictionPolicy::default())
+    }
+
+    /// Like [`new`](Self::new), with an explicit eviction policy.
+    pub fn with_policy(
+        capacity: usize,
+        num_folds: usize,
+        hasher: fn(usize) -> usize,
+        policy: EvictionPolicy,
+    ) -> Self {
+        LRUCache::with_hasher(capacity, num_folds, hasher, policy)
+    }
+
+    /// Starts an [`LRUCacheBuilder`], which names each option and reports an
+    /// invalid combination as an error.
+    pub fn builder() -> LRUCacheBuilder<K, V> {
+        LRUCacheBuilder::new()
+    }
+}
+
+impl<K: AtomicStorage, V: AtomicStorage, H: FoldHasher> LRUCache<K, V, H> {
+    /// Like [`with_hasher`](Self::with_hasher) with LRU eviction, with the
+    /// fold count picked by [`auto_fold_count`] at
+    /// [`AUTO_FOLDS_PER_THREAD`]. [`num_folds`](Self::num_folds) reports it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn with_auto_folds(capacity: usize, hasher: H) -> Self {
+        let num_folds = auto_fold_count(capacity, AUTO_FOLDS_PER_THREAD);
+        Self::with_hasher(capacity, num_folds, hasher, EvictionPolicy::default())
+    }
+
+    /// Like [`with_policy`](LRUCache::with_policy), with any [`FoldHasher`],
+    /// such as a seeded [`FibonacciHasher`](crate::FibonacciHasher) or a
+    /// capturing closure.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` or `num_folds` is zero, or if there are more
+    /// folds than slots.
+    pub fn with_hasher(
+        capacity: usize,
+        num_folds: usize,
+        hasher: H,
+        policy: EvictionPolicy,
+    ) -> Self {
+        if let Err(e) = check_shape(capacity, num_folds) {
+            panic!("{e}");
+        }
+        Self {
+            slots: RwLock::new(Slots::new(
+                capacity,
+                num_folds,
+                usize::from(policy.counts_hits()),
+            )),
+            folds: (0..num_folds)
+                .map(|_| Mutex::new(FoldState::default()))
+                .collect(),
+            stats: (0..num_folds).map(|_| Counters::default()).collect(),
+            tags: Vec::new(),
+            tag_stats: Vec::new(),
+            occupancy: Occupancy::default(),
+            num_folds,
+            hasher,
+            policy,
+            hit_decay_period: 0,
+            hit_sample_shift: 0,
+            max_probe: 0,
+            rebalance_percent: 0,
+            borrowed: (0..num_folds).map(|_| AtomicBool::new(false)).collect(),
+            on_evict: None,
+            generation: AtomicUsize::new(0),
+            fold_generations: (0..num_folds).map(|_| AtomicUsize::new(0)).collect(),
+            clock: AtomicUsize::new(0),
+            pop_cursor: AtomicUsize::new(0),
+            time_source: {
+                let start = Instant::now();
+                Box::new(move || u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX))
+            },
+        }
+    }
+
+    /// Enables hit-count aging: every `period` puts into a fold halve the hit
+    /// counts of all slots in that fold, so an entry that was hot long ago
+    /// cannot outrank current traffic forever under [`EvictionPolicy::Lfu`].
+    /// A period of 0 disables aging (the default).
+    pub fn with_hit_decay(mut self, period: usize) -> Self {
+        self.hit_decay_period = period;
+        self
+    }
+
+    /// Counts only one in `1 << shift` hits, chosen at random, and adds
+    /// `1 << shift` for each, so hit counts stay estimates of the real ones
+    /// while a hot key's counter is written `1 << shift` times less often.
+    /// Many threads reading one key then contend less on its cache line.
+    /// `shift` is clamped to 16; 0 counts every hit (the default).
+    ///
+    /// Only policies that [count hits](EvictionPolicy::counts_hits) are
+    /// affected. Keys with few hits get noisy counts, so
+    /// [`EvictionPolicy::Lfu`] may evict one of two rarely read keys
+    /// out of order.
+    pub fn with_hit_sampling(mut self, shift: u32) -> Self {
+        self.hit_sample_shift = shift.min(16);
+        self
+    }
+
+    /// Splits each slot's hit count over `stripes` counters, each thread
+    /// incrementing only its own, so threads reading one hot key do not all
+    /// contend on one atomic. Counts stay exact; reading one sums its
+    /// stripes, which eviction does under the fold lock. Costs `stripes`
+    /// times the memory of the counts. 1, the default, keeps one counter
+    /// per slot, and 0 is treated as 1.
+    ///
+    /// Only policies that [count hits](EvictionPolicy::counts_hits) keep
+    /// the counters; for the others this does nothing. See
+    /// [`with_hit_sampling`](Self::with_hit_sampling) for a cheaper,
+    /// approximate alternative.
+    pub fn with_hit_stripes(self, stripes: usize) -> Self {
+        self.slots
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .restripe(stripes.max(1));
+        self
+    }
+
+    /// Bounds lookups to the first `max_probe` slots of each fold, so a miss
+    /// costs at most that many loads however large the fold is. 0 removes the
+    /// bound (the default).
+    ///
+    /// With a bound set, writers keep each fold's entries packed at its start
+    /// and move every key they write into the probed window, displacing the
+    /// window's eviction candidate beyond it. While a fold holds no more
+    /// than `max_probe` entries every one of them is found. Beyond that,
+    /// misses on keys pushed past the window are counted as
+    /// [`probe_overflows`](CacheStats::probe_overflows).
+    ///
+    /// Moving an entry publishes it at its new slot before clearing the old
+    /// one, but a lookup racing with the move can still miss it.
+    pub fn with_max_probe(mut self, max_probe: usize) -> Self {
+        self.max_probe = max_probe;
+        self
+    }
+
+    /// Lets a fold that keeps filling up borrow free slots of the next fold
+    /// (the last fold borrows from the first). Once at least `percent` of a
+    /// fold's last [`REBALANCE_WINDOW`] new keys found it full, a new key
+    /// that finds it full again takes a free slot of the next fold, if it
+    /// has one, instead of evicting. 0 disables rebalancing (the default);
+    /// values above 100 act as 100.
+    ///
+    /// A borrowed slot belongs to the next fold for eviction, so that fold's
+    /// own keys take it back as they need it. Once a fold has borrowed, a
+    /// lookup that misses it scans the next fold too, until a
+    /// [`resize`](Self::resize) moves every entry home.
+    ///
+    /// Writers of a borrowing fold hold the next fold's lock as well. Every
+    /// put then locks its fold, as with
+    /// [`with_hit_decay`](Self::with_hit_decay), and an eviction listener
+    /// must write to neither fold. Has no effect with one fold or with
+    /// [`with_max_probe`](Self::with_max_probe), whose relocations assume a
+    /// fold holds only its own keys.
+    pub fn with_rebalancing(mut self, percent: usize) -> Self {
+        self.rebalance_percent = percent.min(100);
+        self
+    }
+
+    /// Registers `f` to be called with the key and value of every entry
+    /// that leaves the cache, whether evicted by a `put`, removed or
+    /// cleared.
+    ///
+    /// `f` runs after the entry's slot has been invalidated, so lookups of
+    /// the key from inside `f` already miss. It runs with the entry's fold
+    /// locked: it may read the cache freely and write to other folds, but
+    /// writing to the same fold deadlocks, and a slow `f` stalls that fold's
+    /// writers.
+    pub fn with_on_evict<F>(mut self, f: F) -> Self
+    where
+        F: Fn(K::Value, V::Value, EvictReason) + Send + Sync + 'static,
+    {
+        self.on_evict = Some(Box::new(f));
+        self
+    }
+
+    fn notify_evicted(&self, key: K::Value, value: V::Value, reason: EvictReason) {
+        if let Some(f) = &self.on_evict {
+            f(key, value, reason);
+        }
+    }
+
+    /// Replaces the clock that [`put_with_ttl`](Self::put_with_ttl)
+    /// deadlines are measured on. `f` returns milliseconds since any fixed
+    /// origin and must never go backwards. The default counts from the
+    /// cache's creation; tests can pass a manually advanced counter to expire
+    /// entries deterministically.
+    pub fn with_time_source<F>(mut self, f: F) -> Self
+    where
+        F: Fn() -> u64 + Send + Sync + 'static,
+    {
+        self.time_source = Box::new(f);
+        self
+    }
+
+    /// Whether an entry with `deadline` has expired. Reads the time source
+    /// only for entries that have a deadline.
+    fn expired(&self, deadline: u64) -> bool {
+        deadline != NO_DEADLINE && is_expired(deadline, (self.time_source)())
+    }
+
+    /// Why an entry with `deadline` that is leaving the cache for want of
+    /// room is gone.
+    fn capacity_reason(&self, deadline: u64) -> EvictReason {
+        if self.expired(deadline) {
+            EvictReason::Expired
+        } else {
+            EvictReason::Capacity
+        }
+    }
+
+    /// Registers the caller tags accepted by [`get_tagged`](Self::get_tagged)
+    /// and [`put_tagged`](Self::put_tagged), replacing any registered before.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than [`MAX_TAGS`](crate::MAX_TAGS) tags are given, or
+    /// if a tag is repeated or equal to [`UNTAGGED`].
+    pub fn with_tags(mut self, tags: &[&'static str]) -> Self {
+        if let Err(e) = check_tags(tags) {
+            panic!("{e}");
+        }
+        self.tags = tags.to_vec();
+        self.tag_stats = (0..self.num_folds * tags.len())
+            .map(|_| TagCounters::default())
+            .collect();
+        self
+    }
+
+    fn get_fold_index(&self, key: K::Value) -> usize {
+        self.hasher.fold_hash(K::as_usize(key)) % self.num_folds
+    }
+
+    /// Shared access to the slot arrays.
+    ///
+    /// Writers take it after their fold lock, never before, and every
+    /// caller drops it before running user code (eviction listeners and
+    /// `get_or_insert_with` closures). A `resize` waiting for exclusive
+    /// access therefore never deadlocks with user code that uses the cache.
+    fn slots(&self) -> RwLockReadGuard<'_, Slots<K, V>> {
+        self.slots.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The slots of `fold` that lookups scan.
+    fn probe_range(&self, slots: &Slots<K, V>, fold: usize) -> Range<usize> {
+        let range = slots.get_fold_range(fold);
+        if self.max_probe == 0 {
+            return range;
+        }
+        range.start..range.end.min(range.start + self.max_probe)
+    }
+
+    fn lock_fold(&self, fold: usize) -> MutexGuard<'_, FoldState> {
+        #[allow(unused_mut)]
+        let mut state = self.folds[fold]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        #[cfg(test)]
+        {
+            state.acquisitions += 1;
+        }
+        self.sweep_if_cleared(fold);
+        state
+    }
+
+    /// The fold that `fold` borrows slots from, if rebalancing is on.
+    fn lender_of(&self, fold: usize) -> Option<usize> {
+        (self.rebalance_percent != 0 && self.max_probe == 0 && self.num_folds > 1)
+            .then(|| (fold + 1) % self.num_folds)
+    }
+
+    /// Locks `fold` for a write to one of its keys, plus the fold it borrows
+    /// from while it is rebalancing or holds borrowed slots. Two folds are
+    /// always locked in index order, so neighbouring writers cannot
+    /// deadlock.
+    fn lock_key(&self, fold: usize) -> FoldLocks<'_> {
+        let state = self.lock_fold(fold);
+        let lender = self.lender_of(fold).filter(|_| {
+            // Both only change under this fold's lock.
+            state.rebalancing || self.borrowed[fold].load(Ordering::Relaxed)
+        });
+        match lender {
+            None => FoldLocks {
+                state,
+                lender: None,
+            },
+            Some(lender) if lender > fold => FoldLocks {
+                state,
+                lender: Some(self.lock_fold(lender)),
+            },
+            Some(lender) => {
+                drop(state);
+                let lender = Some(self.lock_fold(lender));
+                FoldLocks {
+                    state: self.lock_fold(fold),
+                    lender,
+                }
+            }
+        }
+    }
+
+    /// The slots `fold` may have lent entries to: the next fold's, once
+    /// `fold` has borrowed and unless that fold awaits a sweep. Empty
+    /// otherwise.
+    fn borrowed_range(&self, slots: &Slots<K, V>, fold: usize) -> Range<usize> {
+        match self.lender_of(fold) {
+            Some(lender)
+                if self.borrowed[fold].load(Ordering::Acquire) && self.fold_is_current(lender) =>
+            {
+                slots.get_fold_range(lender)
+            }
+            _ => 0..0,
+        }
+    }
+
+    /// Empties `fold` if a `clear` happened since it was last swept. Every
+    /// writer runs this as it takes the fold lock, so each writer sees
+    /// either no pre-clear entries or, if it is the sweeper, reclaims them.
+    fn sweep_if_cleared(&self, fold: usize) {
+        let current = self.generation.load(Ordering::Acquire);
+        if self.fold_generations[fold].load(Ordering::Relaxed) == current {
+            return;
+        }
+        let slots = self.slots();
+        let cleared: Vec<_> = slots
+            .get_fold_range(fold)
+            .filter_map(|i| slots.take_slot(i))
+            .map(|entry| (entry.key, entry.value))
+            .collect();
+        // Release: a reader that sees the new generation here also sees the
+        // slots wiped above.
+        self.fold_generations[fold].store(current, Ordering::Release);
+        drop(slots);
+        self.occupancy.dropped(cleared.len());
+        for (key, value) in cleared {
+            self.notify_evicted(key, value, EvictReason::Cleared);
+        }
+    }
+
+    /// Whether `fold` has been swept since the last `clear`. Until it has,
+    /// lock-free readers treat it as empty.
+    fn fold_is_current(&self, fold: usize) -> bool {
+        let current = self.generation.load(Ordering::Acquire);
+        self.fold_generations[fold].load(Ordering::Acquire) == current
+    }
+
+    /// Looks up `key` without taking any fold lock.
+    ///
+    /// The key is re-read after the value: if a concurrent `put` repurposed
+    /// the slot in between, the second read no longer matches and the slot is
+    /// skipped rather than returning another key's value. An entry whose time
+    /// to live has run out is a miss.
+    pub fn get(&self, key: K::Value) -> Option<V::Value> {
+        let fold = self.get_fold_index(key);
+        let slots = self.slots();
+        let found = self.lookup(&slots, fold, key);
+        let counters = &self.stats[fold];
+        if found.is_some() {
+            bump(&counters.hits);
+        } else {
+            bump(&counters.misses);
+            if self.has_entries_past_window(&slots, fold) {
+                bump(&counters.probe_overflows);
+            }
+        }
+        found
+    }
+
+    /// Records an access to `key` like [`get`](Self::get) does, so the
+    /// eviction policy treats it as just used, and returns whether it is
+    /// cached. Unlike `get` it counts neither a hit nor a miss in
+    /// [`stats`](Self::stats), so prefetchers can protect keys without
+    /// skewing the hit rate. Takes no lock.
+    pub fn touch(&self, key: K::Value) -> bool {
+        let fold = self.get_fold_index(key);
+        self.lookup(&self.slots(), fold, key).is_some()
+    }
+
+    /// Whether a bounded scan of `fold` left occupied slots unvisited.
+    /// Entries are packed, so checking the first slot past the window is
+    /// enough.
+    fn has_entries_past_window(&self, slots: &Slots<K, V>, fold: usize) -> bool {
+        let end = self.probe_range(slots, fold).end;
+        end < slots.get_fold_range(fold).end && slots.is_occupied(end)
+    }
+
+    /// Lock-free scan of `fold` for `key`, counting the access in the slot's
+    /// metadata but not in the cache stats.
+    fn lookup(&self, slots: &Slots<K, V>, fold: usize, key: K::Value) -> Option<V::Value> {
+        if !self.fold_is_current(fold) {
+            return None;
+        }
+        let borrowed = self.borrowed_range(slots, fold);
+        let candidates = slots.matching(self.probe_range(slots, fold), key);
+        candidates
+            .chain(slots.matching(borrowed, key))
+            .find_map(|i| {
+                let (value, deadline) = slots.read_slot(i, key)?;
+                if self.expired(deadline) {
+                    return None;
+                }
+                self.record_hit(slots, i, self.clock.load(Ordering::Relaxed));
+                Some(value)
+            })
+    }
+
+    /// Counts an access to slot `i` at logical time `now`: its hit count,
+    /// plus whatever the policy orders victims by. The count saturates, so
+    /// a key hot enough to reach `usize::MAX` cannot wrap to looking cold.
+    /// With [hit sampling](Self::with_hit_sampling), most hits leave the
+    /// count alone.
+    fn record_hit(&self, slots: &Slots<K, V>, i: usize, now: usize) {
+        match self.hit_sample_shift {
+            0 => slots.add_hits(i, 1),
+            shift if slots.hits.is_some() && sample_hit(shift) => slots.add_hits(i, 1 << shift),
+            _ => {}
+        }
+        match self.policy {
+            EvictionPolicy::Lru | EvictionPolicy::Lfu => slots.touch(i, now),
+            EvictionPolicy::Clock => slots.reference(i),
+            EvictionPolicy::Fifo | EvictionPolicy::Random => {}
+        }
+    }
+
+    /// Inserts or updates `key`.
+    ///
+    /// Updating a cached key, and inserting into a fold that has a free
+    /// slot, take no lock: the slot is claimed with a compare-and-swap on
+    /// its state. Only an insert into a full fold locks it, to repurpose the
+    /// slot chosen by the eviction policy. With
+    /// [`with_max_probe`](Self::with_max_probe) or
+    /// [`with_hit_decay`](Self::with_hit_decay) set, every put locks its
+    /// fold. Returns the previous value if the key was already cached.
+    ///
+    /// The entry never expires, even if it replaces one that was put with a
+    /// time to live. If every slot of the key's fold is
+    /// [pinned](Self::pin), a new key is not inserted; see
+    /// [`try_put`](Self::try_put).
+    pub fn put(&self, key: K::Value, value: V::Value) -> Option<V::Value> {
+        self.try_put(key, value).unwrap_or(None)
+    }
+
+    /// Like [`put`](Self::put), but reports a new key that could not be
+    /// inserted because every slot of its fold holds a pinned entry.
+    pub fn try_put(&self, key: K::Value, value: V::Value) -> Result<Option<V::Value>, CacheFull> {
+        self.put_until(key, value, NO_DEADLINE)
+            .map(|outcome| outcome.previous)
+    }
+
+    /// Like [`put`](Self::put), but returns the entry evicted to make room
+    /// for `key`, expired or not. `None` if the key was updated in place or
+    /// took a free slot.
+    ///
+    /// The pair is read from the victim slot after it is unpublished and
+    /// before it is reused, so it is always an entry that was put and never
+    /// a key matched with another entry's value. It is also passed to the
+    /// eviction listener.
+    pub fn put_returning(&self, key: K::Value, value: V::Value) -> Option<(K::Value, V::Value)> {
+        self.put_until(key, value, NO_DEADLINE)
+            .ok()
+            .and_then(|outcome| outcome.evicted)
+    }
+
+    /// Like [`put`](Self::put), but the entry expires once `ttl` has passed
+    /// on the [time source](Self::with_time_source).
+    ///
+    /// An expired entry is a miss for [`get`](Self::get) and
+    /// [`contains_key`](Self::contains_key), and a put into a full fold
+    /// evicts expired entries before any other. Its slot is only freed by
+    /// such a put, by [`remove`](Self::remove) or by
+    /// [`purge_expired`](Self::purge_expired), so until then it still counts
+    /// towards [`len`](Self::len).
+    pub fn put_with_ttl(&self, key: K::Value, value: V::Value, ttl: Duration) -> Option<V::Value> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let deadline = (self.time_source)().saturating_add(ttl).max(1);
+        self.put_until(key, value, deadline)
+            .map_or(None, |outcome| outcome.previous)
+    }
+
+    /// Like [`put`](Self::put), but a new entry starts with `initial_hits`
+    /// hits instead of one, so a cache warmed with entries that were hot
+    /// elsewhere does not evict them for the first cold keys put after
+    /// them. An update adds `initial_hits` to the entry's count, on top of
+    /// the hit every update records.
+    ///
+    /// The count only steers eviction under policies that [keep
+    /// one](EvictionPolicy::counts_hits); otherwise this is `put`. It always
+    /// locks the key's fold.
+    pub fn put_with_hits(
+        &self,
+        key: K::Value,
+        value: V::Value,
+        initial_hits: usize,
+    ) -> Option<V::Value> {
+        let fold = self.get_fold_index(key);
+        let mut locks = self.lock_key(fold);
+        let cached = self.find_locked(&self.slots(), fold, key).is_some();
+        let previous = self
+            .put_locked(&mut locks, fold, key, value, NO_DEADLINE)
+            .map_or(None, |outcome| outcome.previous);
+        let slots = self.slots();
+        if let Some(i) = self.find_locked(&slots, fold, key) {
+            if cached {
+                slots.add_hits(i, initial_hits);
+            } else {
+                slots.set_hit_count(i, initial_hits);
+            }
+        }
+        previous
+    }
+
+    /// Body of [`try_put`](Self::try_put),
+    /// [`put_returning`](Self::put_returning) and
+    /// [`put_with_ttl`](Self::put_with_ttl).
+    fn put_until(
+        &self,
+        key: K::Value,
+        value: V::Value,
+        deadline: u64,
+    ) -> Result<PutOutcome<K::Value, V::Value>, CacheFull> {
+        let fold = self.get_fold_index(key);
+        if let Some(previous) = self.try_put_unlocked(fold, key, value, deadline) {
+            return Ok(PutOutcome {
+                previous,
+                evicted: None,
+            });
+        }
+        let mut locks = self.lock_key(fold);
+        self.put_locked(&mut locks, fold, key, value, deadline)
+    }
+
+    /// [`put`](Self::put) without the fold lock, by updating `key` in place
+    /// or claiming a free slot for it. `None` if the fold must be locked
+    /// instead: it is full, awaits a sweep after `clear`, or its writers
+    /// keep entries packed, count puts for hit decay or may borrow slots.
+    fn try_put_unlocked(
+        &self,
+        fold: usize,
+        key: K::Value,
+        value: V::Value,
+        deadline: u64,
+    ) -> Option<Option<V::Value>> {
+        if self.max_probe != 0
+            || self.hit_decay_period != 0
+            || self.rebalance_percent != 0
+            || !self.fold_is_current(fold)
+        {
+            return None;
+        }
+        let slots = self.slots();
+        let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        let entry = SlotEntry {
+            key,
+            value,
+            hits: 1,
+            last_access: now,
+            deadline,
+            pinned: false,
+            referenced: false,
+        };
+        let counters = &self.stats[fold];
+        loop {
+            let range = slots.get_fold_range(fold);
+            let update = self.update_in_place(&slots, range.clone(), key, value, deadline, now);
+            if let Some((_, old)) = update {
+                bump(&counters.updates);
+                return Some(old);
+            }
+            let slot = slots.vacant(range).find(|&i| slots.claim_empty(i))?;
+            if self.publish(&slots, fold, slot, &entry) {
+                bump(&counters.insertions);
+                self.occupancy.inserted(false);
+                return Some(None);
+            }
+            // The rival may still be pending; give it a chance to publish.
+            hint::spin_loop();
+        }
+    }
+
+    /// Replaces the value and deadline of `key` if a slot in `range` holds
+    /// it, returning its slot and the old value, or `None` for it if it had
+    /// expired. Needs no lock: the slot is marked [`UPDATING`] for the
+    /// write, so it cannot be evicted or moved meanwhile.
+    fn update_in_place(
+        &self,
+        slots: &Slots<K, V>,
+        range: Range<usize>,
+        key: K::Value,
+        value: V::Value,
+        deadline: u64,
+        now: usize,
+    ) -> Option<(usize, Option<V::Value>)> {
+        loop {
+            let i = slots
+                .matching(range.clone(), key)
+                .find(|&i| slots.is_occupied(i))?;
+            if !slots.claim_published(i, UPDATING) {
+                continue;
+            }
+            let old = if slots.keys[i].load(Ordering::Relaxed) == key {
+                let old = slots.values[i].load(Ordering::Relaxed);
+                let old_deadline = slots.deadlines[i].load(Ordering::Relaxed);
+                slots.values[i].store(value, Ordering::Release);
+                slots.deadlines[i].store(deadline, Ordering::Relaxed);
+                self.record_hit(slots, i, now);
+                Some((!self.expired(old_deadline)).then_some(old))
+            } else {
+                // Repurposed for another key between the scan and the claim.
+                None
+            };
+            slots.state[i].store(FULL, Ordering::Release);
+            if let Some(old) = old {
+                return Some((i, old));
+            }
+        }
+    }
+
+    /// Publishes `entry` in the claimed slot `i`, unless another slot of
+    /// `fold` holds or is inserting the same key. Returns `false`, with the
+    /// slot freed again, if it lost that race; the caller then retries,
+    /// normally as an update.
+    ///
+    /// The slot is marked [`PENDING`] before the rest of the fold is scanned,
+    /// with sequentially consistent stores and loads, so of two racing
+    /// inserters at least one sees the other. A published rival wins, and of
+    /// two pending ones the lower slot does. The lower one waits for the
+    /// higher to give way, because the higher may not have seen it.
+    fn publish(
+        &self,
+        slots: &Slots<K, V>,
+        fold: usize,
+        i: usize,
+        entry: &SlotEntry<K::Value, V::Value>,
+    ) -> bool {
+        let key = entry.key;
+        slots.values[i].store(entry.value, Ordering::Relaxed);
+        slots.set_hit_count(i, entry.hits);
+        slots.counters[i]
+            .last_access
+            .store(entry.last_access, Ordering::Relaxed);
+        slots.counters[i]
+            .referenced
+            .store(entry.referenced, Ordering::Relaxed);
+        slots.deadlines[i].store(entry.deadline, Ordering::Relaxed);
+        slots.pinned[i].store(entry.pinned, Ordering::Relaxed);
+        slots.keys[i].store(key, Ordering::SeqCst);
+        slots.state[i].store(PENDING, Ordering::SeqCst);
+        // Redundant with the `SeqCst` accesses in the C++ model, but loom
+        // treats those as `AcqRel` and only orders this store-load pair
+        // through a fence.
+        fence(Ordering::SeqCst);
+        for j in slots.get_fold_range(fold).filter(|&j| j != i) {
+            loop {
+                let state = slots.state[j].load(Ordering::SeqCst);
+                if state == EMPTY || state == CLAIMED || slots.keys[j].load(Ordering::SeqCst) != key
+                {
+                    break;
+                }
+                if state == PENDING && j > i {
+                    hint::spin_loop();
+                    continue;
+                }
+                // Stop counting as pending before the key is zeroed, so no
+                // inserter of the zero key mistakes this slot for a rival.
+                slots.state[i].store(CLAIMED, Ordering::SeqCst);
+                slots.clear_slot(i);
+                slots.release(i);
+                return false;
+            }
+        }
+        slots.publish_slot(i);
+        true
+    }
+
+    /// Indices of `keys`, paired with their fold and sorted by fold. The sort
+    /// is stable, so items for one fold keep their order.
+    fn by_fold(&self, keys: impl Iterator<Item = K::Value>) -> Vec<(usize, usize)> {
+        let mut order: Vec<_> = keys
+            .enumerate()
+            .map(|(i, key)| (self.get_fold_index(key), i))
+            .collect();
+        order.sort_by_key(|&(fold, _)| fold);
+        order
+    }
+
+    /// Looks up every key in `keys`, returning the results in the same
+    /// order. Each lookup behaves like [`get`](Self::get); keys are visited
+    /// fold by fold so each fold's slots are scanned while still in cache.
+    pub fn get_many(&self, keys: &[K::Value]) -> Vec<Option<V::Value>> {
+        let mut out = vec![None; keys.len()];
+        let slots = self.slots();
+        for &(fold, i) in &self.by_fold(keys.iter().copied()) {
+            let found = self.lookup(&slots, fold, keys[i]);
+            bump(if found.is_some() {
+                &self.stats[fold].hits
+            } else {
+                &self.stats[fold].misses
+            });
+            out[i] = found;
+        }
+        out
+    }
+
+    /// Inserts or updates every item, taking each fold's lock once for all
+    /// of that fold's items.
+    ///
+    /// The result is the same as calling [`put`](Self::put) for each item in
+    /// order, including which entries are evicted; only the interleaving
+    /// with concurrent writers differs.
+    pub fn put_many(&self, items: &[(K::Value, V::Value)]) {
+        let order = self.by_fold(items.iter().map(|&(key, _)| key));
+        for group in order.chunk_by(|a, b| a.0 == b.0) {
+            let fold = group[0].0;
+            let mut locks = self.lock_key(fold);
+            for &(_, i) in group {
+                let (key, value) = items[i];
+                // Like `put`, a key whose fold is all pinned is dropped.
+                let _ = self.put_locked(&mut locks, fold, key, value, NO_DEADLINE);
+            }
+        }
+    }
+
+    /// Returns the value for `key`, computing and inserting it with `f` if it
+    /// is absent.
+    ///
+    /// A hit is served lock-free like [`get`](Self::get). On a miss the fold
+    /// lock is taken and the fold re-checked, so when several threads miss on
+    /// the same key at once `f` runs only once and every caller gets that
+    /// value. `f` runs while the key's fold is locked: writers to that fold
+    /// wait, while readers and other folds are unaffected. `f` must not write
+    /// to the same fold of this cache or it will deadlock.
+    pub fn get_or_insert_with<F>(&self, key: K::Value, f: F) -> V::Value
+    where
+        F: FnOnce() -> V::Value,
+    {
+        let fold = self.get_fold_index(key);
+        if let Some(value) = self.lookup(&self.slots(), fold, key) {
+            bump(&self.stats[fold].hits);
+            return value;
+        }
+        let mut locks = self.lock_key(fold);
+        {
+            let slots = self.slots();
+            let live = self
+                .find_locked(&slots, fold, key)
+                .filter(|&i| !self.expired(slots.deadlines[i].load(Ordering::Relaxed)));
+            if let Some(i) = live {
+                // Another writer inserted it while we waited for the lock.
+                bump(&self.stats[fold].hits);
+                self.record_hit(&slots, i, self.clock.load(Ordering::Relaxed));
+                return slots.values[i].load(Ordering::Relaxed);
+            }
+        }
+        bump(&self.stats[fold].misses);
+        let value = f();
+        // The value is returned even if a fold of pinned entries has no room
+        // to cache it.
+        let _ = self.put_locked(&mut locks, fold, key, value, NO_DEADLINE);
+        value
+    }
+
+    /// Replaces the value of `key` with `f` applied to it, returning the new
+    /// value, or `None` if the key is absent or expired.
+    ///
+    /// The read, `f` and the write happen under the key's fold lock with the
+    /// slot marked [`UPDATING`], so no other writer, locked or lock-free, can
+    /// interleave: concurrent `modify` calls on one key never lose an
+    /// update. `f` runs while the cache is locked and must not call back into
+    /// it. The deadline is kept, and the call counts as an update and an
+    /// access for eviction.
+    pub fn modify<F>(&self, key: K::Value, f: F) -> Option<V::Value>
+    where
+        F: FnOnce(V::Value) -> V::Value,
+    {
+        let fold = self.get_fold_index(key);
+        let _locks = self.lock_key(fold);
+        self.modify_locked(&self.slots(), fold, key, f)
+    }
+
+    /// Like [`modify`](Self::modify), but inserts `init` if `key` is absent
+    /// or expired. Returns the value now stored, which is `init` on an
+    /// insert. An insert into a fold whose slots are all pinned is dropped,
+    /// as with [`put`](Self::put).
+    pub fn upsert<F>(&self, key: K::Value, init: V::Value, f: F) -> V::Value
+    where
+        F: FnOnce(V::Value) -> V::Value,
+    {
+        let fold = self.get_fold_index(key);
+        let mut locks = self.lock_key(fold);
+        if let Some(value) = self.modify_locked(&self.slots(), fold, key, f) {
+            return value;
+        }
+        let _ = self.put_locked(&mut locks, fold, key, init, NO_DEADLINE);
+        init
+    }
+
+    /// Body of [`modify`](Self::modify); the caller holds the locks of
+    /// [`lock_key`](Self::lock_key).
+    fn modify_locked<F>(
+        &self,
+        slots: &Slots<K, V>,
+        fold: usize,
+        key: K::Value,
+        f: F,
+    ) -> Option<V::Value>
+    where
+        F: FnOnce(V::Value) -> V::Value,
+    {
+        let i = self.find_locked(slots, fold, key)?;
+        // Only a lock-free update can hold the published slot, and it keeps
+        // the key, so the claim waits for it and then succeeds.
+        let claimed = slots.claim_published(i, UPDATING);
+        debug_assert!(claimed);
+        let value = if self.expired(slots.deadlines[i].load(Ordering::Relaxed)) {
+            None
+        } else {
+            let value = f(slots.values[i].load(Ordering::Relaxed));
+            slots.values[i].store(value, Ordering::Release);
+            self.record_hit(slots, i, self.clock.fetch_add(1, Ordering::Relaxed) + 1);
+            bump(&self.stats[fold].updates);
+            Some(value)
+        };
+        slots.state[i].store(FULL, Ordering::Release);
+        value
+    }
+
+    /// Slot holding `key` in `fold` or a slot it borrowed. Caller holds the
+    /// locks of [`lock_key`](Self::lock_key): keys only change under them,
+    /// so Relaxed loads are enough on the writer side.
+    fn find_locked(&self, slots: &Slots<K, V>, fold: usize, key: K::Value) -> Option<usize> {
+        slots
+            .matching(slots.get_fold_range(fold), key)
+            .chain(slots.matching(self.borrowed_range(slots, fold), key))
+            .find(|&i| slots.is_occupied(i))
+    }
+
+    /// Body of [`put`](Self::put); `locks` are the key's, from
+    /// [`lock_key`](Self::lock_key).
+    fn put_locked(
+        &self,
+        locks: &mut FoldLocks<'_>,
+        fold: usize,
+        key: K::Value,
+        value: V::Value,
+        deadline: u64,
+    ) -> Result<PutOutcome<K::Value, V::Value>, CacheFull> {
+        let can_borrow = locks.lender.is_some();
+        let state = &mut *locks.state;
+        let slots = self.slots();
+        let range = slots.get_fold_range(fold);
+        let borrowed = self.borrowed_range(&slots, fold);
+        let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+        let entry = SlotEntry {
+            key,
+            value,
+            hits: 1,
+            last_access: now,
+            deadline,
+            pinned: false,
+            referenced: false,
+        };
+        self.maybe_decay(&slots, state, range.clone());
+        let counters = &self.stats[fold];
+        let mut evicted = Vec::new();
+        let mut counted = false;
+
+        let previous = loop {
+            let update = self.update_in_place(&slots, range.clone(), key, value, deadline, now);
+            if let Some((i, old)) = update {
+                bump(&counters.updates);
+                self.bring_into_window(&slots, state, fold, i);
+                break Ok(old);
+            }
+            let update = self.update_in_place(&slots, borrowed.clone(), key, value, deadline, now);
+            if let Some((_, old)) = update {
+                bump(&counters.updates);
+                break Ok(old);
+            }
+            let free = slots.vacant(range.clone()).find(|&i| slots.claim_empty(i));
+            if !counted {
+                counted = true;
+                self.record_pressure(state, free.is_none());
+            }
+            let lent = match free {
+                None if can_borrow && state.rebalancing => self.claim_lent(&slots, fold),
+                _ => None,
+            };
+            let (target, slot, victim) = match (free, lent) {
+                (Some(i), _) => (fold, i, false),
+                (None, Some((lender, i))) => (lender, i, false),
+                (None, None) => {
+                    // Slots that lock-free inserters are still filling are
+                    // not candidates, and neither are pinned ones. Pins and
+                    // published entries only change under the fold lock, so
+                    // a fold of published, pinned entries stays full.
+                    let Some(victim) = self.select_victim(&slots, state, range.clone()) else {
+                        if range.clone().all(|i| slots.is_occupied(i)) {
+                            break Err(CacheFull);
+                        }
+                        hint::spin_loop();
+                        continue;
+                    };
+                    // Unpublish first so a concurrent reader that already
+                    // matched the old key fails its re-check instead of
+                    // returning the new value.
+                    let old = slots
+                        .claim_for_reuse(victim)
+                        .expect("only the fold lock's holder unpublishes entries");
+                    bump(&counters.evictions);
+                    evicted.push((old.key, old.value, self.capacity_reason(old.deadline)));
+                    (fold, victim, true)
+                }
+            };
+            if self.publish(&slots, target, slot, &entry) {
+                bump(&counters.insertions);
+                self.occupancy.inserted(victim);
+                if target == fold {
+                    self.bring_into_window(&slots, state, fold, slot);
+                } else {
+                    bump(&counters.rebalanced);
+                }
+                break Ok(None);
+            }
+            if victim {
+                // A lock-free put inserted the key meanwhile; the victim is
+                // gone all the same.
+                self.occupancy.dropped(1);
+            }
+            hint::spin_loop();
+        };
+        drop(slots);
+        // At most one victim in practice: after a lost race the key is
+        // cached, so the retry updates it in place.
+        let first = evicted.first().map(|&(key, value, _)| (key, value));
+        for (old_key, old_value, reason) in evicted {
+            self.notify_evicted(old_key, old_value, reason);
+        }
+        previous.map(|previous| PutOutcome {
+            previous,
+            evicted: first,
+        })
+    }
+
+    /// Counts a new key against `fold`'s rebalancing window, as `full` if it
+    /// found no free slot. At the end of each window the fold starts or
+    /// stops borrowing. Caller holds the fold lock.
+    fn record_pressure(&self, state: &mut FoldState, full: bool) {
+        if self.rebalance_percent == 0 {
+            return;
+        }
+        state.window_inserts += 1;
+        state.window_full += usize::from(full);
+        if state.window_inserts == REBALANCE_WINDOW {
+            state.rebalancing =
+                state.window_full * 100 >= self.rebalance_percent * REBALANCE_WINDOW;
+            state.window_inserts = 0;
+            state.window_full = 0;
+        }
+    }
+
+    /// Claims a free slot of the fold that `fold` borrows from, returning
+    /// that fold and the slot. `fold` is marked as borrowing before the
+    /// caller publishes into it, so a lookup that starts after the put
+    /// returns scans the slot. Caller holds both locks of
+    /// [`lock_key`](Self::lock_key).
+    fn claim_lent(&self, slots: &Slots<K, V>, fold: usize) -> Option<(usize, usize)> {
+        let lender = self.lender_of(fold).filter(|&l| self.fold_is_current(l))?;
+        let i = slots
+            .vacant(slots.get_fold_range(lender))
+            .find(|&i| slots.claim_empty(i))?;
+        self.borrowed[fold].store(true, Ordering::Release);
+        Some((lender, i))
+    }
+
+    /// If slot `i` lies past `fold`'s probe window, swaps its entry with the
+    /// window's eviction candidate. Caller holds the fold lock.
+    fn bring_into_window(&self, slots: &Slots<K, V>, state: &mut FoldState, fold: usize, i: usize) {
+        let window = self.probe_range(slots, fold);
+        if i < window.end {
+            return;
+        }
+        // A window of pinned entries cannot make room; the key stays
+        // reachable by a full scan, such as `contains_key`.
+        let Some(w) = self.select_victim(slots, state, window) else {
+            return;
+        };
+        let displaced = slots.take_slot(w).expect("victims are occupied");
+        slots.relocate(i, w);
+        slots.claim_free(i);
+        slots.put_slot(i, displaced);
+        bump(&self.stats[fold].relocations);
+    }
+
+    /// Halves the fold's hit counts once every `hit_decay_period` puts.
+    /// Caller holds the fold lock.
+    fn maybe_decay(&self, slots: &Slots<K, V>, state: &mut FoldState, range: Range<usize>) {
+        if self.hit_decay_period == 0 || slots.hits.is_none() {
+            return;
+        }
+        state.puts_since_decay += 1;
+        if state.puts_since_decay < self.hit_decay_period {
+            return;
+        }
+        state.puts_since_decay = 0;
+        for i in range {
+            // `get` bumps counts without the lock; a CAS loop keeps those
+            // concurrent increments instead of overwriting them.
+            slots.halve_hit_count(i);
+        }
+    }
+
+    /// Picks the occupied, unpinned slot to repurpose in a full fold: an
+    /// expired entry if there is one, else the policy's choice. `None` if no
+    /// slot in `range` holds an unpinned published entry. Under
+    /// [`EvictionPolicy::Clock`] only the slots the hand passes are checked
+    /// for expiry, so the search stays bounded. Caller holds the fold lock.
+    fn select_victim(
+        &self,
+        slots: &Slots<K, V>,
+        state: &mut FoldState,
+        range: Range<usize>,
+    ) -> Option<usize> {
+        let occupied = range
+            .clone()
+            .filter(|&i| slots.is_occupied(i) && !slots.is_pinned(i));
+        if self.policy != EvictionPolicy::Clock {
+            let expired = occupied
+                .clone()
+                .find(|&i| self.expired(slots.deadlines[i].load(Ordering::Relaxed)));
+            if expired.is_some() {
+                return expired;
+            }
+        }
+        let counters = |i: usize| &slots.counters[i];
+        match self.policy {
+            // Fifo never touches a slot after filling it, so its stamp is
+            // the fill time.
+            EvictionPolicy::Lru | EvictionPolicy::Fifo => {
+                occupied.min_by_key(|&i| counters(i).last_access.load(Ordering::Relaxed))
+            }
+            EvictionPolicy::Lfu => occupied.min_by_key(|&i| slots.hit_count(i)),
+            EvictionPolicy::Random => {
+                let n = occupied.clone().count();
+                if n == 0 {
+                    return None;
+                }
+                occupied.clone().nth(state.next_random() as usize % n)
+            }
+            EvictionPolicy::Clock => self.sweep_clock(slots, state, range),
+        }
+    }
+
+    /// Advances the fold's clock hand over `range` to the first occupied,
+    /// unpinned slot that has expired or whose reference bit is clear,
+    /// clearing the set bits it passes. After [`CLOCK_SWEEP_LIMIT`] slots,
+    /// or a lap of a smaller fold, it settles for the first candidate it
+    /// passed, whose bit it cleared; it only goes further while it has seen
+    /// none, as in a fold of mostly pinned entries. Caller holds the fold
+    /// lock.
+    fn sweep_clock(
+        &self,
+        slots: &Slots<K, V>,
+        state: &mut FoldState,
+        range: Range<usize>,
+    ) -> Option<usize> {
+        let len = range.len();
+        let mut first = None;
+        for step in 0..len {
+            if step >= CLOCK_SWEEP_LIMIT && first.is_some() {
+                break;
+            }
+            let offset = (state.hand + step) % len;
+            let i = range.start + offset;
+            if !slots.is_occupied(i) || slots.is_pinned(i) {
+                continue;
+            }
+            if self.expired(slots.deadlines[i].load(Ordering::Relaxed))
+                || !slots.counters[i].referenced.swap(false, Ordering::Relaxed)
+            {
+                state.hand = offset + 1;
+                return Some(i);
+            }
+            first.get_or_insert((offset, i));
+        }
+        let (offset, i) = first?;
+        state.hand = offset + 1;
+        Some(i)
+    }
+
+    /// Exempts `key`'s entry from eviction until it is
+    /// [unpinned](Self::unpin), returning whether the key is cached.
+    ///
+    /// A pinned entry is never chosen as a victim, so once every slot of a
+    /// fold is pinned, new keys for that fold are not inserted (see
+    /// [`try_put`](Self::try_put)). Updates keep the pin; [`remove`],
+    /// [`clear`] and expiry still drop the entry.
+    ///
+    /// [`remove`]: Self::remove
+    /// [`clear`]: Self::clear
+    pub fn pin(&self, key: K::Value) -> bool {
+        self.set_pinned(key, true)
+    }
+
+    /// Makes `key`'s entry evictable again, returning whether the key is
+    /// cached.
+    pub fn unpin(&self, key: K::Value) -> bool {
+        self.set_pinned(key, false)
+    }
+
+    fn set_pinned(&self, key: K::Value, pinned: bool) -> bool {
+        let fold = self.get_fold_index(key);
+        let _locks = self.lock_key(fold);
+        let slots = self.slots();
+        let live = self
+            .find_locked(&slots, fold, key)
+            .filter(|&i| !self.expired(slots.deadlines[i].load(Ordering::Relaxed)));
+        let Some(i) = live else {
+            return false;
+        };
+        slots.pinned[i].store(pinned, Ordering::Relaxed);
+        true
+    }
+
+    /// Removes `key`, returning its value if it was present. An expired
+    /// entry is removed too, but reported as [`EvictReason::Expired`] and
+    /// not returned.
+    pub fn remove(&self, key: K::Value) -> Option<V::Value> {
+        let fold = self.get_fold_index(key);
+        let _locks = self.lock_key(fold);
+        let entry = {
+            let slots = self.slots();
+            let i = self.find_locked(&slots, fold, key)?;
+            self.remove_slot(&slots, fold, i)
+        };
+        self.occupancy.dropped(1);
+        if self.expired(entry.deadline) {
+            self.notify_evicted(key, entry.value, EvictReason::Expired);
+            return None;
+        }
+        self.notify_evicted(key, entry.value, EvictReason::Removed);
+        Some(entry.value)
+    }
+
+    /// Removes and returns the entry with the fewest hits in the next
+    /// non-empty fold, visiting folds round-robin.
+    ///
+    /// Only that one fold is searched, so the entry is the least used of its
+    /// fold rather than of the whole cache; successive calls spread the
+    /// trimming over every fold. Pinned entries are skipped. Returns `None`
+    /// once the cache holds no unpinned entry. Under policies that keep no
+    /// [hit counts](EvictionPolicy::counts_hits), the entry filled longest
+    /// ago is taken instead. The entry is passed to the eviction listener as
+    /// [`EvictReason::Removed`].
+    pub fn pop_least_used(&self) -> Option<(K::Value, V::Value)> {
+        let start = self.pop_cursor.fetch_add(1, Ordering::Relaxed);
+        (0..self.num_folds).find_map(|offset| self.pop_fold_lru((start + offset) % self.num_folds))
+    }
+
+    /// Removes and returns the least used entry of `fold`, picked as by
+    /// [`pop_least_used`](Self::pop_least_used), with only that fold
+    /// locked. Together with [`fold_len`](Self::fold_len) it drains one
+    /// fold to a target size. Returns `None` if the fold holds no unpinned
+    /// entry or is not below [`num_folds`](Self::num_folds).
+    pub fn pop_fold_lru(&self, fold: usize) -> Option<(K::Value, V::Value)> {
+        if fold >= self.num_folds {
+            return None;
+        }
+        let _guard = self.lock_fold(fold);
+        let SlotEntry { key, value, .. } = {
+            let slots = self.slots();
+            let coldest = slots
+                .get_fold_range(fold)
+                .filter(|&i| slots.is_occupied(i) && !slots.is_pinned(i))
+                .min_by_key(|&i| {
+                    slots
+                        .hit_count(i)
+                        .unwrap_or_else(|| slots.counters[i].last_access.load(Ordering::Relaxed))
+                })?;
+            self.remove_slot(&slots, fold, coldest)
+        };
+        self.occupancy.dropped(1);
+        self.notify_evicted(key, value, EvictReason::Removed);
+        Some((key, value))
+    }
+
+    /// Empties the occupied slot `i` of `fold`, compacting the fold if
+    /// lookups are bounded, and returns its entry. Caller holds the fold
+    /// lock.
+    fn remove_slot(
+        &self,
+        slots: &Slots<K, V>,
+        fold: usize,
+        i: usize,
+    ) -> SlotEntry<K::Value, V::Value> {
+        let entry = slots
+            .take_slot(i)
+            .expect("only the fold lock's holder unpublishes entries");
+        if self.max_probe != 0 {
+            self.fill_hole(slots, fold, i);
+        }
+        entry
+    }
+
+    /// Frees the slot of every expired entry and returns how many there
+    /// were, each passed to the eviction listener as
+    /// [`EvictReason::Expired`].
+    ///
+    /// Expired entries are already misses, but keep their slots until a
+    /// put into a full fold reuses them. Purging periodically frees them
+    /// sooner, so [`len`](Self::len) and free-slot inserts reflect only live
+    /// entries. Folds are locked one at a time.
+    pub fn purge_expired(&self) -> usize {
+        let mut purged = 0;
+        for fold in 0..self.num_folds {
+            let _guard = self.lock_fold(fold);
+            let expired: Vec<_> = {
+                let slots = self.slots();
+                let now = (self.time_source)();
+                // Backwards, so compaction only ever moves entries that were
+                // already checked into the holes.
+                slots
+                    .get_fold_range(fold)
+                    .rev()
+                    .filter_map(|i| {
+                        let entry = slots.take_if_expired(i, now)?;
+                        if self.max_probe != 0 {
+                            self.fill_hole(&slots, fold, i);
+                        }
+                        Some(entry)
+                    })
+                    .collect()
+            };
+            self.occupancy.dropped(expired.len());
+            purged += expired.len();
+            for entry in expired {
+                self.notify_evicted(entry.key, entry.value, EvictReason::Expired);
+            }
+        }
+        purged
+    }
+
+    /// Removes every entry for which `pred(key, value)` returns `true`, and
+    /// returns how many live ones there were.
+    ///
+    /// Folds are locked one at a time, so the rest of the cache stays
+    /// available throughout. Each removed entry is passed to the eviction
+    /// listener as [`EvictReason::Removed`], the reason
+    /// [`remove`](Self::remove) and every other explicit removal report, or
+    /// as [`EvictReason::Expired`] if it had already expired. `pred` runs
+    /// with the fold locked and must not call back into the cache. A
+    /// lock-free update racing with the call may leave `pred` looking at the
+    /// value just before it.
+    pub fn remove_if<F>(&self, pred: F) -> usize
+    where
+        F: Fn(K::Value, V::Value) -> bool,
+    {
+        let mut removed = 0;
+        for fold in 0..self.num_folds {
+            let _guard = self.lock_fold(fold);
+            let matched: Vec<_> = {
+                let slots = self.slots();
+                // Backwards, so compaction only ever moves entries that were
+                // already checked into the holes.
+                slots
+                    .get_fold_range(fold)
+                    .rev()
+                    .filter(|&i| {
+                        slots.is_occupied(i)
+                            && pred(
+                                slots.keys[i].load(Ordering::Relaxed),
+                                slots.values[i].load(Ordering::Acquire),
+                            )
+                    })
+                    .map(|i| self.remove_slot(&slots, fold, i))
+                    .collect()
+            };
+            self.occupancy.dropped(matched.len());
+            for entry in matched {
+                if self.expired(entry.deadline) {
+                    self.notify_evicted(entry.key, entry.value, EvictReason::Expired);
+                } else {
+                    removed += 1;
+                    self.notify_evicted(entry.key, entry.value, EvictReason::Removed);
+                }
+            }
+        }
+        removed
+    }
+
+    /// Fills the hole at slot `i` with `fold`'s last entry, keeping the
+    /// fold's entries packed at its start. Caller holds the fold lock.
+    fn fill_hole(&self, slots: &Slots<K, V>, fold: usize, i: usize) {
+        let last = slots
+            .get_fold_range(fold)
+            .rev()
+            .find(|&j| slots.is_occupied(j));
+        if let Some(last) = last.filter(|&last| last > i) {
+            slots.relocate(last, i);
+            bump(&self.stats[fold].relocations);
+        }
+    }
+
+    /// Whether `key` is cached and unexpired, without counting as an
+    /// access, like [`peek`](Self::peek).
+    pub fn contains_key(&self, key: K::Value) -> bool {
+        self.peek(key).is_some()
+    }
+
+    /// Returns the value for `key` without counting as an access: neither
+    /// the eviction policy nor [`stats`](Self::stats) sees it. Reads slots
+    /// the same way as [`get`](Self::get), but scans the whole fold even
+    /// when lookups are bounded by [`with_max_probe`](Self::with_max_probe).
+    pub fn peek(&self, key: K::Value) -> Option<V::Value> {
+        let fold = self.get_fold_index(key);
+        let slots = self.slots();
+        if !self.fold_is_current(fold) {
+            return None;
+        }
+        let found = slots
+            .matching(slots.get_fold_range(fold), key)
+            .chain(slots.matching(self.borrowed_range(&slots, fold), key))
+            .find_map(|i| {
+                let (value, deadline) = slots.read_slot(i, key)?;
+                (!self.expired(deadline)).then_some(value)
+            });
+        found
+    }
+
+    /// Empties the cache.
+    ///
+    /// The clear takes effect at a single instant for every fold: a lookup
+    /// that starts after it finds none of the entries written before it,
+    /// even while folds are still being swept. The folds are then swept one
+    /// at a time, each with its lock held. Writers reclaim a fold themselves
+    /// if they reach it first, so the [`EvictReason::Cleared`] notification
+    /// for an entry may run on the thread of a concurrent `put` or `remove`.
+    pub fn clear(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        for fold in 0..self.num_folds {
+            drop(self.lock_fold(fold));
+        }
+    }
+
+    /// Empties one fold, leaving the others untouched, and returns how many
+    /// entries it dropped. Returns 0 if `fold` is not below
+    /// [`num_folds`](Self::num_folds).
+    ///
+    /// Only `fold`'s lock is taken. Unlike [`clear`](Self::clear), the slots
+    /// are emptied right away rather than swept later, so a lock-free put
+    /// racing with the call may survive it. Each entry, expired or not, is
+    /// passed to the eviction listener as [`EvictReason::Cleared`]. With
+    /// [rebalancing](Self::with_rebalancing), entries another fold borrowed
+    /// these slots for go too, while `fold`'s own entries in slots it
+    /// borrowed stay.
+    pub fn clear_fold(&self, fold: usize) -> usize {
+        if fold >= self.num_folds {
+            return 0;
+        }
+        let _guard = self.lock_fold(fold);
+        let cleared: Vec<_> = {
+            let slots = self.slots();
+            slots
+                .get_fold_range(fold)
+                .filter_map(|i| slots.take_slot(i))
+                .collect()
+        };
+        self.occupancy.dropped(cleared.len());
+        for entry in &cleared {
+            self.notify_evicted(entry.key, entry.value, EvictReason::Cleared);
+        }
+        cleared.len()
+    }
+
+    /// How often `key` has been looked up since it was put, or `None` if it
+    /// is not cached or the policy keeps no [hit
+    /// counts](EvictionPolicy::counts_hits). Reading the count is not a hit
+    /// itself. Takes the key's fold lock.
+    pub fn hit_count(&self, key: K::Value) -> Option<usize> {
+        let fold = self.get_fold_index(key);
+        let _locks = self.lock_key(fold);
+        let slots = self.slots();
+        let i = self.find_locked(&slots, fold, key)?;
+        if self.expired(slots.deadlines[i].load(Ordering::Relaxed)) {
+            return None;
+        }
+        slots.hit_count(i)
+    }
+
+    /// The `n` cached keys with the most hits, with their counts, hottest
+    /// first. Ties go to the key in the lower slot. Empty if the policy
+    /// keeps no [hit counts](EvictionPolicy::counts_hits).
+    ///
+    /// Folds are scanned one at a time under their locks, keeping the
+    /// candidates in a heap of at most `n + 1` entries, so memory stays
+    /// bounded by `n` rather than by the cache size. Lock-free readers keep
+    /// counting meanwhile, so the ranking is approximate under load.
+    pub fn top_n_by_hits(&self, n: usize) -> Vec<(K::Value, usize)> {
+        if n == 0 || !self.policy.counts_hits() {
+            return Vec::new();
+        }
+        let mut heap = BinaryHeap::with_capacity(n + 1);
+        for fold in 0..self.num_folds {
+            let _guard = self.lock_fold(fold);
+            let slots = self.slots();
+            for i in slots.get_fold_range(fold) {
+                if !slots.is_occupied(i) || self.expired(slots.deadlines[i].load(Ordering::Relaxed))
+                {
+                    continue;
+                }
+                let Some(hits) = slots.hit_count(i) else {
+                    continue;
+                };
+                heap.push(Reverse(HotKey {
+                    hits,
+                    slot: i,
+                    key: slots.keys[i].load(Ordering::Relaxed),
+                }));
+                if heap.len() > n {
+                    heap.pop();
+                }
+            }
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(hot)| (hot.key, hot.hits))
+            .collect()
+    }
+
+    /// Every cached entry as `(key, value, hit_count)`, fold by fold. Hit
+    /// counts are zero unless the policy
+    /// [counts them](EvictionPolicy::counts_hits).
+    ///
+    /// Each fold is copied with its lock held, so no entry is evicted or
+    /// removed while it is read, but lock-free puts can still update values
+    /// and fill free slots meanwhile. Folds are visited one at a time, and
+    /// writers to the others carry on. Hit counts are bumped by lock-free
+    /// readers and so are only approximate.
+    pub fn snapshot(&self) -> Vec<(K::Value, V::Value, usize)> {
+        let mut out = Vec::new();
+        for fold in 0..self.num_folds {
+            let _guard = self.lock_fold(fold);
+            let slots = self.slots();
+            out.extend(
+                slots
+                    .get_fold_range(fold)
+                    .filter(|&i| slots.is_occupied(i))
+                    .map(|i| {
+                        (
+                            slots.keys[i].load(Ordering::Relaxed),
+                            slots.values[i].load(Ordering::Relaxed),
+                            slots.hit_count(i).unwrap_or(0),
+                        )
+                    }),
+            );
+        }
+        out
+    }
+
+    /// Encodes the policy, capacity, fold count and every live entry with
+    /// its hit count into a byte image for
+    /// [`from_snapshot`](Self::from_snapshot); the layout is described in
+    /// `src/snapshot.rs`.
+    ///
+    /// Folds are copied one at a time, as for [`snapshot`](Self::snapshot).
+    /// Expired entries are left out. Deadlines are not kept, because they
+    /// are measured on this cache's time source, so restored entries never
+    /// expire.
+    pub fn to_snapshot(&self) -> Vec<u8> {
+        let mut entries = Vec::new();
+        for fold in 0..self.num_folds {
+            let _guard = self.lock_fold(fold);
+            let slots = self.slots();
+            entries.extend(
+                slots
+                    .get_fold_range(fold)
+                    .filter(|&i| {
+                        slots.is_occupied(i)
+                            && !self.expired(slots.deadlines[i].load(Ordering::Relaxed))
+                    })
+                    .map(|i| {
+                        let counters = &slots.counters[i];
+                        let hits = slots.hit_count(i).unwrap_or(0);
+                        let rank = match self.policy {
+                            EvictionPolicy::Lfu => hits,
+                            _ => counters.last_access.load(Ordering::Relaxed),
+                        };
+                        let key = slots.keys[i].load(Ordering::Relaxed);
+                        (rank, (key, slots.values[i].load(Ordering::Relaxed), hits))
+                    }),
+            );
+        }
+        // First victims first, so restoring in order rebuilds the ranking.
+        entries.sort_by_key(|&(rank, _)| rank);
+        snapshot::encode::<K, V>(&Snapshot {
+            policy: self.policy,
+            capacity: self.capacity(),
+            folds: self.num_folds,
+            entries: entries.into_iter().map(|(_, entry)| entry).collect(),
+        })
+    }
+
+    /// Rebuilds a cache from a [`to_snapshot`](Self::to_snapshot) image,
+    /// with the image's policy, capacity and fold count.
+    ///
+    /// Entries are put back in the order the policy ranked them, with their
+    /// hit counts, and placed by `hasher`. If that hasher maps more keys to
+    /// a fold than it has slots, the entries the policy ranks lowest are
+    /// evicted as usual. The image is validated in full before any cache is
+    /// built.
+    pub fn from_snapshot(bytes: &[u8], hasher: H) -> Result<Self, SnapshotError> {
+        let image = snapshot::decode::<K, V>(bytes)?;
+        check_shape(image.capacity, image.folds).map_err(SnapshotError::Shape)?;
+        let cache = Self::with_hasher(image.capacity, image.folds, hasher, image.policy);
+        for (key, value, hits) in image.entries {
+            cache.put_with_hits(key, value, hits);
+        }
+        Ok(cache)
+    }
+
+    /// The keys of [`snapshot`](Self::snapshot).
+    pub fn keys(&self) -> Vec<K::Value> {
+        self.snapshot().into_iter().map(|(key, _, _)| key).collect()
+    }
+
+    /// Number of occupied slots, read from a counter that every insert and
+    /// removal maintains.
+    ///
+    /// The counter changes just after a slot is filled or emptied, so under
+    /// concurrent writes it can briefly lag a scan of the slots, though it
+    /// never exceeds the capacity. A `clear` uncounts each fold as it is
+    /// swept, so `len` reaches 0 only once `clear` returns. Expired entries
+    /// count until their slots are freed.
+    pub fn len(&self) -> usize {
+        self.occupancy.len().min(self.capacity())
+    }
+
+    /// Changes the number of slots to `new_capacity`, keeping the fold
+    /// count.
+    ///
+    /// Fresh slot arrays are allocated and each fold's entries, including
+    /// those in slots it [borrowed](Self::with_rebalancing), are copied
+    /// over, pinned entries first and then best first by the eviction
+    /// policy (most hits under [`EvictionPolicy::Lfu`], else most recently
+    /// touched, which for policies that ignore recency means most recently
+    /// filled), as many as the fold's new share
+    /// of slots holds. Shrinking a fold below its pinned entries drops some
+    /// of them. The rest are passed
+    /// to the eviction listener with [`EvictReason::Capacity`] once the new
+    /// arrays are in place.
+    ///
+    /// The copy runs with the slot arrays locked exclusively, so every other
+    /// operation waits for it; a resize is one pause proportional to the old
+    /// capacity, never a window where lookups could see a half-copied fold.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `new_capacity` is smaller than the fold count.
+    pub fn resize(&self, new_capacity: usize) {
+        assert!(
+            new_capacity >= self.num_folds,
+            "new_capacity must not be less than num_folds"
+        );
+        let mut dropped = Vec::new();
+        {
+            let mut slots = self.slots.write().unwrap_or_else(PoisonError::into_inner);
+            let stripes = if slots.hits.is_some() {
+                slots.hit_stripes
+            } else {
+                0
+            };
+            let resized = Slots::new(new_capacity, self.num_folds, stripes);
+            let current = self.generation.load(Ordering::Acquire);
+            let mut homes: Vec<Vec<_>> = (0..self.num_folds).map(|_| Vec::new()).collect();
+            for fold in 0..self.num_folds {
+                let entries = slots
+                    .get_fold_range(fold)
+                    .filter_map(|i| slots.take_slot(i));
+                if !self.fold_is_current(fold) {
+                    // Not swept since a `clear`; its entries are already gone.
+                    dropped.extend(entries.map(|e| (e.key, e.value, EvictReason::Cleared)));
+                    self.fold_generations[fold].store(current, Ordering::Release);
+                    continue;
+                }
+                // Entries in borrowed slots go back to their own fold.
+                for entry in entries {
+                    homes[self.get_fold_index(entry.key)].push(entry);
+                }
+            }
+            for borrowed in &self.borrowed {
+                borrowed.store(false, Ordering::Relaxed);
+            }
+            for (fold, mut entries) in homes.into_iter().enumerate() {
+                // Pinned entries are kept first, then the policy's hottest.
+                entries.sort_by_key(|e| {
+                    let rank = match self.policy {
+                        EvictionPolicy::Lfu => e.hits,
+                        _ => e.last_access,
+                    };
+                    Reverse((e.pinned, rank))
+                });
+                let range = resized.get_fold_range(fold);
+                let evicted = entries.split_off(entries.len().min(range.len()));
+                for (i, entry) in range.zip(entries) {
+                    resized.claim_free(i);
+                    resized.put_slot(i, entry);
+                }
+                dropped.extend(
+                    evicted
+                        .into_iter()
+                        .map(|e| (e.key, e.value, self.capacity_reason(e.deadline))),
+                );
+            }
+            *slots = resized;
+        }
+        self.occupancy.dropped(dropped.len());
+        for (key, value, reason) in dropped {
+            self.notify_evicted(key, value, reason);
+        }
+    }
+
+    /// Moves every fold's entries to the start of its slots, keeping their
+    /// order, so lookups and inserts that scan a sparse fold from its start
+    /// reach live entries first. Returns the number of entries moved.
+    ///
+    /// Like [`resize`](Self::resize), it holds the slots exclusively, so it
+    /// waits for every operation in progress and blocks new ones until it
+    /// returns. Folds not swept since a [`clear`](Self::clear) are left for
+    /// the sweep. With [`with_max_probe`](Self::with_max_probe) set, writers
+    /// already keep folds packed and there is nothing to move.
+    pub fn compact(&self) -> usize {
+        let slots = self.slots.write().unwrap_or_else(PoisonError::into_inner);
+        let mut moved = 0;
+        for fold in (0..self.num_folds).filter(|&fold| self.fold_is_current(fold)) {
+            let range = slots.get_fold_range(fold);
+            let live: Vec<usize> = range.clone().filter(|&i| slots.is_occupied(i)).collect();
+            for (to, from) in range.zip(live).filter(|&(to, from)| to != from) {
+                slots.relocate(from, to);
+                bump(&self.stats[fold].relocations);
+                moved += 1;
+            }
+        }
+        moved
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Totals of the operation counters.
+    ///
+    /// Each `get` counts one hit or miss, as does each `get_or_insert_with`;
+    /// a `get_or_insert_with` miss also counts the insertion it makes.
+    /// Counters are summed fold by fold without a lock, so under concurrent
+    /// use the totals are not a single point-in-time snapshot.
+    pub fn stats(&self) -> CacheStats {
+        let mut total = CacheStats {
+            entry_hits_unavailable: !self.policy.counts_hits(),
+            ..CacheStats::default()
+        };
+        for counters in &self.stats {
+            counters.add_to(&mut total);
+        }
+        total
+    }
+
+    /// Zeroes the operation counters, including the per-tag ones.
+    pub fn reset_stats(&self) {
+        for counters in &self.stats {
+            counters.reset();
+        }
+        for counters in &self.tag_stats {
+            counters.reset();
+        }
+    }
+
+    /// High-water mark of [`len`](Self::len) and insertion/eviction counts
+    /// since creation or the last
+    /// [`reset_occupancy_stats`](Self::reset_occupancy_stats), with the churn
+    /// rate of the last completed window.
+    pub fn occupancy_stats(&self) -> OccupancyStats {
+        self.occupancy.snapshot()
+    }
+
+    /// Closes the current churn window and starts a new one. Call it on
+    /// whatever schedule the window should span, e.g. from a periodic
+    /// maintenance task.
+    pub fn rotate_occupancy_window(&self) {
+        self.occupancy.rotate();
+    }
+
+    /// Zeroes the occupancy counters and restarts the high-water mark from
+    /// the current length. Independent of [`reset_stats`](Self::reset_stats).
+    pub fn reset_occupancy_stats(&self) {
+        self.occupancy.reset();
+    }
+
+    /// Counters of `fold` for the registered tag `tag`, or `None` for an
+    /// unregistered one. The registry is small, so the scan is a handful of
+    /// pointer comparisons for the usual string literals.
+    fn tag_counters(&self, fold: usize, tag: &'static str) -> Option<&TagCounters> {
+        let idx = self
+            .tags
+            .iter()
+            .position(|t| std::ptr::eq(*t, tag) || *t == tag)?;
+        Some(&self.tag_stats[fold * self.tags.len() + idx])
+    }
+
+    /// [`get`](Self::get), with the lookup also counted under `tag`.
+    ///
+    /// An unregistered tag counts as untagged.
+    pub fn get_tagged(&self, key: K::Value, tag: &'static str) -> Option<V::Value> {
+        let value = self.get(key);
+        if let Some(counters) = self.tag_counters(self.get_fold_index(key), tag) {
+            bump(if value.is_some() {
+                &counters.hits
+            } else {
+                &counters.misses
+            });
+        }
+        value
+    }
+
+    /// [`put`](Self::put), with the write also counted under `tag`.
+    ///
+    /// An unregistered tag counts as untagged.
+    pub fn put_tagged(
+        &self,
+        key: K::Value,
+        value: V::Value,
+        tag: &'static str,
+    ) -> Option<V::Value> {
+        let previous = self.put(key, value);
+        if let Some(counters) = self.tag_counters(self.get_fold_index(key), tag) {
+            bump(&counters.puts);
+        }
+        previous
+    }
+
+    /// Hits, misses and puts per tag: [`UNTAGGED`] first, then the
+    /// registered tags in registration order.
+    ///
+    /// Tagged calls are counted a second time in their own bucket, so plain
+    /// calls pay nothing extra. The untagged bucket is derived as the
+    /// cache-wide totals minus the tagged ones; like
+    /// [`stats`](Self::stats), it is not an exact snapshot under concurrent
+    /// use.
+    pub fn stats_by_tag(&self) -> Vec<(&'static str, TagStats)> {
+        let mut tagged = vec![TagStats::default(); self.tags.len()];
+        for (i, counters) in self.tag_stats.iter().enumerate() {
+            counters.add_to(&mut tagged[i % self.tags.len()]);
+        }
+        let total = self.stats();
+        let mut untagged = TagStats {
+            hits: total.hits,
+            misses: total.misses,
+            puts: total.insertions + total.updates,
+        };
+        for t in &tagged {
+            untagged.hits = untagged.hits.saturating_sub(t.hits);
+            untagged.misses = untagged.misses.saturating_sub(t.misses);
+            untagged.puts = untagged.puts.saturating_sub(t.puts);
+        }
+        std::iter::once((UNTAGGED, untagged))
+            .chain(self.tags.iter().copied().zip(tagged))
+            .collect()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots().capacity()
+    }
+
+    pub fn num_folds(&self) -> usize {
+        self.num_folds
+    }
+
+    /// Index of the fold `key` hashes to, in `0..num_folds()`.
+    pub fn fold_of(&self, key: K::Value) -> usize {
+        self.get_fold_index(key)
+    }
+
+    /// Number of occupied slots in `fold`, counted under its lock from the
+    /// fold's occupancy bits.
+    ///
+    /// The lock keeps out evictions and removals, but not lock-free puts
+    /// into free slots, so the count can be stale by the time it returns. A
+    /// slot such a put is still filling counts as occupied.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fold` is not below [`num_folds`](Self::num_folds).
+    pub fn fold_len(&self, fold: usize) -> usize {
+        let _guard = self.lock_fold(fold);
+        let slots = self.slots();
+        slots.count_occupied(slots.get_fold_range(fold))
+    }
+
+    /// `(occupied, capacity)` of every fold, in fold order. Folds are
+    /// counted like [`fold_len`](Self::fold_len), one lock at a time, so the
+    /// report is not a snapshot of the whole cache.
+    ///
+    /// A hasher that clusters keys shows up as a few folds at capacity
+    /// while others sit empty.
+    pub fn fold_utilization(&self) -> Vec<(usize, usize)> {
+        (0..self.num_folds)
+            .map(|fold| {
+                let _guard = self.lock_fold(fold);
+                let slots = self.slots();
+                let range = slots.get_fold_range(fold);
+                let occupied = slots.count_occupied(range.clone());
+                (occupied, range.len())
+            })
+            .collect()
+    }
+
+    pub fn policy(&self) -> EvictionPolicy {
+        self.policy
+    }
+
+    /// The cache's size and counters in the Prometheus text exposition
+    /// format, each metric name starting with `prefix`:
+    ///
+    /// * `{prefix}_size` and `{prefix}_capacity`, gauges of entries and slots;
+    /// * `{prefix}_hits_total`, `{prefix}_misses_total` and
+    ///   `{prefix}_evictions_total`, counters since creation or the last
+    ///   [`reset_stats`](Self::reset_stats);
+    /// * `{prefix}_fold_occupancy{fold="N"}`, a gauge of each fold's occupied
+    ///   slots.
+    ///
+    /// Everything but the per-fold occupancy comes from counters kept by the
+    /// cache; that is counted like
+    /// [`fold_utilization`](Self::fold_utilization), one fold locked at a
+    /// time.
+    pub fn metrics_text(&self, prefix: &str) -> String {
+        let stats = self.stats();
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, u64)]| {
+            out += &format!("# HELP {prefix}_{name} {help}\n# TYPE {prefix}_{name} {kind}\n");
+            for (labels, value) in samples {
+                out += &format!("{prefix}_{name}{labels} {value}\n");
+            }
+        };
+        let totals = [
+            ("size", "gauge", "Entries cached.", self.len() as u64),
+            ("capacity", "gauge", "Slots.", self.capacity() as u64),
+            ("hits_total", "counter", "Lookups that hit.", stats.hits),
+            (
+                "misses_total",
+                "counter",
+                "Lookups that missed.",
+                stats.misses,
+            ),
+            (
+                "evictions_total",
+                "counter",
+                "Entries evicted to make room.",
+                stats.evictions,
+            ),
+        ];
+        for (name, kind, help, value) in totals {
+            metric(name, kind, help, &[(String::new(), value)]);
+        }
+        let folds: Vec<_> = self
+            .fold_utilization()
+            .into_iter()
+            .enumerate()
+            .map(|(fold, (occupied, _))| (format!("{{fold=\"{fold}\"}}"), occupied as u64))
+            .collect();
+        metric(
+            "fold_occupancy",
+            "gauge",
+            "Occupied slots per fold.",
+            &folds,
+        );
+        out
+    }
+
+    /// A one-line description of the cache's shape, occupancy and
+    /// counters, suitable for logs, such as `capacity 16, 4 folds (4/4 1/4
+    /// 1/4 0/4), policy Lru, hits 3, misses 1, evictions 6`.
+    ///
+    /// Occupancy is counted like [`fold_utilization`](Self::fold_utilization),
+    /// each fold locked only while it is counted. So like every locking call
+    /// it must not be made from an eviction listener.
+    pub fn summary(&self) -> String {
+        let folds: Vec<_> = self
+            .fold_utilization()
+            .iter()
+            .map(|(occupied, capacity)| format!("{occupied}/{capacity}"))
+            .collect();
+        let stats = self.stats();
+        format!(
+            "capacity {}, {} folds ({}), policy {:?}, hits {}, misses {}, evictions {}",
+            self.capacity(),
+            self.num_folds,
+            folds.join(" "),
+            self.policy,
+            stats.hits,
+            stats.misses,
+            stats.evictions,
+        )
+    }
+}
+
+/// Shows the shape, per-fold `(occupied, capacity)` and counters rather than
+/// the slots; see [`LRUCache::summary`] for the locking.
+impl<K: AtomicStorage, V: AtomicStorage, H: FoldHasher> fmt::Debug for LRUCache<K, V, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LRUCache")
+            .field("capacity", &self.capacity())
+            .field("num_folds", &self.num_folds)
+            .field("policy", &self.policy)
+            .field("folds", &self.fold_utilization())
+            .field("stats", &self.stats())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Weak};
+
+    fn identity(k: usize) -> usize {
+        k
+    }
+
+    fn cache(capacity: usize, folds: usize) -> LRUCache<AtomicUsize, AtomicUsize> {
+        LRUCache::new(capacity, folds, identity)
+    }
+
+    #[test]
+    fn get_and_put() {
+        let cache = cache(8, 2);
+        assert_eq!(cache.get(1), None);
+        cache.put(1, 10);
+        cache.put(2, 20);
+        assert_eq!(cache.get(1), Some(10));
+        assert_eq!(cache.get(2), Some(20));
+        cache.put(1, 11);
+        assert_eq!(cache.get(1), Some(11));
+        assert_eq!(cache.len(), 2);
+    }
+
+    /// Whether every slot's occupancy bit agrees with its state.
+    fn bits_match_states(cache: &LRUCache<AtomicUsize, AtomicUsize>) -> bool {
+        let slots = cache.slots();
+        (0..slots.capacity()).all(|i| {
+            let bit = slots.occupied[i / 64].load(Ordering::Relaxed) >> (i % 64) & 1 == 1;
+            bit == (slots.state[i].load(Ordering::Relaxed) != EMPTY)
+        })
+    }
+
+    #[test]
+    fn occupancy_bits_follow_every_write() {
+        // Fold boundaries fall inside words.
+        let cache = cache(200, 3);
+        for k in 0..150 {
+            cache.put(k, k);
+        }
+        assert!(bits_match_states(&cache));
+        assert_eq!(cache.fold_len(0), 50);
+        // Keys 0..300 crowd fold 0 and evict.
+        for k in (0..300).step_by(3) {
+            cache.put(k, k);
+        }
+        assert_eq!(cache.fold_len(0), 67);
+        for k in (0..150).step_by(2) {
+            cache.remove(k);
+        }
+        assert!(bits_match_states(&cache));
+        let used: usize = cache.fold_utilization().iter().map(|&(n, _)| n).sum();
+        assert_eq!(used, cache.len());
+        cache.clear_fold(1);
+        assert_eq!(cache.fold_len(1), 0);
+        assert!(bits_match_states(&cache));
+        cache.resize(130);
+        assert!(bits_match_states(&cache));
+        cache.clear();
+        assert!(bits_match_states(&cache));
+        assert_eq!(cache.fold_utilization(), [(0, 44), (0, 43), (0, 43)]);
+        cache.put(1, 1);
+        assert_eq!(cache.get(1), Some(1));
+    }
+
+    #[test]
+    fn compact_packs_folds_and_keeps_values() {
+        let cache = cache(64, 4);
+        for k in 0..64 {
+            cache.put(k, k * 10);
+        }
+        // Fold `k % 4` loses every other one of its keys, leaving holes
+        // before each survivor.
+        let removed = |k: usize| (k / 4).is_multiple_of(2);
+        for k in (0..64).filter(|&k| removed(k)) {
+            cache.remove(k);
+        }
+        cache.get(5);
+        let utilization = cache.fold_utilization();
+        assert_eq!(cache.compact(), 32);
+        assert_eq!(cache.stats().relocations, 32);
+        assert_eq!(cache.compact(), 0);
+        {
+            let slots = cache.slots();
+            for fold in 0..4 {
+                let range = slots.get_fold_range(fold);
+                let live = range.clone().filter(|&i| slots.is_occupied(i)).count();
+                assert!(range.clone().take(live).all(|i| slots.is_occupied(i)));
+            }
+        }
+        assert!(bits_match_states(&cache));
+        assert_eq!(cache.fold_utilization(), utilization);
+        assert_eq!(cache.hit_count(5), Some(2));
+        for k in 0..64 {
+            let expected = (!removed(k)).then_some(k * 10);
+            assert_eq!(cache.get(k), expected, "key {k}");
+        }
+    }
+
+    #[test]
+    fn put_returns_previous_value() {
+        let cache = cache(2, 1);
+        assert_eq!(cache.put(1, 10), None);
+        assert_eq!(cache.put(1, 11), Some(10));
+        assert_eq!(cache.put(2, 20), None);
+        // Eviction is not an overwrite.
+        assert_eq!(cache.put(3, 30), None);
+        assert_eq!(cache.get(1), None);
+    }
+
+    #[test]
+    fn get_or_insert_with_computes_only_on_miss() {
+        let cache = cache(4, 1);
+        let mut calls = 0;
+        assert_eq!(
+            cache.get_or_insert_with(1, || {
+                calls += 1;
+                10
+            }),
+            10
+        );
+        assert_eq!(cache.get_or_insert_with(1, || unreachable!()), 10);
+        assert_eq!(calls, 1);
+        assert_eq!(cache.get(1), Some(10));
+    }
+
+    #[test]
+    fn get_or_insert_with_caches_zero_key() {
+        let cache = cache(4, 1);
+        assert_eq!(cache.get_or_insert_with(0, || 5), 5);
+        assert_eq!(cache.get_or_insert_with(0, || unreachable!()), 5);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn modify_and_upsert() {
+        let (cache, _, now) = ttl_cache(4, 1);
+        assert_eq!(cache.modify(1, |v| v + 1), None);
+        assert_eq!(cache.upsert(1, 10, |_| unreachable!()), 10);
+        assert_eq!(cache.modify(1, |v| v + 1), Some(11));
+        assert_eq!(cache.upsert(1, 0, |v| v * 2), 22);
+        assert_eq!(cache.get(1), Some(22));
+
+        // An expired entry is absent, and `modify` keeps the deadline.
+        cache.put_with_ttl(2, 5, Duration::from_millis(10));
+        assert_eq!(cache.modify(2, |v| v + 1), Some(6));
+        now.fetch_add(10, Ordering::Relaxed);
+        assert_eq!(cache.modify(2, |v| v + 1), None);
+        assert_eq!(cache.upsert(2, 1, |_| unreachable!()), 1);
+        assert_eq!(cache.get(2), Some(1));
+    }
+
+    #[test]
+    fn chunked_scan_matches_across_unaligned_folds() {
+        let slots: Slots<AtomicUsize, AtomicUsize> = Slots::new(45, 2, 0);
+        for i in 0..45 {
+            slots.keys[i].store(i % 10, Ordering::Relaxed);
+        }
+        let found: Vec<_> = slots.matching(3..41, 7).collect();
+        assert_eq!(found, [7, 17, 27, 37]);
+        assert_eq!(slots.matching(8..17, 7).count(), 0);
+        assert_eq!(slots.matching(5..5, 0).count(), 0);
+
+        // Folds of 23 and 22 slots, so the second starts mid-chunk.
+        let cache = cache(45, 2);
+        for key in 0..45 {
+            cache.put(key, key * 10);
+        }
+        assert_eq!(cache.len(), 45);
+        for key in 0..45 {
+            assert_eq!(cache.get(key), Some(key * 10), "key {key}");
+            assert!(cache.contains_key(key));
+        }
+        assert_eq!(cache.get(45), None);
+        assert_eq!(cache.put(44, 1), Some(440));
+        assert_eq!(cache.remove(23), Some(230));
+        assert_eq!(cache.get(23), None);
+    }
+
+    #[test]
+    fn zero_key_is_storable() {
+        let cache = cache(4, 1);
+        assert_eq!(cache.get(0), None);
+        assert!(!cache.contains_key(0));
+        assert_eq!(cache.put(0, 5), None);
+        assert_eq!(cache.get(0), Some(5));
+        assert!(cache.contains_key(0));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.put(0, 6), Some(5));
+        assert_eq!(cache.remove(0), Some(6));
+        assert_eq!(cache.get(0), None);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn zero_key_with_zero_value_is_distinct_from_an_empty_slot() {
+        let cache = cache(2, 1);
+        cache.put(0, 0);
+        assert_eq!(cache.get(0), Some(0));
+        cache.put(1, 10);
+        cache.put(2, 20);
+        // Key 0 was least recently used, so it made room for key 2.
+        assert_eq!(cache.get(0), None);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn remove_and_contains() {
+        let cache = cache(4, 1);
+        cache.put(1, 10);
+        assert!(cache.contains_key(1));
+        assert_eq!(cache.remove(1), Some(10));
+        assert_eq!(cache.remove(1), None);
+        assert!(!cache.contains_key(1));
+        assert_eq!(cache.get(1), None);
+    }
+
+    #[test]
+    fn peek_is_not_an_access() {
+        let cache = cache(2, 1);
+        cache.put(1, 10);
+        cache.put(2, 20);
+        assert_eq!(cache.peek(1), Some(10));
+        assert_eq!(cache.peek(3), None);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (0, 0));
+        // Key 1 is still the least recently used.
+        cache.put(3, 30);
+        assert_eq!(cache.peek(1), None);
+        assert_eq!(cache.peek(2), Some(20));
+    }
+
+    #[test]
+    fn clear_empties_every_fold() {
+        let cache = cache(16, 4);
+        for k in 1..=16 {
+            cache.put(k, k);
+        }
+        cache.clear();
+        assert!(cache.is_empty());
+        assert!((1..=16).all(|k| cache.get(k).is_none()));
+    }
+
+    #[test]
+    fn fold_ranges_cover_capacity() {
+        let cache = cache(10, 3);
+        assert_eq!(cache.slots().get_fold_range(0), 0..4);
+        assert_eq!(cache.slots().get_fold_range(1), 4..7);
+        assert_eq!(cache.slots().get_fold_range(2), 7..10);
+    }
+
+    #[test]
+    fn keys_stay_in_their_fold() {
+        let cache = cache(4, 2);
+        // Odd keys map to fold 1 under the identity hasher.
+        for k in [1, 3, 5, 7] {
+            cache.put(k, k);
+        }
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(5), Some(5));
+        assert_eq!(cache.get(7), Some(7));
+    }
+
+    #[test]
+    #[should_panic(expected = "num_folds must not exceed capacity")]
+    fn more_folds_than_slots_panics() {
+        let _ = cache(2, 3);
+    }
+
+    #[test]
+    fn lru_evicts_least_recently_touched() {
+        let cache = cache(3, 1);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        cache.put(3, 3);
+        cache.get(1);
+        cache.put(4, 4);
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(1), Some(1));
+        assert_eq!(cache.get(3), Some(3));
+        assert_eq!(cache.get(4), Some(4));
+    }
+
+    #[test]
+    fn lru_evicts_stale_hot_key_before_recent_one() {
+        let cache = cache(3, 1);
+        cache.put(1, 1);
+        for _ in 0..1_000 {
+            cache.get(1);
+        }
+        cache.put(2, 2);
+        cache.put(3, 3);
+        cache.get(2);
+        cache.get(3);
+        cache.put(4, 4);
+        assert_eq!(cache.get(1), None, "old hot key should be the victim");
+        assert!([2, 3, 4].iter().all(|&k| cache.get(k).is_some()));
+    }
+
+    #[test]
+    fn touch_protects_keys_without_counting_lookups() {
+        for policy in [
+            EvictionPolicy::Lru,
+            EvictionPolicy::Lfu,
+            EvictionPolicy::Clock,
+        ] {
+            let cache = policy_cache(3, policy);
+            for k in 1..=3 {
+                cache.put(k, k);
+            }
+            for k in 4..10 {
+                assert!(cache.touch(1), "{policy:?}");
+                cache.put(k, k);
+            }
+            assert_eq!(cache.get(1), Some(1), "{policy:?}");
+            assert!(!cache.touch(2));
+            let stats = cache.stats();
+            assert_eq!((stats.hits, stats.misses), (1, 0), "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn lfu_keeps_stale_hot_key() {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> =
+            LRUCache::with_policy(3, 1, identity, EvictionPolicy::Lfu);
+        cache.put(1, 1);
+        for _ in 0..1_000 {
+            cache.get(1);
+        }
+        cache.put(2, 2);
+        cache.put(3, 3);
+        cache.get(2);
+        cache.get(3);
+        cache.put(4, 4);
+        assert_eq!(cache.get(1), Some(1));
+        assert_eq!(cache.get(2), None);
+    }
+
+    fn policy_cache(capacity: usize, policy: EvictionPolicy) -> LRUCache<AtomicUsize, AtomicUsize> {
+        LRUCache::with_policy(capacity, 1, identity, policy)
+    }
+
+    #[test]
+    fn fifo_ignores_hits_and_updates() {
+        let cache = policy_cache(3, EvictionPolicy::Fifo);
+        for k in 1..=3 {
+            cache.put(k, k);
+        }
+        for _ in 0..10 {
+            cache.get(1);
+        }
+        cache.put(1, 10);
+        cache.put(4, 4);
+        assert_eq!(cache.get(1), None, "oldest fill goes first");
+        cache.put(5, 5);
+        assert_eq!(cache.get(2), None);
+        assert!([3, 4, 5].iter().all(|&k| cache.contains_key(k)));
+    }
+
+    #[test]
+    fn auto_fold_count_keeps_folds_big_enough() {
+        assert_eq!(fold_count_for(1, 8, 4), 1);
+        assert_eq!(fold_count_for(MIN_SLOTS_PER_FOLD - 1, 8, 4), 1);
+        assert_eq!(fold_count_for(100, 8, 4), 100 / MIN_SLOTS_PER_FOLD);
+        assert_eq!(fold_count_for(1 << 20, 8, 4), 32);
+        assert_eq!(fold_count_for(1 << 20, 8, 0), 1);
+        assert_eq!(fold_count_for(1 << 20, usize::MAX, 4), (1 << 20) / 16);
+
+        let tiny: LRUCache<AtomicUsize, AtomicUsize> = LRUCache::with_auto_folds(3, identity);
+        assert_eq!((tiny.capacity(), tiny.num_folds()), (3, 1));
+        let big: LRUCache<AtomicUsize, AtomicUsize> = LRUCache::with_auto_folds(1 << 16, identity);
+        assert_eq!(
+            big.num_folds(),
+            auto_fold_count(1 << 16, AUTO_FOLDS_PER_THREAD)
+        );
+        big.put(7, 70);
+        assert_eq!(big.get(7), Some(70));
+    }
+
+    #[test]
+    fn top_n_by_hits_finds_the_hammered_keys() {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> = LRUCache::new(64, 4, identity);
+        for k in 0..32 {
+            cache.put(k, k);
+        }
+        for (key, gets) in [(5, 100), (17, 50), (30, 20), (8, 20)] {
+            for _ in 0..gets {
+                cache.get(key);
+            }
+        }
+        for k in 0..32 {
+            cache.get(k);
+        }
+        assert_eq!(cache.hit_count(5), Some(102));
+        assert_eq!(cache.hit_count(5), Some(102), "reading is not a hit");
+        assert_eq!(cache.hit_count(99), None);
+        // 8 and 30 tie; 8 sits in the lower slot of fold 0.
+        assert_eq!(
+            cache.top_n_by_hits(4),
+            [(5, 102), (17, 52), (8, 22), (30, 22)]
+        );
+        assert_eq!(cache.top_n_by_hits(1), [(5, 102)]);
+        assert_eq!(cache.top_n_by_hits(0), []);
+        assert_eq!(cache.top_n_by_hits(100).len(), 32);
+
+        let fifo = policy_cache(4, EvictionPolicy::Fifo);
+        fifo.put(1, 1);
+        fifo.get(1);
+        assert_eq!(fifo.hit_count(1), None);
+        assert_eq!(fifo.top_n_by_hits(1), []);
+    }
+
+    #[test]
+    fn fifo_and_random_keep_no_hit_counts() {
+        for policy in EvictionPolicy::ALL {
+            let cache = policy_cache(4, policy);
+            cache.put(1, 1);
+            cache.put(2, 2);
+            cache.get(2);
+            cache.get(2);
+            let counted = policy.counts_hits();
+            assert_eq!(cache.slots().hits.is_some(), counted, "{policy:?}");
+            assert_eq!(cache.stats().entry_hits_unavailable, !counted);
+            assert_eq!(cache.stats().hits, 2, "lookups are counted regardless");
+            let mut entries = cache.snapshot();
+            entries.sort();
+            let hits = if counted { [1, 3] } else { [0, 0] };
+            assert_eq!(entries, [(1, 1, hits[0]), (2, 2, hits[1])], "{policy:?}");
+            cache.resize(8);
+            assert_eq!(cache.slots().hits.is_some(), counted, "{policy:?}");
+        }
+        let cache = policy_cache(4, EvictionPolicy::Fifo);
+        for k in [3, 1, 2] {
+            cache.put(k, k);
+        }
+        assert_eq!(
+            cache.pop_least_used(),
+            Some((3, 3)),
+            "oldest fill without counts"
+        );
+    }
+
+    #[test]
+    fn clock_gives_referenced_slots_a_second_chance() {
+        let cache = policy_cache(3, EvictionPolicy::Clock);
+        for k in 1..=3 {
+            cache.put(k, k);
+        }
+        cache.get(1);
+        cache.get(3);
+        // The hand clears 1's bit, then stops at 2.
+        cache.put(4, 4);
+        assert!(!cache.contains_key(2));
+        // It resumes at 3, clears its bit, and wraps to 1, now unreferenced.
+        cache.put(5, 5);
+        assert!(!cache.contains_key(1));
+        assert!([3, 4, 5].iter().all(|&k| cache.contains_key(k)));
+    }
+
+    #[test]
+    fn clock_sweep_is_bounded_and_takes_expired_slots() {
+        let cache = policy_cache(1024, EvictionPolicy::Clock);
+        for k in 0..1024 {
+            cache.put(k, k);
+            cache.get(k);
+        }
+        let referenced = |cache: &LRUCache<AtomicUsize, AtomicUsize>| {
+            let slots = cache.slots();
+            let bits = slots
+                .counters
+                .iter()
+                .map(|c| c.referenced.load(Ordering::Relaxed));
+            bits.filter(|&b| b).count()
+        };
+        // Every bit is set, so the hand gives up after its limit and takes
+        // the first slot it passed.
+        cache.put(5000, 0);
+        assert!(!cache.contains_key(0));
+        assert_eq!(referenced(&cache), 1024 - CLOCK_SWEEP_LIMIT);
+        cache.put(5001, 0);
+        assert!(!cache.contains_key(1));
+
+        let now = Arc::new(AtomicU64::new(0));
+        let clock = Arc::clone(&now);
+        let cache = policy_cache(8, EvictionPolicy::Clock)
+            .with_time_source(move || clock.load(Ordering::Relaxed));
+        for k in 0..8 {
+            if k == 2 {
+                cache.put_with_ttl(k, k, Duration::from_millis(5));
+            } else {
+                cache.put(k, k);
+            }
+            cache.get(k);
+        }
+        now.store(5, Ordering::Relaxed);
+        cache.put(8, 8);
+        assert!(!cache.contains_key(2), "the expired slot is the victim");
+        assert_eq!(referenced(&cache), 5);
+    }
+
+    #[test]
+    fn random_evicts_only_unpinned_occupied_slots() {
+        let cache = policy_cache(4, EvictionPolicy::Random);
+        for k in 1..=4 {
+            cache.put(k, k);
+        }
+        cache.pin(1);
+        for k in 5..100 {
+            cache.put(k, k);
+            assert!(cache.contains_key(1) && cache.contains_key(k));
+        }
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.stats().evictions, 95);
+    }
+
+    #[test]
+    fn every_policy_keeps_values_consistent_under_churn() {
+        for policy in EvictionPolicy::ALL {
+            let cache = LRUCache::<AtomicUsize, AtomicUsize>::with_policy(16, 2, identity, policy);
+            for k in 0..500 {
+                cache.put(k % 37, k % 37 * 3);
+                cache.get(k % 11);
+            }
+            assert_eq!(cache.len(), 16, "{policy:?}");
+            assert!(
+                cache.snapshot().iter().all(|&(k, v, _)| v == k * 3),
+                "{policy:?}"
+            );
+        }
+    }
+
+    /// A burst of hits on key 1, then steady traffic on fresh keys that each
+    /// get a few hits.
+    fn burst_then_churn(cache: &LRUCache<AtomicUsize, AtomicUsize>) {
+        cache.put(1, 1);
+        for _ in 0..1_000 {
+            cache.get(1);
+        }
+        for k in 2..200 {
+            cache.put(k, k);
+            for _ in 0..3 {
+                cache.get(k);
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_hits_survive_cold_churn() {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> =
+            LRUCache::with_policy(4, 1, identity, EvictionPolicy::Lfu);
+        for k in 1..=3 {
+            assert_eq!(cache.put_with_hits(k, k, 100), None);
+        }
+        for k in 10..200 {
+            cache.put(k, k);
+            cache.get(k);
+        }
+        assert!((1..=3).all(|k| cache.contains_key(k)));
+        assert_eq!(cache.len(), 4);
+
+        assert_eq!(cache.put_with_hits(1, 11, 5), Some(1));
+        assert_eq!(cache.hit_count(1), Some(106));
+    }
+
+    #[test]
+    fn lfu_without_decay_pins_burst_key() {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> =
+            LRUCache::with_policy(4, 1, identity, EvictionPolicy::Lfu);
+        burst_then_churn(&cache);
+        assert!(cache.contains_key(1));
+    }
+
+    #[test]
+    fn sampled_hits_keep_lfu_order() {
+        let sampled =
+            || LRUCache::with_policy(4, 1, identity, EvictionPolicy::Lfu).with_hit_sampling(3);
+        let cache: LRUCache<AtomicUsize, AtomicUsize> = sampled();
+        burst_then_churn(&cache);
+        assert!(cache.contains_key(1));
+
+        let cache: LRUCache<AtomicUsize, AtomicUsize> = sampled();
+        cache.put(1, 1);
+        for _ in 0..8_000 {
+            cache.get(1);
+        }
+        let hits = cache.hit_count(1).unwrap();
+        assert_eq!((hits - 1) % 8, 0, "counted hits add 8");
+        assert!((6_001..=10_001).contains(&hits), "{hits} estimates 8001");
+    }
+
+    #[test]
+    fn striped_hits_sum_to_exact_counts() {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> =
+            LRUCache::with_policy(4, 1, identity, EvictionPolicy::Lfu);
+        cache.put(1, 1);
+        cache.get(1);
+        // Restriping keeps the counts.
+        let cache = cache.with_hit_stripes(4);
+        assert_eq!(cache.hit_count(1), Some(2));
+        for _ in 0..1_000 {
+            cache.get(1);
+        }
+        assert_eq!(cache.hit_count(1), Some(1_002));
+        assert_eq!(cache.slots().hits.as_ref().map(Vec::len), Some(16));
+        burst_then_churn(&cache);
+        assert!(cache.contains_key(1));
+        cache.resize(8);
+        assert_eq!(cache.hit_count(1), Some(2_003));
+        assert_eq!(cache.slots().hits.as_ref().map(Vec::len), Some(32));
+    }
+
+    #[test]
+    fn hit_decay_lets_stale_hot_key_age_out() {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> =
+            LRUCache::with_policy(4, 1, identity, EvictionPolicy::Lfu).with_hit_decay(4);
+        burst_then_churn(&cache);
+        assert!(!cache.contains_key(1));
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn hit_decay_halves_counts_per_fold() {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> =
+            LRUCache::with_policy(4, 2, identity, EvictionPolicy::Lfu).with_hit_decay(2);
+        cache.put(2, 2);
+        for _ in 0..9 {
+            cache.get(2);
+        }
+        cache.put(1, 1);
+        assert_eq!(cache.slots().hit_count(0), Some(10));
+        // Second put into fold 0 triggers the decay there only.
+        cache.put(4, 4);
+        assert_eq!(cache.slots().hit_count(0), Some(5));
+        assert_eq!(cache.slots().hit_count(2), Some(1));
+    }
+
+    #[test]
+    fn hit_count_saturates() {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> =
+            LRUCache::with_policy(2, 1, identity, EvictionPolicy::Lfu);
+        cache.put(1, 1);
+        cache.put(2, 2);
+        cache.slots().set_hit_count(0, usize::MAX - 1);
+        for _ in 0..3 {
+            cache.get(1);
+        }
+        assert_eq!(cache.slots().hit_count(0), Some(usize::MAX));
+        // Still the hottest entry, so the other one is evicted.
+        cache.put(3, 3);
+        assert_eq!(cache.get(1), Some(1));
+        assert_eq!(cache.get(2), None);
+    }
+
+    #[test]
+    fn stats_count_each_operation() {
+        let cache = cache(2, 1);
+        cache.get(1);
+        cache.put(1, 10);
+        cache.put(1, 11);
+        cache.get(1);
+        cache.put(2, 20);
+        cache.put(3, 30);
+        cache.get(0);
+        cache.put(0, 1);
+        cache.remove(3);
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 2,
+                insertions: 4,
+                updates: 1,
+                evictions: 2,
+                ..CacheStats::default()
+            }
+        );
+        assert_eq!(cache.stats().hit_rate(), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn stats_count_get_or_insert_with_once() {
+        let cache = cache(4, 2);
+        cache.get_or_insert_with(1, || 10);
+        cache.get_or_insert_with(1, || 11);
+        cache.get_or_insert_with(2, || 20);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!(stats.insertions, 2);
+    }
+
+    #[test]
+    fn reset_stats_zeroes_counters() {
+        let cache = cache(4, 2);
+        for k in 1..=8 {
+            cache.put(k, k);
+            cache.get(k);
+        }
+        assert_ne!(cache.stats(), CacheStats::default());
+        cache.reset_stats();
+        assert_eq!(cache.stats(), CacheStats::default());
+        assert_eq!(cache.len(), 4, "reset must not touch the entries");
+    }
+
+    /// Whether every fold's entries occupy a prefix of its slots.
+    fn is_packed(cache: &LRUCache<AtomicUsize, AtomicUsize>) -> bool {
+        (0..cache.num_folds).all(|fold| {
+            let slots = cache.slots();
+            let occupied: Vec<_> = slots
+                .get_fold_range(fold)
+                .map(|i| slots.is_occupied(i))
+                .collect();
+            occupied.windows(2).all(|w| w[0] || !w[1])
+        })
+    }
+
+    #[test]
+    fn bounded_probe_finds_everything_below_bound() {
+        let cache = cache(64, 1).with_max_probe(8);
+        let mut live = Vec::new();
+        let mut x = 12345usize;
+        for _ in 0..5_000 {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            let key = 1 + x % 32;
+            if live.len() < 8 && !live.contains(&key) {
+                cache.put(key, key);
+                live.push(key);
+            } else if let Some(pos) = live.iter().position(|&k| k == key) {
+                assert_eq!(cache.remove(key), Some(key));
+                live.swap_remove(pos);
+            }
+            assert!(live.iter().all(|&k| cache.get(k) == Some(k)));
+        }
+        assert_eq!(cache.stats().probe_overflows, 0);
+        assert!(is_packed(&cache));
+    }
+
+    #[test]
+    fn misses_past_the_window_count_as_overflows() {
+        let cache = cache(8, 1).with_max_probe(4);
+        for k in 1..=4 {
+            cache.put(k, k);
+        }
+        cache.get(9);
+        assert_eq!(cache.stats().probe_overflows, 0, "window not exceeded");
+        cache.put(5, 5);
+        // 5 was swapped into the window; the displaced key is out of reach.
+        assert_eq!(cache.get(5), Some(5));
+        let reachable = (1..=4).filter(|&k| cache.get(k).is_some()).count();
+        assert_eq!(reachable, 3);
+        assert_eq!(cache.stats().probe_overflows, 1);
+        assert_eq!(cache.stats().relocations, 1);
+        assert!(cache.contains_key(1));
+    }
+
+    #[test]
+    fn remove_compacts_the_fold() {
+        let cache = cache(8, 1).with_max_probe(4);
+        for k in 1..=6 {
+            cache.put(k, k);
+        }
+        let before = cache.stats().relocations;
+        assert_eq!(before, 2, "5 and 6 were each swapped into the window");
+        // Removals pull the displaced entries back into the window.
+        let hidden: Vec<_> = (1..=6).filter(|&k| cache.get(k).is_none()).collect();
+        assert_eq!(hidden.len(), 2);
+        cache.remove(5);
+        cache.remove(6);
+        assert!(is_packed(&cache));
+        assert_eq!(cache.stats().relocations, before + 2);
+        assert!(hidden.iter().all(|&k| cache.get(k) == Some(k)));
+        // Removing the last entry leaves no hole to fill.
+        let last = cache.slots().keys[3].load(Ordering::Relaxed);
+        cache.remove(last);
+        assert_eq!(cache.stats().relocations, before + 2);
+    }
+
+    #[test]
+    fn updating_a_displaced_key_brings_it_back() {
+        let cache = cache(4, 1).with_max_probe(2);
+        for k in 1..=4 {
+            cache.put(k, k);
+        }
+        let hidden = (1..=4).find(|&k| cache.get(k).is_none()).unwrap();
+        assert_eq!(cache.put(hidden, 40), Some(hidden));
+        assert_eq!(cache.get(hidden), Some(40));
+        assert_eq!(cache.len(), 4);
+    }
+
+    type Evicted = Arc<Mutex<Vec<(usize, usize, EvictReason)>>>;
+
+    fn recording_cache(
+        capacity: usize,
+        folds: usize,
+    ) -> (LRUCache<AtomicUsize, AtomicUsize>, Evicted) {
+        let log = Evicted::default();
+        let sink = Arc::clone(&log);
+        let cache = cache(capacity, folds)
+            .with_on_evict(move |k, v, reason| sink.lock().unwrap().push((k, v, reason)));
+        (cache, log)
+    }
+
+    #[test]
+    fn clear_fold_drops_only_that_fold() {
+        let (cache, log) = recording_cache(16, 4);
+        for k in 0..16 {
+            cache.put(k, k * 10);
+        }
+        assert_eq!(cache.clear_fold(1), 4);
+        assert_eq!(cache.fold_utilization(), [(4, 4), (0, 4), (4, 4), (4, 4)]);
+        assert_eq!(cache.len(), 12);
+        assert!((0..16).all(|k| cache.contains_key(k) == (k % 4 != 1)));
+        let mut cleared = log.lock().unwrap().clone();
+        cleared.sort_unstable_by_key(|&(k, _, _)| k);
+        assert_eq!(
+            cleared,
+            [1, 5, 9, 13].map(|k| (k, k * 10, EvictReason::Cleared))
+        );
+        assert_eq!(cache.clear_fold(1), 0);
+        assert_eq!(cache.clear_fold(4), 0, "out of range");
+        cache.put(5, 1);
+        assert_eq!(cache.get(5), Some(1));
+    }
+
+    #[test]
+    fn on_evict_reports_each_reason() {
+        let (cache, log) = recording_cache(2, 1);
+        cache.put(1, 10);
+        cache.put(2, 20);
+        cache.put(1, 11);
+        assert!(log.lock().unwrap().is_empty(), "updates are not evictions");
+        cache.get(1);
+        cache.put(3, 30);
+        cache.remove(1);
+        cache.remove(1);
+        cache.clear();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (2, 20, EvictReason::Capacity),
+                (1, 11, EvictReason::Removed),
+                (3, 30, EvictReason::Cleared),
+            ]
+        );
+    }
+
+    #[test]
+    fn on_evict_ignores_relocations() {
+        let (cache, log) = recording_cache(8, 1);
+        let cache = cache.with_max_probe(2);
+        for k in 1..=4 {
+            cache.put(k, k);
+        }
+        cache.remove(1);
+        assert!(cache.stats().relocations > 0);
+        assert_eq!(*log.lock().unwrap(), vec![(1, 1, EvictReason::Removed)]);
+    }
+
+    #[test]
+    fn on_evict_can_read_another_fold() {
+        type Cache = LRUCache<AtomicUsize, AtomicUsize>;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        // Fold 0 holds even keys and fold 1 odd ones.
+        let cache = Arc::new_cyclic(|weak: &Weak<Cache>| {
+            let weak = weak.clone();
+            cache(4, 2).with_on_evict(move |k, v, reason| {
+                let cache = weak.upgrade().unwrap();
+                assert!(!cache.contains_key(k), "slot must be invalidated first");
+                sink.lock().unwrap().push((k, v, reason, cache.get(1)));
+            })
+        });
+        cache.put(1, 100);
+        cache.put(2, 2);
+        cache.put(4, 4);
+        cache.put(6, 6);
+        cache.remove(4);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                (2, 2, EvictReason::Capacity, Some(100)),
+                (4, 4, EvictReason::Removed, Some(100)),
+            ]
+        );
+    }
+
+    #[test]
+    fn stats_by_tag_attributes_traffic() {
+        let cache = cache(64, 4).with_tags(&["index", "blocks", "meta"]);
+        for k in 1..=10 {
+            cache.put_tagged(k, k, "index");
+        }
+        for k in 1..=20 {
+            cache.get_tagged(k, "blocks");
+        }
+        cache.put_tagged(100, 1, "meta");
+        cache.put_tagged(100, 2, "meta");
+        cache.get_tagged(100, "meta");
+        cache.get(1);
+        cache.put(200, 1);
+        cache.get_tagged(200, "unregistered");
+        let by_tag = cache.stats_by_tag();
+        let tags: Vec<_> = by_tag.iter().map(|(t, _)| *t).collect();
+        assert_eq!(tags, [UNTAGGED, "index", "blocks", "meta"]);
+        let stats = |hits, misses, puts| TagStats { hits, misses, puts };
+        assert_eq!(by_tag[0].1, stats(2, 0, 1));
+        assert_eq!(by_tag[1].1, stats(0, 0, 10));
+        assert_eq!(by_tag[2].1, stats(10, 10, 0));
+        assert_eq!(by_tag[3].1, stats(1, 0, 2));
+
+        cache.reset_stats();
+        assert!(cache
+            .stats_by_tag()
+            .iter()
+            .all(|(_, s)| *s == TagStats::default()));
+    }
+
+    #[test]
+    #[should_panic(expected = "registered twice")]
+    fn duplicate_tags_panic() {
+        let _ = cache(4, 1).with_tags(&["a", "b", "a"]);
+    }
+
+    #[test]
+    fn high_water_mark_survives_removals() {
+        let cache = cache(8, 1);
+        for k in 1..=6 {
+            cache.put(k, k);
+        }
+        for k in 1..=5 {
+            cache.remove(k);
+        }
+        cache.put(7, 7);
+        assert_eq!(cache.occupancy_stats().high_water_mark, 6);
+        cache.clear();
+        assert_eq!(cache.occupancy_stats().high_water_mark, 6);
+
+        cache.reset_stats();
+        assert_eq!(
+            cache.occupancy_stats().high_water_mark,
+            6,
+            "reset separately"
+        );
+        cache.put(1, 1);
+        cache.reset_occupancy_stats();
+        let stats = cache.occupancy_stats();
+        assert_eq!((stats.high_water_mark, stats.insertions), (1, 0));
+    }
+
+    #[test]
+    fn churn_rate_follows_rotated_windows() {
+        let cache = cache(4, 1);
+        for k in 1..=4 {
+            cache.put(k, k);
+        }
+        assert_eq!(
+            cache.occupancy_stats().churn_rate,
+            0.0,
+            "no window closed yet"
+        );
+        cache.rotate_occupancy_window();
+        assert_eq!(
+            cache.occupancy_stats().churn_rate,
+            0.0,
+            "filling evicts nothing"
+        );
+
+        for k in 5..=12 {
+            cache.put(k, k);
+        }
+        cache.rotate_occupancy_window();
+        assert_eq!(cache.occupancy_stats().churn_rate, 1.0);
+
+        cache.remove(12);
+        cache.put(13, 13);
+        cache.put(14, 14);
+        cache.rotate_occupancy_window();
+        let stats = cache.occupancy_stats();
+        assert_eq!(stats.churn_rate, 0.5);
+        assert_eq!((stats.insertions, stats.evictions), (14, 9));
+        assert_eq!(stats.high_water_mark, 4);
+
+        cache.rotate_occupancy_window();
+        assert_eq!(cache.occupancy_stats().churn_rate, 0.0, "idle window");
+    }
+
+    #[test]
+    fn capturing_hasher() {
+        use crate::FibonacciHasher;
+        let salt = 3;
+        let cache: LRUCache<AtomicUsize, AtomicUsize, _> =
+            LRUCache::with_hasher(4, 2, move |k: usize| k + salt, EvictionPolicy::Lru);
+        cache.put(1, 1);
+        // 1 + 3 is even, so key 1 lands in fold 0.
+        assert_eq!(cache.slots().keys[0].load(Ordering::Relaxed), 1);
+
+        let cache: LRUCache<AtomicUsize, AtomicUsize, _> =
+            LRUCache::with_hasher(64, 8, FibonacciHasher::new(42), EvictionPolicy::Lfu);
+        for k in 1..=64 {
+            cache.put(k, k);
+        }
+        assert!(cache.len() > 40);
+        assert_eq!(cache.policy(), EvictionPolicy::Lfu);
+    }
+
+    fn acquisitions(cache: &LRUCache<AtomicUsize, AtomicUsize>) -> usize {
+        (0..cache.num_folds)
+            .map(|f| cache.folds[f].lock().unwrap().acquisitions)
+            .sum()
+    }
+
+    #[test]
+    fn put_many_matches_sequential_puts() {
+        let items: Vec<_> = (0..200).map(|i| (1 + (i * 7) % 50, i)).collect();
+        let batched = cache(16, 4);
+        let single = cache(16, 4);
+        batched.put_many(&items);
+        for &(k, v) in &items {
+            single.put(k, v);
+        }
+        let keys: Vec<_> = (0..=50).collect();
+        assert_eq!(batched.get_many(&keys), single.get_many(&keys));
+        assert_eq!(batched.stats(), single.stats());
+    }
+
+    #[test]
+    fn puts_lock_only_to_evict() {
+        let cache = cache(8, 2);
+        for k in 0..8 {
+            cache.put(k, k);
+        }
+        cache.put(3, 30);
+        assert_eq!(
+            acquisitions(&cache),
+            0,
+            "free slots and updates are lock-free"
+        );
+        assert_eq!(cache.put(8, 80), None);
+        assert_eq!(acquisitions(&cache), 1);
+        assert_eq!(cache.get(3), Some(30));
+        assert_eq!(cache.len(), 8);
+    }
+
+    #[test]
+    fn bounded_probes_and_hit_decay_keep_puts_locked() {
+        let bounded = cache(8, 2).with_max_probe(2);
+        bounded.put(1, 1);
+        assert_eq!(acquisitions(&bounded), 1);
+        let decaying = cache(8, 2).with_hit_decay(4);
+        decaying.put(1, 1);
+        assert_eq!(acquisitions(&decaying), 1);
+    }
+
+    #[test]
+    fn put_many_locks_each_fold_once() {
+        let cache = cache(64, 4);
+        let items: Vec<_> = (1..=40).map(|k| (k, k * 10)).collect();
+        cache.put_many(&items);
+        assert_eq!(acquisitions(&cache), 4);
+        cache.put_many(&[(0, 1), (8, 80), (4, 40)]);
+        assert_eq!(acquisitions(&cache), 5, "clustered keys share a lock");
+    }
+
+    #[test]
+    fn get_many_keeps_input_order() {
+        let cache = cache(16, 4);
+        cache.put_many(&[(3, 30), (1, 10), (2, 20)]);
+        assert_eq!(
+            cache.get_many(&[2, 0, 9, 1, 3, 2]),
+            vec![Some(20), None, None, Some(10), Some(30), Some(20)]
+        );
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (4, 2));
+        assert_eq!(acquisitions(&cache), 3, "lookups take no locks");
+    }
+
+    #[test]
+    fn resize_up_keeps_every_entry() {
+        let cache = cache(4, 2);
+        for k in 1..=4 {
+            cache.put(k, k * 10);
+        }
+        cache.resize(8);
+        assert_eq!(cache.capacity(), 8);
+        assert!((1..=4).all(|k| cache.get(k) == Some(k * 10)));
+        for k in 5..=8 {
+            cache.put(k, k * 10);
+        }
+        assert_eq!(cache.len(), 8);
+    }
+
+    #[test]
+    fn resize_down_keeps_most_recently_used() {
+        let (cache, log) = recording_cache(8, 1);
+        for k in 1..=8 {
+            cache.put(k, k);
+        }
+        cache.put(1, 10);
+        cache.put(2, 20);
+        cache.resize(3);
+        assert_eq!(cache.capacity(), 3);
+        assert_eq!(cache.len(), 3);
+        assert_eq!(cache.get(1), Some(10));
+        assert_eq!(cache.get(2), Some(20));
+        assert_eq!(cache.get(8), Some(8));
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 5);
+        assert!(log
+            .iter()
+            .all(|&(k, _, r)| (3..=7).contains(&k) && r == EvictReason::Capacity));
+    }
+
+    #[test]
+    fn resize_down_keeps_most_hit_under_lfu() {
+        let cache: LRUCache<AtomicUsize, AtomicUsize> =
+            LRUCache::with_policy(4, 1, identity, EvictionPolicy::Lfu);
+        for k in 1..=4 {
+            cache.put(k, k);
+        }
+        for _ in 0..3 {
+            cache.get(1);
+            cache.get(3);
+        }
+        cache.resize(2);
+        assert!(cache.contains_key(1) && cache.contains_key(3));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn resize_accounts_dropped_entries() {
+        let cache = cache(8, 2).with_max_probe(2);
+        for k in 1..=8 {
+            cache.put(k, k);
+        }
+        cache.resize(4);
+        assert!(is_packed(&cache));
+        cache.reset_occupancy_stats();
+        assert_eq!(cache.occupancy_stats().high_water_mark, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "new_capacity must not be less than num_folds")]
+    fn resize_below_fold_count_panics() {
+        cache(8, 4).resize(3);
+    }
+
+    #[test]
+    fn snapshot_lists_every_entry_with_hits() {
+        let cache = cache(8, 2);
+        for k in 1..=4 {
+            cache.put(k, k * 10);
+        }
+        cache.get(3);
+        cache.get(3);
+        let mut snapshot = cache.snapshot();
+        snapshot.sort_unstable();
+        assert_eq!(
+            snapshot,
+            vec![(1, 10, 1), (2, 20, 1), (3, 30, 3), (4, 40, 1)]
+        );
+        let mut keys = cache.keys();
+        keys.sort_unstable();
+        assert_eq!(keys, vec![1, 2, 3, 4]);
+        cache.clear();
+        assert!(cache.snapshot().is_empty());
+    }
+
+    #[test]
+    fn pop_least_used_drains_coldest_first() {
+        let (cache, log) = recording_cache(8, 2);
+        for k in 1..=8 {
+            cache.put(k, k * 10);
+        }
+        for k in 1..=8 {
+            for _ in 0..(k * 5) % 7 {
+                cache.get(k);
+            }
+        }
+        let mut popped = 0;
+        loop {
+            let before = cache.snapshot();
+            let Some((key, value)) = cache.pop_least_used() else {
+                break;
+            };
+            assert_eq!(value, key * 10);
+            let fold = cache.get_fold_index(key);
+            let hits = |k: usize| before.iter().find(|e| e.0 == k).unwrap().2;
+            let coldest = before
+                .iter()
+                .filter(|e| cache.get_fold_index(e.0) == fold)
+                .map(|e| e.2)
+                .min();
+            assert_eq!(Some(hits(key)), coldest, "{key} was not its fold's coldest");
+            popped += 1;
+        }
+        assert_eq!(popped, 8);
+        assert!(cache.is_empty());
+        assert_eq!(log.lock().unwrap().len(), 8);
+        assert_eq!(cache.pop_least_used(), None);
+    }
+
+    #[test]
+    fn pop_fold_lru_drains_one_fold_by_hits() {
+        let cache = cache(16, 2);
+        for k in 0..16 {
+            cache.put(k, k * 10);
+        }
+        // Even keys fill fold 0; key 2k gets (5k mod 8) lookups.
+        for k in 0..8 {
+            for _ in 0..(k * 5) % 8 {
+                cache.get(2 * k);
+            }
+        }
+        let mut drained = Vec::new();
+        while cache.fold_len(0) > 2 {
+            let (key, value) = cache.pop_fold_lru(0).unwrap();
+            assert_eq!(value, key * 10);
+            drained.push((key / 2 * 5) % 8);
+        }
+        assert_eq!(drained, [0, 1, 2, 3, 4, 5]);
+        assert_eq!(cache.fold_len(1), 8, "other folds are untouched");
+        assert!(cache.pop_fold_lru(0).is_some() && cache.pop_fold_lru(0).is_some());
+        assert_eq!(cache.pop_fold_lru(0), None);
+        assert_eq!(cache.pop_fold_lru(2), None, "out of range");
+    }
+
+    /// A cache on a clock that only moves when the test advances it.
+    fn ttl_cache(
+        capacity: usize,
+        folds: usize,
+    ) -> (LRUCache<AtomicUsize, AtomicUsize>, Evicted, Arc<AtomicU64>) {
+        let now = Arc::new(AtomicU64::new(1_000));
+        let clock = Arc::clone(&now);
+        let (cache, log) = recording_cache(capacity, folds);
+        let cache = cache.with_time_source(move || clock.load(Ordering::Relaxed));
+        (cache, log, now)
+    }
+
+    #[test]
+    fn ttl_entries_expire_on_the_time_source() {
+        let (cache, _, now) = ttl_cache(4, 1);
+        cache.put_with_ttl(1, 10, Duration::from_millis(50));
+        cache.put(2, 20);
+        now.fetch_add(49, Ordering::Relaxed);
+        assert_eq!(cache.get(1), Some(10));
+        now.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(cache.get(1), None);
+        assert!(!cache.contains_key(1));
+        assert_eq!(cache.get(2), Some(20), "plain puts never expire");
+        assert_eq!(cache.len(), 2, "expired entries keep their slot");
+        assert_eq!(cache.put(1, 11), None, "an expired value is not returned");
+        now.fetch_add(1_000, Ordering::Relaxed);
+        assert_eq!(cache.get(1), Some(11), "a plain put clears the deadline");
+    }
+
+    #[test]
+    fn full_fold_evicts_expired_entries_first() {
+        let (cache, log, now) = ttl_cache(3, 1);
+        cache.put(1, 10);
+        cache.put_with_ttl(2, 20, Duration::from_millis(10));
+        cache.put(3, 30);
+        for k in [2, 3] {
+            cache.get(k);
+        }
+        now.fetch_add(10, Ordering::Relaxed);
+        cache.put(4, 40);
+        // Key 1 is the least recently used, but key 2 has expired.
+        assert_eq!(*log.lock().unwrap(), [(2, 20, EvictReason::Expired)]);
+        let mut keys = cache.keys();
+        keys.sort();
+        assert_eq!(keys, [1, 3, 4]);
+    }
+
+    #[test]
+    fn purge_expired_frees_slots_and_keeps_folds_packed() {
+        let (cache, log, now) = ttl_cache(8, 1);
+        let cache = cache.with_max_probe(8);
+        for k in 1..=6 {
+            if k % 2 == 0 {
+                cache.put_with_ttl(k, k * 10, Duration::from_millis(5));
+            } else {
+                cache.put(k, k * 10);
+            }
+        }
+        assert_eq!(cache.purge_expired(), 0);
+        now.fetch_add(5, Ordering::Relaxed);
+        assert_eq!(cache.purge_expired(), 3);
+        assert_eq!(cache.len(), 3);
+        assert!(is_packed(&cache));
+        let mut purged: Vec<_> = log.lock().unwrap().iter().map(|&(k, _, _)| k).collect();
+        purged.sort();
+        assert_eq!(purged, [2, 4, 6]);
+        assert!(log
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|&(_, _, reason)| reason == EvictReason::Expired));
+        for k in [1, 3, 5] {
+            assert_eq!(cache.get(k), Some(k * 10));
+        }
+    }
+
+    #[test]
+    fn remove_if_drops_matching_entries_fold_by_fold() {
+        let (cache, log, now) = ttl_cache(16, 4);
+        let cache = cache.with_max_probe(4);
+        for k in 0..12 {
+            cache.put(k, k * 10);
+        }
+        cache.put_with_ttl(13, 0, Duration::from_millis(5));
+        now.fetch_add(5, Ordering::Relaxed);
+        assert_eq!(cache.remove_if(|k, v| k % 2 == 1 && v != 50), 5);
+        assert_eq!(cache.len(), 7);
+        assert!(is_packed(&cache));
+        let mut keys = cache.keys();
+        keys.sort();
+        assert_eq!(keys, [0, 2, 4, 5, 6, 8, 10]);
+        let mut logged = log.lock().unwrap().clone();
+        logged.sort_by_key(|&(k, _, _)| k);
+        assert_eq!(logged.len(), 6);
+        assert_eq!(logged[5], (13, 0, EvictReason::Expired));
+        assert!(logged[..5]
+            .iter()
+            .all(|&(_, _, reason)| reason == EvictReason::Removed));
+    }
+
+    #[test]
+    fn removing_an_expired_entry_reports_it_as_expired() {
+        let (cache, log, now) = ttl_cache(4, 1);
+        cache.put_with_ttl(1, 10, Duration::ZERO);
+        assert_eq!(cache.get(1), None, "a zero ttl expires at once");
+        now.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(cache.remove(1), None);
+        assert!(cache.is_empty());
+        assert_eq!(*log.lock().unwrap(), [(1, 10, EvictReason::Expired)]);
+    }
+
+    #[test]
+    fn fold_remainder_is_spread_over_the_first_folds() {
+        let cache = cache(10, 3);
+        let sizes: Vec<_> = cache
+            .fold_utilization()
+            .iter()
+            .map(|&(_, cap)| cap)
+            .collect();
+        assert_eq!(sizes, [4, 3, 3]);
+        // Under the identity hasher, keys 0..10 fill the folds 4/3/3.
+        for k in 0..10 {
+            cache.put(k, k);
+        }
+        assert_eq!(cache.stats().evictions, 0);
+        assert_eq!(cache.len(), 10);
+    }
+
+    #[test]
+    fn metrics_text_matches_cache_state() {
+        let cache = cache(16, 4);
+        for k in (0..24).step_by(4) {
+            cache.put(k, k);
+        }
+        cache.put(1, 1);
+        cache.get(1);
+        cache.get(2);
+        let text = cache.metrics_text("lru");
+        let mut samples = HashMap::new();
+        for line in text.lines() {
+            if let Some(meta) = line.strip_prefix("# ") {
+                let mut words = meta.split(' ');
+                assert!(matches!(words.next(), Some("HELP" | "TYPE")), "{line}");
+                assert!(words.next().unwrap().starts_with("lru_"), "{line}");
+                continue;
+            }
+            let (name, value) = line.rsplit_once(' ').unwrap();
+            samples.insert(name.to_string(), value.parse::<u64>().unwrap());
+        }
+        assert!(text.contains("# TYPE lru_hits_total counter\n"));
+        assert!(text.contains("# TYPE lru_fold_occupancy gauge\n"));
+        let expected = [
+            ("lru_size", 5),
+            ("lru_capacity", 16),
+            ("lru_hits_total", 1),
+            ("lru_misses_total", 1),
+            ("lru_evictions_total", 2),
+            ("lru_fold_occupancy{fold=\"0\"}", 4),
+            ("lru_fold_occupancy{fold=\"1\"}", 1),
+            ("lru_fold_occupancy{fold=\"2\"}", 0),
+            ("lru_fold_occupancy{fold=\"3\"}", 0),
+        ];
+        assert_eq!(samples.len(), expected.len());
+        for (name, value) in expected {
+            assert_eq!(samples[name], value, "{name}");
+        }
+    }
+
+    #[test]
+    fn summary_and_debug_show_fold_occupancy() {
+        let cache = cache(16, 4);
+        for k in (0..24).step_by(4) {
+            cache.put(k, k);
+        }
+        cache.put(1, 1);
+        cache.get(1);
+        cache.get(2);
+        assert_eq!(
+            cache.summary(),
+            "capacity 16, 4 folds (4/4 1/4 0/4 0/4), policy Lru, hits 1, misses 1, evictions 2"
+        );
+        let debug = format!("{cache:?}");
+        assert!(debug.starts_with("LRUCache { capacity: 16, num_folds: 4, policy: Lru, "));
+        assert!(debug.contains("folds: [(4, 4), (1, 4), (0, 4), (0, 4)]"));
+        assert!(debug.contains("evictions: 2"));
+    }
+
+    #[test]
+    fn fold_utilization_shows_skewed_keys() {
+        let cache = cache(16, 4);
+        // Multiples of 4 all land in fold 0 under the identity hasher.
+        for k in (0..40).step_by(4) {
+            cache.put(k, k);
+        }
+        cache.put(1, 1);
+        cache.put(6, 6);
+        assert_eq!(
+            (cache.fold_of(8), cache.fold_of(1), cache.fold_of(6)),
+            (0, 1, 2)
+        );
+        assert_eq!(cache.fold_utilization(), [(4, 4), (1, 4), (1, 4), (0, 4)]);
+        assert_eq!(cache.fold_len(0), 4);
+        assert_eq!(cache.fold_len(3), 0);
+        assert_eq!(cache.stats().evictions, 6, "fold 0 churned");
+    }
+
+    #[test]
+    fn rebalancing_lends_free_slots_to_a_full_fold() {
+        // Even keys all land in fold 0 of 2; 24 of the first 32 find it full.
+        let skewed = |percent| {
+            let cache = cache(16, 2).with_rebalancing(percent);
+            for k in 0..REBALANCE_WINDOW + 8 {
+                cache.put(2 * k, k);
+            }
+            cache
+        };
+        let fixed = skewed(0);
+        assert_eq!((fixed.len(), fixed.stats().rebalanced), (8, 0));
+        let cache = skewed(50);
+        assert_eq!(cache.fold_utilization(), [(8, 8), (8, 8)]);
+        assert_eq!(cache.stats().rebalanced, 8);
+        assert!((24..40).all(|k| cache.get(2 * k) == Some(k)));
+        assert_eq!(skewed(90).stats().rebalanced, 0, "75% full is below 90%");
+
+        // Borrowed slots hold their keys like any other.
+        assert_eq!(cache.put(70, 0), Some(35));
+        assert_eq!(cache.len(), 16);
+        assert_eq!(cache.get(70), Some(0));
+        assert_eq!(cache.modify(72, |v| v + 1), Some(37));
+        assert!(cache.pin(74) && cache.contains_key(74));
+        assert_eq!(cache.remove(76), Some(38));
+        assert!(!cache.contains_key(76));
+
+        // Fold 1's own keys take its slots back, all but the pinned one.
+        for k in (1..16).step_by(2) {
+            cache.put(k, k);
+        }
+        assert_eq!(cache.get(74), Some(37));
+        assert!([64, 66, 68, 70, 72, 78]
+            .iter()
+            .all(|&k| cache.get(k).is_none()));
+
+        cache.resize(16);
+        assert_eq!(cache.fold_of(74), 0);
+        assert_eq!(cache.fold_len(0), 8);
+        assert_eq!(cache.get(74), Some(37), "pinned entries move home first");
+    }
+
+    #[test]
+    fn put_returning_reports_the_victim() {
+        let (cache, log) = recording_cache(4, 1);
+        for k in 1..=4 {
+            assert_eq!(cache.put_returning(k, k * 10), None, "free slot");
+        }
+        assert_eq!(cache.put_returning(2, 21), None, "update in place");
+        assert_eq!(cache.put_returning(5, 50), Some((1, 10)));
+        assert_eq!(cache.put_returning(6, 60), Some((3, 30)));
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                (1, 10, EvictReason::Capacity),
+                (3, 30, EvictReason::Capacity)
+            ]
+        );
+    }
+
+    #[test]
+    fn pinned_entries_survive_a_full_fold() {
+        let (cache, log) = recording_cache(8, 2);
+        // Fold 0 holds the even keys, fold 1 the odd ones.
+        for k in [0, 2, 4, 6] {
+            cache.put(k, k * 10);
+            assert!(cache.pin(k));
+        }
+        assert!(!cache.pin(8), "absent keys cannot be pinned");
+        assert_eq!(cache.try_put(8, 80), Err(CacheFull));
+        assert_eq!(cache.put(10, 100), None);
+        assert_eq!(cache.get(10), None, "put drops the insert");
+        assert_eq!(cache.try_put(2, 21), Ok(Some(20)), "updates still work");
+        cache.put(1, 10);
+        for k in [0, 2, 4, 6] {
+            assert!(cache.contains_key(k));
+        }
+        assert_eq!(cache.get(1), Some(10), "other folds are unaffected");
+        assert_eq!(cache.pop_least_used(), Some((1, 10)), "pops skip pins");
+        assert_eq!(cache.pop_least_used(), None);
+        assert!(log
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|&(_, _, r)| r == EvictReason::Removed));
+
+        assert!(cache.unpin(0));
+        assert_eq!(cache.try_put(8, 80), Ok(None));
+        assert!(
+            !cache.contains_key(0),
+            "unpinned entries are evictable again"
+        );
+        assert_eq!(cache.remove(2), Some(21), "pinned entries can be removed");
+        assert_eq!(cache.try_put(12, 120), Ok(None));
+        assert!(cache.contains_key(4) && cache.contains_key(6));
+    }
+
+    #[test]
+    fn slot_counters_are_padded_only_with_the_feature() {
+        let size = std::mem::size_of::<SlotCounters>();
+        if cfg!(feature = "padded-slots") {
+            assert_eq!((size, std::mem::align_of::<SlotCounters>()), (64, 64));
+        } else {
+            assert_eq!(size, 2 * std::mem::size_of::<usize>());
+        }
+    }
+
+    #[test]
+    fn narrow_storage_types() {
+        use std::sync::atomic::{AtomicU16, AtomicU8};
+        let cache: LRUCache<AtomicU16, AtomicU8> = LRUCache::new(4, 2, identity);
+        cache.put(300, 7);
+        assert_eq!(cache.get(300), Some(7));
+    }
+
+    #[test]
+    fn slot_versions_are_even_between_writes() {
+        let cache = cache(2, 1);
+        let version = |i: usize| cache.slots().versions[i].load(Ordering::Relaxed);
+        cache.put(1, 10);
+        assert_eq!(version(0), 2);
+        cache.put(1, 11);
+        assert_eq!(version(0), 2, "in-place updates keep the version");
+        cache.remove(1);
+        cache.put(1, 12);
+        assert_eq!(version(0), 6);
+        assert_eq!(cache.get(1), Some(12));
+        assert!((0..2).all(|i| version(i) % 2 == 0));
+    }
+
+    #[test]
+    fn byte_values_wider_than_a_word() {
+        let cache: LRUCacheBytes<24> = LRUCache::new(4, 2, identity);
+        let extent: [u8; 24] = std::array::from_fn(|i| i as u8);
+        assert_eq!(cache.put(1, extent), None);
+        assert_eq!(cache.get(1), Some(extent));
+        assert_eq!(cache.put(1, [9; 24]), Some(extent));
+        for k in 2..=5 {
+            cache.put(k, [k as u8; 24]);
+        }
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.remove(5), Some([5; 24]));
+        assert_eq!(cache.get(0), None);
+    }
+}
@@ -0,0 +1,153 @@
+//! A ready-made `usize → usize` cache with the sharded cache's call shape.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::cache::{EvictionPolicy, LRUCache};
+use crate::hasher::FibonacciHasher;
+use crate::stats::CacheStats;
+use crate::sync::AtomicUsize;
+
+/// Slots per fold the facade aims for: short enough that the lock-free scan
+/// stays cheap, long enough that the eviction choice within a fold means
+/// something.
+const TARGET_FOLD_SLOTS: usize = 64;
+
+/// Returned by [`FastUsizeCache::try_new`] for a zero capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZeroCapacityError;
+
+impl fmt::Display for ZeroCapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("cache capacity must be non-zero")
+    }
+}
+
+impl Error for ZeroCapacityError {}
+
+/// A `usize → usize` cache backed by [`LRUCache`], so reads never lock.
+///
+/// Fold count and hasher are derived from the capacity, and keys are taken
+/// by reference, so code written against `ShardedLruCache<usize, usize>` can
+/// switch over by changing the type. As with [`LRUCache`], eviction is per
+/// fold, so an insert can displace an entry before the cache as a whole is
+/// full.
+pub struct FastUsizeCache {
+    inner: LRUCache<AtomicUsize, AtomicUsize, FibonacciHasher>,
+}
+
+impl FastUsizeCache {
+    /// Creates a cache holding at most `capacity` entries.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero; see [`try_new`](Self::try_new).
+    pub fn new(capacity: usize) -> Self {
+        match Self::try_new(capacity) {
+            Ok(cache) => cache,
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    /// Creates a cache holding at most `capacity` entries, or an error if
+    /// `capacity` is zero.
+    pub fn try_new(capacity: usize) -> Result<Self, ZeroCapacityError> {
+        if capacity == 0 {
+            return Err(ZeroCapacityError);
+        }
+        let folds = (capacity / TARGET_FOLD_SLOTS).max(1);
+        Ok(Self {
+            inner: LRUCache::with_hasher(
+                capacity,
+                folds,
+                FibonacciHasher::default(),
+                EvictionPolicy::default(),
+            ),
+        })
+    }
+
+    pub fn get(&self, key: &usize) -> Option<usize> {
+        self.inner.get(*key)
+    }
+
+    /// Inserts or updates `key`, returning the previous value.
+    pub fn put(&self, key: usize, value: usize) -> Option<usize> {
+        self.inner.put(key, value)
+    }
+
+    pub fn remove(&self, key: &usize) -> Option<usize> {
+        self.inner.remove(*key)
+    }
+
+    /// Returns the value for `key` without counting as an access, so
+    /// neither eviction order nor [`stats`](Self::stats) changes.
+    pub fn peek(&self, key: &usize) -> Option<usize> {
+        self.inner.peek(*key)
+    }
+
+    pub fn contains_key(&self, key: &usize) -> bool {
+        self.inner.contains_key(*key)
+    }
+
+    /// Every cached entry, in no particular order. See
+    /// [`LRUCache::snapshot`] for how it copes with concurrent writers.
+    pub fn entries(&self) -> Vec<(usize, usize)> {
+        self.inner
+            .snapshot()
+            .into_iter()
+            .map(|(key, value, _)| (key, value))
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.inner.clear()
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
+    pub fn total_capacity(&self) -> usize {
+        self.inner.capacity()
+    }
+
+    /// See [`LRUCache::stats`].
+    pub fn stats(&self) -> CacheStats {
+        self.inner.stats()
+    }
+
+    pub fn reset_stats(&self) {
+        self.inner.reset_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_new_rejects_zero_capacity() {
+        assert_eq!(FastUsizeCache::try_new(0).err(), Some(ZeroCapacityError));
+        assert!(FastUsizeCache::try_new(1).is_ok());
+    }
+
+    #[test]
+    fn fold_count_follows_capacity() {
+        assert_eq!(FastUsizeCache::new(10).inner.num_folds(), 1);
+        assert_eq!(FastUsizeCache::new(64 * 100).inner.num_folds(), 100);
+    }
+
+    #[test]
+    fn sequential_keys_fill_the_cache() {
+        let cache = FastUsizeCache::new(1_024);
+        for k in 1..=1_024 {
+            cache.put(k, k);
+        }
+        // Mixing keeps folds balanced enough that most keys survive.
+        assert!(cache.len() > 900, "only {} keys kept", cache.len());
+    }
+}
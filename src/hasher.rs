@@ -0,0 +1,78 @@
+//! Mapping keys to folds.
+
+/// Spreads keys over folds: a key lives in fold
+/// `hasher.fold_hash(key) % num_folds`.
+///
+/// Implemented for any `Fn(usize) -> usize + Send + Sync`, so plain functions
+/// and capturing closures both work.
+pub trait FoldHasher: Send + Sync {
+    fn fold_hash(&self, key: usize) -> usize;
+}
+
+impl<F: Fn(usize) -> usize + Send + Sync> FoldHasher for F {
+    fn fold_hash(&self, key: usize) -> usize {
+        self(key)
+    }
+}
+
+/// Seeded Fibonacci (multiply-rotate) hashing. Cheap, and spreads sequential
+/// or strided keys evenly over folds where the identity hash would not.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FibonacciHasher {
+    seed: u64,
+}
+
+impl FibonacciHasher {
+    pub fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+}
+
+impl FoldHasher for FibonacciHasher {
+    fn fold_hash(&self, key: usize) -> usize {
+        (key as u64 ^ self.seed)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            .rotate_left(29) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fold_sizes(hasher: &impl FoldHasher, keys: impl Iterator<Item = usize>) -> Vec<usize> {
+        let mut sizes = vec![0; 64];
+        for k in keys {
+            sizes[hasher.fold_hash(k) % 64] += 1;
+        }
+        sizes
+    }
+
+    #[test]
+    fn sequential_keys_spread_evenly() {
+        let sizes = fold_sizes(&FibonacciHasher::default(), 1..=64_000);
+        assert!(
+            sizes.iter().all(|&n| (900..=1_100).contains(&n)),
+            "uneven folds: {sizes:?}"
+        );
+    }
+
+    #[test]
+    fn strided_keys_spread_unlike_identity() {
+        let strided = || (1..=8_000).map(|k| k * 64);
+        let identity = fold_sizes(&|k| k, strided());
+        assert_eq!(identity[0], 8_000);
+        let sizes = fold_sizes(&FibonacciHasher::new(7), strided());
+        assert!(
+            sizes.iter().all(|&n| (75..=175).contains(&n)),
+            "uneven folds: {sizes:?}"
+        );
+    }
+
+    #[test]
+    fn seed_changes_placement() {
+        let a = FibonacciHasher::new(1);
+        let b = FibonacciHasher::new(2);
+        assert!((1..100).any(|k| a.fold_hash(k) % 64 != b.fold_hash(k) % 64));
+    }
+}
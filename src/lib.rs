@@ -0,0 +1,61 @@
+//! A thread-safe, fixed-capacity cache whose reads never take a lock.
+//!
+//! [`LRUCache`] keeps keys, values and per-slot metadata in flat arrays of
+//! atomics. The slots are partitioned into folds; each key hashes to exactly
+//! one fold, readers scan that fold lock-free, and writers claim slots by
+//! compare-and-swap, locking a per-fold mutex only to evict. Values wider
+//! than a word are stored behind a per-slot seqlock ([`LRUCacheBytes`]).
+//! See `DESIGN.md` for the read/write protocol.
+//!
+//! ```
+//! use std::sync::atomic::AtomicUsize;
+//! use lru_rs::LRUCache;
+//!
+//! let cache: LRUCache<AtomicUsize, AtomicUsize> = LRUCache::new(1024, 8, |k| k);
+//! cache.put(1, 100);
+//! assert_eq!(cache.get(1), Some(100));
+//! ```
+//!
+//! The workspace's other implementations are re-exported behind features,
+//! each in its own module so their type names do not collide with these:
+//!
+//! * `sharded`: `sharded::ShardedLruCache`, a mutex-per-shard LRU with
+//!   exact recency within each shard.
+//! * `single-lock`: `single_lock::LruCache`, a single-mutex LRU with exact
+//!   global recency.
+//!
+//! The `padded-slots` feature gives each slot's hit counter its own cache
+//! line, at 64 bytes per slot, for workloads that hit neighbouring keys
+//! from many cores.
+
+mod builder;
+mod cache;
+mod fast;
+mod hasher;
+mod snapshot;
+mod stats;
+mod storage;
+mod sync;
+
+pub use builder::{BuildError, LRUCacheBuilder};
+pub use cache::{
+    auto_fold_count, CacheFull, EvictReason, EvictionPolicy, LRUCache, LRUCacheBytes,
+    AUTO_FOLDS_PER_THREAD, MIN_SLOTS_PER_FOLD, REBALANCE_WINDOW,
+};
+pub use fast::{FastUsizeCache, ZeroCapacityError};
+pub use hasher::{FibonacciHasher, FoldHasher};
+pub use snapshot::SnapshotError;
+pub use stats::{CacheStats, OccupancyStats, TagStats, MAX_TAGS, UNTAGGED};
+pub use storage::{AtomicStorage, SeqLockBytes};
+
+/// The sharded LRU cache from the `solution_2` crate.
+#[cfg(feature = "sharded")]
+pub mod sharded {
+    pub use solution_2::*;
+}
+
+/// The single-lock LRU cache from the `solution_1` crate.
+#[cfg(feature = "single-lock")]
+pub mod single_lock {
+    pub use solution_1::*;
+}
@@ -0,0 +1,310 @@
+//! The byte image written by [`LRUCache::to_snapshot`].
+//!
+//! All integers are little-endian:
+//!
+//! | bytes          | field                                              |
+//! |----------------|----------------------------------------------------|
+//! | 4              | magic, `LRUS`                                      |
+//! | 1              | format version, 1                                  |
+//! | 1              | [`EvictionPolicy`], as its index in `ALL`          |
+//! | 2 + 2          | key and value size (`AtomicStorage::SIZE`)         |
+//! | 8 + 8          | capacity and fold count                            |
+//! | 8              | entry count `n`                                    |
+//! | `n` × entry    | key, value, then the hit count as 8 bytes          |
+//!
+//! Entries go from the first the policy would evict to the last, so
+//! restoring them in order rebuilds the same ranking.
+//!
+//! [`LRUCache::to_snapshot`]: crate::LRUCache::to_snapshot
+
+use std::error::Error;
+use std::fmt;
+
+use crate::builder::BuildError;
+use crate::cache::EvictionPolicy;
+use crate::storage::AtomicStorage;
+
+const MAGIC: [u8; 4] = *b"LRUS";
+const VERSION: u8 = 1;
+
+/// Why [`LRUCache::from_snapshot`](crate::LRUCache::from_snapshot) rejected
+/// an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The image ends before its last field or entry.
+    Truncated,
+    /// The image does not start with the snapshot magic bytes.
+    BadMagic,
+    /// The image was written by an unknown format version.
+    UnsupportedVersion(u8),
+    /// The image's keys or values are a different size from the cache's
+    /// storage types.
+    SizeMismatch { key: usize, value: usize },
+    /// A field holds an impossible value.
+    Corrupt(&'static str),
+    /// The capacity and fold count are no valid cache shape.
+    Shape(BuildError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => f.write_str("snapshot is truncated"),
+            Self::BadMagic => f.write_str("not a cache snapshot"),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported snapshot version {v}"),
+            Self::SizeMismatch { key, value } => {
+                write!(f, "snapshot holds {key}-byte keys and {value}-byte values")
+            }
+            Self::Corrupt(what) => write!(f, "corrupt snapshot: {what}"),
+            Self::Shape(e) => write!(f, "invalid snapshot shape: {e}"),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Shape(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A decoded image.
+pub(crate) struct Snapshot<K, V> {
+    pub(crate) policy: EvictionPolicy,
+    pub(crate) capacity: usize,
+    pub(crate) folds: usize,
+    /// `(key, value, hits)`, first victim first.
+    pub(crate) entries: Vec<(K, V, usize)>,
+}
+
+/// Encodes an image; `entries` must already be in victim order.
+pub(crate) fn encode<K: AtomicStorage, V: AtomicStorage>(
+    snapshot: &Snapshot<K::Value, V::Value>,
+) -> Vec<u8> {
+    let entry_size = K::SIZE + V::SIZE + 8;
+    let mut out = Vec::with_capacity(34 + snapshot.entries.len() * entry_size);
+    out.extend_from_slice(&MAGIC);
+    out.push(VERSION);
+    let policy = EvictionPolicy::ALL
+        .iter()
+        .position(|&p| p == snapshot.policy);
+    out.push(policy.expect("ALL lists every policy") as u8);
+    for size in [K::SIZE, V::SIZE] {
+        out.extend_from_slice(&(size as u16).to_le_bytes());
+    }
+    for n in [snapshot.capacity, snapshot.folds, snapshot.entries.len()] {
+        out.extend_from_slice(&(n as u64).to_le_bytes());
+    }
+    for &(key, value, hits) in &snapshot.entries {
+        K::write_bytes(key, &mut out);
+        V::write_bytes(value, &mut out);
+        out.extend_from_slice(&(hits as u64).to_le_bytes());
+    }
+    out
+}
+
+/// Decodes an image written by [`encode`] for the same storage types.
+pub(crate) fn decode<K: AtomicStorage, V: AtomicStorage>(
+    bytes: &[u8],
+) -> Result<Snapshot<K::Value, V::Value>, SnapshotError> {
+    let mut input = Reader(bytes);
+    if input.take(MAGIC.len())? != MAGIC {
+        return Err(SnapshotError::BadMagic);
+    }
+    let version = input.u8()?;
+    if version != VERSION {
+        return Err(SnapshotError::UnsupportedVersion(version));
+    }
+    let policy = *EvictionPolicy::ALL
+        .get(usize::from(input.u8()?))
+        .ok_or(SnapshotError::Corrupt("unknown eviction policy"))?;
+    let (key, value) = (input.u16()?, input.u16()?);
+    if (key, value) != (K::SIZE, V::SIZE) {
+        return Err(SnapshotError::SizeMismatch { key, value });
+    }
+    let capacity = input.len()?;
+    let folds = input.len()?;
+    let count = input.len()?;
+    let entry_size = K::SIZE + V::SIZE + 8;
+    // Check the count against the length before allocating for it.
+    match count.checked_mul(entry_size) {
+        Some(len) if len == input.0.len() => {}
+        Some(len) if len > input.0.len() => return Err(SnapshotError::Truncated),
+        None => return Err(SnapshotError::Truncated),
+        Some(_) => return Err(SnapshotError::Corrupt("trailing bytes")),
+    }
+    let entries = input
+        .0
+        .chunks_exact(entry_size)
+        .map(|entry| {
+            let (key, rest) = entry.split_at(K::SIZE);
+            let (value, hits) = rest.split_at(V::SIZE);
+            let hits = u64::from_le_bytes(hits.try_into().expect("8 bytes"));
+            (
+                K::read_bytes(key),
+                V::read_bytes(value),
+                usize::try_from(hits).unwrap_or(usize::MAX),
+            )
+        })
+        .collect();
+    Ok(Snapshot {
+        policy,
+        capacity,
+        folds,
+        entries,
+    })
+}
+
+/// The unread rest of an image.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.0.len() < n {
+            return Err(SnapshotError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<usize, SnapshotError> {
+        let bytes = self.take(2)?.try_into().expect("2 bytes");
+        Ok(usize::from(u16::from_le_bytes(bytes)))
+    }
+
+    /// A `u64` count that must fit a `usize`.
+    fn len(&mut self) -> Result<usize, SnapshotError> {
+        let bytes = self.take(8)?.try_into().expect("8 bytes");
+        usize::try_from(u64::from_le_bytes(bytes))
+            .map_err(|_| SnapshotError::Corrupt("count exceeds usize"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{LRUCache, LRUCacheBytes};
+    use crate::sync::{AtomicU32, AtomicU64, AtomicUsize};
+
+    type Cache = LRUCache<AtomicUsize, AtomicUsize>;
+
+    fn identity(k: usize) -> usize {
+        k
+    }
+
+    fn decoded(bytes: &[u8]) -> Result<Cache, SnapshotError> {
+        Cache::from_snapshot(bytes, identity)
+    }
+
+    #[test]
+    fn round_trip_keeps_entries_ranking_and_hits() {
+        let cache = Cache::new(4, 2, identity);
+        for k in 1..=4 {
+            cache.put(k, k * 10);
+        }
+        // Fold 1 holds 1 and 3; 1 becomes its most recent entry.
+        cache.get(1);
+        cache.get(1);
+        let restored = decoded(&cache.to_snapshot()).unwrap();
+        assert_eq!((restored.capacity(), restored.num_folds()), (4, 2));
+        let mut entries = restored.snapshot();
+        entries.sort();
+        assert_eq!(entries, [(1, 10, 3), (2, 20, 1), (3, 30, 1), (4, 40, 1)]);
+        restored.put(5, 50);
+        assert_eq!(restored.get(3), None, "3 was fold 1's least recent");
+        assert_eq!(restored.get(1), Some(10));
+    }
+
+    #[test]
+    fn round_trip_keeps_policy_and_wide_values() {
+        let cache = LRUCacheBytes::<12>::with_policy(2, 1, identity, EvictionPolicy::Lfu);
+        cache.put(1, [1; 12]);
+        cache.put(2, [2; 12]);
+        cache.get(1);
+        let restored = LRUCacheBytes::<12>::from_snapshot(&cache.to_snapshot(), identity).unwrap();
+        assert_eq!(restored.policy(), EvictionPolicy::Lfu);
+        restored.put(3, [3; 12]);
+        assert_eq!(restored.get(1), Some([1; 12]));
+        assert_eq!(restored.get(2), None, "2 had the fewest hits");
+    }
+
+    #[test]
+    fn a_new_hasher_evicts_the_lowest_ranked_overflow() {
+        let cache = Cache::new(4, 2, identity);
+        for k in [0, 2, 1, 3] {
+            cache.put(k, k);
+        }
+        // Everything lands in fold 0, which holds two.
+        let restored = Cache::from_snapshot(&cache.to_snapshot(), |_| 0).unwrap();
+        assert_eq!(restored.len(), 2);
+        let mut keys = restored.keys();
+        keys.sort();
+        assert_eq!(keys, [1, 3], "the two most recent survive");
+    }
+
+    #[test]
+    fn corrupt_images_are_rejected() {
+        let cache = Cache::new(4, 2, identity);
+        cache.put(1, 10);
+        cache.put(2, 20);
+        let image = cache.to_snapshot();
+        for len in 0..image.len() {
+            assert_eq!(
+                decoded(&image[..len]).err(),
+                Some(SnapshotError::Truncated),
+                "cut at {len}"
+            );
+        }
+        let mut bytes = image.clone();
+        bytes[0] = b'X';
+        assert_eq!(decoded(&bytes).err(), Some(SnapshotError::BadMagic));
+        let mut bytes = image.clone();
+        bytes[4] = 9;
+        assert_eq!(
+            decoded(&bytes).err(),
+            Some(SnapshotError::UnsupportedVersion(9))
+        );
+        let mut bytes = image.clone();
+        bytes[5] = 200;
+        assert_eq!(
+            decoded(&bytes).err(),
+            Some(SnapshotError::Corrupt("unknown eviction policy"))
+        );
+        let mut bytes = image.clone();
+        bytes.push(0);
+        assert_eq!(
+            decoded(&bytes).err(),
+            Some(SnapshotError::Corrupt("trailing bytes"))
+        );
+        let mut bytes = image.clone();
+        bytes[26..34].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(decoded(&bytes).is_err(), "a huge count is not allocated");
+        let mut bytes = image.clone();
+        bytes[10..18].fill(0);
+        assert_eq!(
+            decoded(&bytes).err(),
+            Some(SnapshotError::Shape(BuildError::ZeroCapacity))
+        );
+    }
+
+    #[test]
+    fn storage_sizes_must_match() {
+        let cache = LRUCache::<AtomicUsize, AtomicU64>::new(4, 1, identity);
+        let err = LRUCache::<AtomicUsize, AtomicU32>::from_snapshot(&cache.to_snapshot(), identity);
+        let size = std::mem::size_of::<usize>();
+        assert_eq!(
+            err.err(),
+            Some(SnapshotError::SizeMismatch {
+                key: size,
+                value: 8
+            })
+        );
+    }
+}
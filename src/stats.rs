@@ -0,0 +1,246 @@
+//! Operation counters for [`LRUCache`](crate::LRUCache).
+
+use std::sync::atomic::Ordering;
+
+use crate::sync::{AtomicU64, AtomicUsize};
+
+/// Counts of cache operations since creation or the last reset.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups that found their key.
+    pub hits: u64,
+    /// Lookups that did not.
+    pub misses: u64,
+    /// Puts that stored a key not already cached.
+    pub insertions: u64,
+    /// Puts that overwrote the value of a cached key.
+    pub updates: u64,
+    /// Entries displaced to make room for an insertion.
+    pub evictions: u64,
+    /// Misses reported by a bounded probe while entries lay past its window
+    /// (see [`LRUCache::with_max_probe`](crate::LRUCache::with_max_probe)).
+    /// Some of these may have been false misses.
+    pub probe_overflows: u64,
+    /// Entries moved between slots to keep them within the probe window.
+    pub relocations: u64,
+    /// Insertions into a slot borrowed from the next fold (see
+    /// [`LRUCache::with_rebalancing`](crate::LRUCache::with_rebalancing)).
+    pub rebalanced: u64,
+    /// Set when the policy keeps no per-entry hit counts (see
+    /// [`EvictionPolicy::counts_hits`](crate::EvictionPolicy::counts_hits)),
+    /// so those reported by [`LRUCache::snapshot`](crate::LRUCache::snapshot)
+    /// are zero rather than measured. `hits` above is counted either way.
+    pub entry_hits_unavailable: bool,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that hit, or 0 if there were none.
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// Name under which [`LRUCache::stats_by_tag`](crate::LRUCache::stats_by_tag)
+/// reports untagged calls and calls with an unregistered tag.
+pub const UNTAGGED: &str = "untagged";
+
+/// Maximum number of tags a cache can register.
+pub const MAX_TAGS: usize = 16;
+
+/// Counts of operations attributed to one caller tag.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TagStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Puts of a storable key, whether they inserted or updated.
+    pub puts: u64,
+}
+
+/// Live counters of one tag within one fold; padded like [`Counters`].
+#[derive(Default)]
+#[repr(align(64))]
+pub(crate) struct TagCounters {
+    pub(crate) hits: AtomicU64,
+    pub(crate) misses: AtomicU64,
+    pub(crate) puts: AtomicU64,
+}
+
+impl TagCounters {
+    pub(crate) fn add_to(&self, total: &mut TagStats) {
+        total.hits += self.hits.load(Ordering::Relaxed);
+        total.misses += self.misses.load(Ordering::Relaxed);
+        total.puts += self.puts.load(Ordering::Relaxed);
+    }
+
+    pub(crate) fn reset(&self) {
+        for c in [&self.hits, &self.misses, &self.puts] {
+            c.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// One stripe of live counters. Each fold owns a stripe, padded to its own
+/// cache line so that counting on one fold never contends with another.
+#[derive(Default)]
+#[repr(align(64))]
+pub(crate) struct Counters {
+    pub(crate) hits: AtomicU64,
+    pub(crate) misses: AtomicU64,
+    pub(crate) insertions: AtomicU64,
+    pub(crate) updates: AtomicU64,
+    pub(crate) evictions: AtomicU64,
+    pub(crate) probe_overflows: AtomicU64,
+    pub(crate) relocations: AtomicU64,
+    pub(crate) rebalanced: AtomicU64,
+}
+
+impl Counters {
+    fn fields(&self) -> [&AtomicU64; 8] {
+        [
+            &self.hits,
+            &self.misses,
+            &self.insertions,
+            &self.updates,
+            &self.evictions,
+            &self.probe_overflows,
+            &self.relocations,
+            &self.rebalanced,
+        ]
+    }
+
+    /// Adds this stripe's counts to `total`.
+    pub(crate) fn add_to(&self, total: &mut CacheStats) {
+        let [hits, misses, insertions, updates, evictions, probe_overflows, relocations, rebalanced] =
+            self.fields().map(|c| c.load(Ordering::Relaxed));
+        total.hits += hits;
+        total.misses += misses;
+        total.insertions += insertions;
+        total.updates += updates;
+        total.evictions += evictions;
+        total.probe_overflows += probe_overflows;
+        total.relocations += relocations;
+        total.rebalanced += rebalanced;
+    }
+
+    pub(crate) fn reset(&self) {
+        for c in self.fields() {
+            c.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Counts one event. Counters are statistics, not synchronization, so
+/// Relaxed is enough.
+pub(crate) fn bump(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Occupancy and turnover figures, as returned by
+/// [`LRUCache::occupancy_stats`](crate::LRUCache::occupancy_stats).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OccupancyStats {
+    /// Largest number of entries held at once.
+    pub high_water_mark: usize,
+    /// New keys stored.
+    pub insertions: u64,
+    /// Entries displaced by those insertions.
+    pub evictions: u64,
+    /// Evictions per insertion over the last completed window (see
+    /// [`rotate_occupancy_window`](crate::LRUCache::rotate_occupancy_window)),
+    /// or 0 if it had no insertions.
+    pub churn_rate: f64,
+}
+
+/// Live occupancy counters. They are reset separately from [`Counters`],
+/// so a capacity-planning window can outlive several hit-rate windows.
+#[derive(Default)]
+pub(crate) struct Occupancy {
+    len: AtomicUsize,
+    high_water_mark: AtomicUsize,
+    insertions: AtomicU64,
+    evictions: AtomicU64,
+    window_insertions: AtomicU64,
+    window_evictions: AtomicU64,
+    last_window_insertions: AtomicU64,
+    last_window_evictions: AtomicU64,
+}
+
+impl Occupancy {
+    /// Records a new key, which displaced another entry if `evicted`.
+    pub(crate) fn inserted(&self, evicted: bool) {
+        bump(&self.insertions);
+        bump(&self.window_insertions);
+        if evicted {
+            bump(&self.evictions);
+            bump(&self.window_evictions);
+        } else {
+            let len = self.len.fetch_add(1, Ordering::Relaxed) + 1;
+            self.high_water_mark.fetch_max(len, Ordering::Relaxed);
+        }
+    }
+
+    /// Records `n` entries leaving without being replaced.
+    pub(crate) fn dropped(&self, n: usize) {
+        self.len.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    /// Live entry count. An entry is counted just after it is published
+    /// and uncounted just after it is unpublished, so a removal that
+    /// overtakes the insert it undoes can dip the count below zero for a
+    /// moment; that reads as 0.
+    pub(crate) fn len(&self) -> usize {
+        let len = self.len.load(Ordering::Relaxed);
+        if len > isize::MAX as usize {
+            0
+        } else {
+            len
+        }
+    }
+
+    /// Ends the current window: its counts become the ones `churn_rate` is
+    /// computed from, and a new window starts empty.
+    pub(crate) fn rotate(&self) {
+        let insertions = self.window_insertions.swap(0, Ordering::Relaxed);
+        let evictions = self.window_evictions.swap(0, Ordering::Relaxed);
+        self.last_window_insertions
+            .store(insertions, Ordering::Relaxed);
+        self.last_window_evictions
+            .store(evictions, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> OccupancyStats {
+        let window_insertions = self.last_window_insertions.load(Ordering::Relaxed);
+        let window_evictions = self.last_window_evictions.load(Ordering::Relaxed);
+        OccupancyStats {
+            high_water_mark: self.high_water_mark.load(Ordering::Relaxed),
+            insertions: self.insertions.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            churn_rate: if window_insertions == 0 {
+                0.0
+            } else {
+                window_evictions as f64 / window_insertions as f64
+            },
+        }
+    }
+
+    /// Zeroes everything except the live entry count; the high-water mark
+    /// restarts from the current occupancy.
+    pub(crate) fn reset(&self) {
+        for c in [
+            &self.insertions,
+            &self.evictions,
+            &self.window_insertions,
+            &self.window_evictions,
+            &self.last_window_insertions,
+            &self.last_window_evictions,
+        ] {
+            c.store(0, Ordering::Relaxed);
+        }
+        self.high_water_mark.store(self.len(), Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,226 @@
+//! Atomic cell types usable as key and value storage in [`LRUCache`].
+//!
+//! [`LRUCache`]: crate::LRUCache
+
+use std::fmt::Debug;
+use std::sync::atomic::Ordering;
+
+use crate::sync::{fence, hint, AtomicU16, AtomicU32, AtomicU64, AtomicU8, AtomicUsize};
+
+/// An atomic cell holding a plain `Copy` value.
+///
+/// The cache stores keys and values in flat arrays of these cells so that
+/// readers never need a lock. A load must never return a mix of two stores.
+pub trait AtomicStorage: Send + Sync {
+    type Value: Copy + Eq + Debug + Send + Sync;
+
+    /// What emptied slots are reset to. Slots also carry an occupancy
+    /// state, so this is an ordinary, storable value.
+    const ZERO: Self::Value;
+
+    fn new(value: Self::Value) -> Self;
+    fn load(&self, order: Ordering) -> Self::Value;
+    fn store(&self, value: Self::Value, order: Ordering);
+    /// Widens (or truncates) a value to the `usize` handed to the fold hasher.
+    fn as_usize(value: Self::Value) -> usize;
+
+    /// Bytes a value takes up in a
+    /// [`to_snapshot`](crate::LRUCache::to_snapshot) image.
+    const SIZE: usize;
+    /// Appends `value` to `out` as `SIZE` little-endian bytes.
+    fn write_bytes(value: Self::Value, out: &mut Vec<u8>);
+    /// Reads back a value written by [`write_bytes`](Self::write_bytes)
+    /// from exactly `SIZE` bytes.
+    fn read_bytes(bytes: &[u8]) -> Self::Value;
+}
+
+macro_rules! impl_atomic_storage {
+    ($($atomic:ty => $int:ty),* $(,)?) => {$(
+        impl AtomicStorage for $atomic {
+            type Value = $int;
+
+            const ZERO: $int = 0;
+
+            fn new(value: $int) -> Self {
+                <$atomic>::new(value)
+            }
+
+            fn load(&self, order: Ordering) -> $int {
+                <$atomic>::load(self, order)
+            }
+
+            fn store(&self, value: $int, order: Ordering) {
+                <$atomic>::store(self, value, order)
+            }
+
+            fn as_usize(value: $int) -> usize {
+                value as usize
+            }
+
+            const SIZE: usize = std::mem::size_of::<$int>();
+
+            fn write_bytes(value: $int, out: &mut Vec<u8>) {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+
+            fn read_bytes(bytes: &[u8]) -> $int {
+                <$int>::from_le_bytes(bytes.try_into().expect("SIZE bytes"))
+            }
+        }
+    )*};
+}
+
+impl_atomic_storage! {
+    AtomicUsize => usize,
+    AtomicU64 => u64,
+    AtomicU32 => u32,
+    AtomicU16 => u16,
+    AtomicU8 => u8,
+}
+
+/// A `[u8; N]` cell guarded by a sequence counter (a seqlock), for values
+/// wider than any atomic integer.
+///
+/// The counter is even while the bytes are stable and odd while a store is
+/// copying them in. A load reads the counter, the bytes and the counter
+/// again, and retries until both reads agree on an even count, so it never
+/// returns a torn value. Loads spin while a store is in progress; stores
+/// take turns by claiming the odd count with a compare-and-swap.
+pub struct SeqLockBytes<const N: usize> {
+    seq: AtomicUsize,
+    bytes: [AtomicU8; N],
+}
+
+impl<const N: usize> AtomicStorage for SeqLockBytes<N> {
+    type Value = [u8; N];
+
+    const ZERO: [u8; N] = [0; N];
+
+    fn new(value: [u8; N]) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            bytes: std::array::from_fn(|i| AtomicU8::new(value[i])),
+        }
+    }
+
+    /// Always at least `Acquire`.
+    fn load(&self, order: Ordering) -> [u8; N] {
+        if order == Ordering::SeqCst {
+            fence(Ordering::SeqCst);
+        }
+        loop {
+            let before = self.seq.load(Ordering::Acquire);
+            if before % 2 == 1 {
+                hint::spin_loop();
+                continue;
+            }
+            let value = std::array::from_fn(|i| self.bytes[i].load(Ordering::Relaxed));
+            // Keeps the byte loads above from moving past the re-check.
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return value;
+            }
+        }
+    }
+
+    /// Always at least `Release`.
+    fn store(&self, value: [u8; N], order: Ordering) {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq % 2 == 1 {
+                hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+                continue;
+            }
+            match self
+                .seq
+                .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => break,
+                Err(current) => seq = current,
+            }
+        }
+        // Keeps the byte stores below from moving before the odd count.
+        fence(Ordering::Release);
+        for (cell, byte) in self.bytes.iter().zip(value) {
+            cell.store(byte, Ordering::Relaxed);
+        }
+        self.seq.store(seq + 2, Ordering::Release);
+        if order == Ordering::SeqCst {
+            fence(Ordering::SeqCst);
+        }
+    }
+
+    /// Folds the bytes a machine word at a time, so byte-array keys spread
+    /// over folds too.
+    fn as_usize(value: [u8; N]) -> usize {
+        const WORD: usize = std::mem::size_of::<usize>();
+        value.chunks(WORD).fold(0, |acc, chunk| {
+            let mut word = [0; WORD];
+            word[..chunk.len()].copy_from_slice(chunk);
+            acc.rotate_left(5) ^ usize::from_le_bytes(word)
+        })
+    }
+
+    const SIZE: usize = N;
+
+    fn write_bytes(value: [u8; N], out: &mut Vec<u8>) {
+        out.extend_from_slice(&value);
+    }
+
+    fn read_bytes(bytes: &[u8]) -> [u8; N] {
+        bytes.try_into().expect("SIZE bytes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn seqlock_round_trips() {
+        let cell = SeqLockBytes::<24>::new([7; 24]);
+        assert_eq!(cell.load(Ordering::Acquire), [7; 24]);
+        let value = std::array::from_fn(|i| i as u8);
+        cell.store(value, Ordering::Release);
+        assert_eq!(cell.load(Ordering::Relaxed), value);
+        assert_eq!(cell.seq.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn as_usize_depends_on_every_word() {
+        let a = [0u8; 24];
+        let mut b = a;
+        b[20] = 1;
+        assert_ne!(
+            SeqLockBytes::<24>::as_usize(a),
+            SeqLockBytes::<24>::as_usize(b)
+        );
+        assert_eq!(SeqLockBytes::<3>::as_usize([1, 0, 0]), 1);
+    }
+
+    #[test]
+    fn concurrent_stores_are_never_torn() {
+        let cell = Arc::new(SeqLockBytes::<64>::new([0; 64]));
+        let handles: Vec<_> = (0..4u8)
+            .map(|t| {
+                let cell = Arc::clone(&cell);
+                thread::spawn(move || {
+                    for i in 0..20_000u32 {
+                        if t < 2 {
+                            cell.store([(i as u8) ^ (t * 0x55); 64], Ordering::Release);
+                        } else {
+                            let value = cell.load(Ordering::Acquire);
+                            assert!(value.iter().all(|&b| b == value[0]), "torn: {value:?}");
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! The atomics and locks [`LRUCache`](crate::LRUCache) is built on. Under
+//! `--cfg loom` they are loom's model-checked versions instead, so the loom
+//! tests explore every interleaving of the slot protocol.
+
+#[cfg(loom)]
+pub(crate) use loom::{
+    hint,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicU8, AtomicUsize},
+        Mutex, MutexGuard, RwLock, RwLockReadGuard,
+    },
+};
+#[cfg(not(loom))]
+pub(crate) use std::{
+    hint,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicU16, AtomicU32, AtomicU64, AtomicU8, AtomicUsize},
+        Mutex, MutexGuard, RwLock, RwLockReadGuard,
+    },
+};
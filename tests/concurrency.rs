@@ -0,0 +1,669 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use lru_rs::{EvictionPolicy, LRUCache, LRUCacheBytes};
+
+type Cache = LRUCache<AtomicUsize, AtomicUsize>;
+
+fn identity(k: usize) -> usize {
+    k
+}
+
+fn spawn_all<F>(threads: usize, f: F)
+where
+    F: Fn(usize) + Send + Sync + 'static,
+{
+    let f = Arc::new(f);
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let f = Arc::clone(&f);
+            thread::spawn(move || f(t))
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn readers_never_see_foreign_values() {
+    for policy in EvictionPolicy::ALL {
+        let cache = Arc::new(Cache::with_policy(64, 4, identity, policy));
+        let c = Arc::clone(&cache);
+        spawn_all(8, move |t| {
+            for i in 0..20_000 {
+                let key = 1 + (i * 31 + t * 7) % 256;
+                if t % 2 == 0 {
+                    c.put(key, key * 3);
+                } else if let Some(v) = c.get(key) {
+                    assert_eq!(v, key * 3, "value for {key} belongs to another key");
+                }
+            }
+        });
+        assert!(cache.len() <= cache.capacity());
+    }
+}
+
+#[test]
+fn long_fold_scans_never_return_an_aliased_value() {
+    // One fold whose length is not a multiple of the scan chunk, with keys
+    // coming and going in every chunk while readers scan the whole fold.
+    let cache = Arc::new(Cache::new(1_003, 1, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(8, move |t| {
+        for i in 0..20_000 {
+            let key = 1 + (i * 37 + t * 11) % 1_500;
+            match t % 4 {
+                0 => {
+                    c.put(key, key * 3);
+                }
+                1 => {
+                    c.remove(key);
+                }
+                _ => {
+                    if let Some(v) = c.get(key) {
+                        assert_eq!(v, key * 3, "value for {key} belongs to another key");
+                    }
+                }
+            }
+        }
+    });
+    assert!(cache.len() <= cache.capacity());
+}
+
+#[test]
+fn auto_fold_configuration_keeps_values_consistent() {
+    let cache = Arc::new(Cache::with_auto_folds(256, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(8, move |t| {
+        for i in 0..20_000 {
+            let key = 1 + (i * 31 + t * 7) % 1024;
+            if t % 2 == 0 {
+                c.put(key, key * 3);
+            } else if let Some(v) = c.get(key) {
+                assert_eq!(v, key * 3, "value for {key} belongs to another key");
+            }
+        }
+    });
+    assert!(cache.len() <= cache.capacity());
+    assert!(cache.num_folds() <= 256 / lru_rs::MIN_SLOTS_PER_FOLD);
+}
+
+#[test]
+fn bounded_probe_relocations_keep_values_consistent() {
+    let cache = Arc::new(Cache::new(64, 2, identity).with_max_probe(8));
+    let c = Arc::clone(&cache);
+    spawn_all(6, move |t| {
+        for i in 0..20_000 {
+            let key = 1 + (i * 17 + t * 5) % 96;
+            match t {
+                0 | 1 => {
+                    c.put(key, key * 3);
+                }
+                2 => {
+                    c.remove(key);
+                }
+                _ => {
+                    if let Some(v) = c.get(key) {
+                        assert_eq!(v, key * 3, "value for {key} belongs to another key");
+                    }
+                }
+            }
+        }
+    });
+    assert!(cache.len() <= cache.capacity());
+}
+
+#[test]
+fn concurrent_puts_respect_capacity() {
+    let cache = Arc::new(Cache::new(128, 8, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(8, move |t| {
+        for i in 0..5_000 {
+            c.put(1 + t * 100_000 + i, i);
+            assert!(c.len() <= 128);
+        }
+    });
+    assert_eq!(cache.len(), 128);
+}
+
+#[test]
+fn hot_key_stress() {
+    let cache = Arc::new(Cache::new(64, 4, identity));
+    cache.put(42, 4200);
+    let c = Arc::clone(&cache);
+    spawn_all(16, move |t| {
+        for i in 0..10_000 {
+            if t == 0 && i % 10 == 0 {
+                c.put(1_000 + i, i);
+            }
+            assert_eq!(c.get(42), Some(4200));
+        }
+    });
+}
+
+#[test]
+fn sampled_hot_key_outranks_churn() {
+    let cache = Arc::new(
+        Cache::builder()
+            .capacity(8)
+            .policy(EvictionPolicy::Lfu)
+            .hit_sampling(4)
+            .build(),
+    );
+    cache.put(42, 4200);
+    let c = Arc::clone(&cache);
+    spawn_all(8, move |t| {
+        for i in 0..10_000 {
+            if t == 0 && i % 10 == 0 {
+                c.put(1_000 + i, i);
+            }
+            assert_eq!(c.get(42), Some(4200));
+        }
+    });
+    assert!(cache.hit_count(42).unwrap() > 40_000);
+}
+
+#[test]
+fn striped_hit_counts_lose_no_hits() {
+    let cache = Arc::new(
+        Cache::builder()
+            .capacity(8)
+            .policy(EvictionPolicy::Lfu)
+            .hit_stripes(4)
+            .build(),
+    );
+    cache.put(42, 4200);
+    let c = Arc::clone(&cache);
+    spawn_all(8, move |_| {
+        for _ in 0..10_000 {
+            assert_eq!(c.get(42), Some(4200));
+        }
+    });
+    assert_eq!(cache.hit_count(42), Some(80_001));
+}
+
+#[test]
+fn remove_and_clear_race_with_readers() {
+    let cache = Arc::new(Cache::new(32, 2, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(6, move |t| {
+        for i in 0..10_000 {
+            let key = 1 + i % 48;
+            match t {
+                0 => {
+                    c.put(key, key + 1);
+                }
+                1 => {
+                    c.remove(key);
+                }
+                2 if i % 1_000 == 0 => c.clear(),
+                _ => {
+                    if let Some(v) = c.get(key) {
+                        assert_eq!(v, key + 1);
+                    }
+                }
+            }
+        }
+    });
+    assert!(cache.len() <= cache.capacity());
+}
+
+#[test]
+fn get_or_insert_with_computes_once_per_miss() {
+    let cache = Arc::new(Cache::new(64, 4, identity));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let barrier = Arc::new(Barrier::new(16));
+    let (c, n, b) = (Arc::clone(&cache), Arc::clone(&calls), barrier);
+    spawn_all(16, move |_| {
+        b.wait();
+        let v = c.get_or_insert_with(7, || {
+            n.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            70
+        });
+        assert_eq!(v, 70);
+    });
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+    // A key in another fold is computed independently.
+    assert_eq!(cache.get_or_insert_with(8, || 80), 80);
+}
+
+#[test]
+fn concurrent_modify_loses_no_increments() {
+    let cache = Arc::new(Cache::new(16, 2, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(8, move |t| {
+        for i in 0..10_000 {
+            c.upsert(9, 1, |v| v + 1);
+            // Plain puts of other keys keep the lock-free path busy.
+            c.put(2 * (i % 4) + t % 2, i);
+        }
+    });
+    assert_eq!(cache.get(9), Some(80_000));
+}
+
+#[test]
+fn remove_if_only_drops_matching_keys_under_readers() {
+    let cache = Arc::new(Cache::new(256, 8, identity));
+    for k in 0..200 {
+        cache.put(k, k);
+    }
+    let c = Arc::clone(&cache);
+    spawn_all(4, move |t| {
+        if t == 0 {
+            assert_eq!(c.remove_if(|k, _| (50..150).contains(&k)), 100);
+            return;
+        }
+        for i in 0..20_000 {
+            let k = (i * 7 + t) % 200;
+            if let Some(v) = c.get(k) {
+                assert_eq!(v, k);
+            }
+            if !(50..150).contains(&k) {
+                assert_eq!(c.get(k), Some(k), "{k} does not match");
+            }
+        }
+    });
+    for k in 0..200 {
+        assert_eq!(cache.get(k).is_some(), !(50..150).contains(&k));
+    }
+}
+
+#[test]
+fn contains_key_never_reports_uninserted_keys() {
+    // One fold, so every put repurposes slots the readers are scanning.
+    let cache = Arc::new(Cache::new(16, 1, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(4, move |t| {
+        for i in 0..50_000 {
+            if t < 2 {
+                // Writers only ever insert even keys.
+                c.put(2 * (1 + (i * 7 + t) % 64), i);
+            } else {
+                let odd = 2 * (i % 64) + 1;
+                assert!(!c.contains_key(odd), "{odd} was never inserted");
+            }
+        }
+    });
+    assert!(cache.len() <= 16);
+}
+
+#[test]
+fn clear_hides_every_earlier_insert() {
+    const WRITERS: usize = 3;
+    let cache = Arc::new(Cache::new(64, 4, identity));
+    // Index of the last put each writer has seen return.
+    let completed: Arc<Vec<AtomicUsize>> =
+        Arc::new((0..WRITERS).map(|_| AtomicUsize::new(0)).collect());
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|t| {
+            let (cache, completed, stop) = (
+                Arc::clone(&cache),
+                Arc::clone(&completed),
+                Arc::clone(&stop),
+            );
+            thread::spawn(move || {
+                let mut i = 1;
+                while !stop.load(Ordering::Relaxed) {
+                    // Values encode writer and sequence number.
+                    cache.put(1 + (i * 7 + t) % 64, t + WRITERS * i);
+                    completed[t].store(i, Ordering::SeqCst);
+                    i += 1;
+                }
+            })
+        })
+        .collect();
+    for _ in 0..500 {
+        let before: Vec<_> = completed.iter().map(|c| c.load(Ordering::SeqCst)).collect();
+        cache.clear();
+        for k in 1..=64 {
+            if let Some(v) = cache.get(k) {
+                let (t, i) = (v % WRITERS, v / WRITERS);
+                assert!(i > before[t], "put {i} of writer {t} survived the clear");
+            }
+        }
+    }
+    stop.store(true, Ordering::Relaxed);
+    for h in writers {
+        h.join().unwrap();
+    }
+}
+
+#[test]
+fn clear_is_never_seen_half_done() {
+    use lru_rs::EvictReason;
+    // Identity hashing over 4 folds: key 4 is in fold 0, swept first, and key
+    // 3 in fold 3, swept last. The listener stalls the sweep in between.
+    let cache = Arc::new(
+        LRUCache::<AtomicUsize, AtomicUsize>::new(8, 4, identity).with_on_evict(|k, _, reason| {
+            if k == 4 && reason == EvictReason::Cleared {
+                thread::sleep(Duration::from_millis(20));
+            }
+        }),
+    );
+    for _ in 0..5 {
+        cache.put(4, 40);
+        cache.put(3, 30);
+        let clearer = {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || cache.clear())
+        };
+        while !clearer.is_finished() {
+            let first = cache.get(4);
+            let last = cache.get(3);
+            assert!(
+                first.is_some() || last.is_none(),
+                "saw fold 0 cleared but fold 3 not"
+            );
+        }
+        clearer.join().unwrap();
+        assert!(cache.is_empty());
+    }
+}
+
+#[test]
+fn zero_key_survives_churn() {
+    // One fold, so every put competes with key 0 for the same slots.
+    let cache = Arc::new(Cache::new(16, 1, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(6, move |t| {
+        for i in 0..20_000 {
+            match t {
+                0 => {
+                    c.put(0, i);
+                }
+                1 | 2 => {
+                    c.put(1 + (i * 7 + t) % 32, 0);
+                }
+                _ => {
+                    // Every other key is stored with value 0.
+                    if let Some(v) = c.get(1 + i % 32) {
+                        assert_eq!(v, 0, "key {} saw key 0's value", 1 + i % 32);
+                    }
+                }
+            }
+        }
+    });
+    cache.put(0, 7);
+    assert_eq!(cache.get(0), Some(7));
+}
+
+#[test]
+fn resize_under_load_keeps_values_consistent() {
+    let cache = Arc::new(Cache::new(64, 4, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(5, move |t| {
+        for i in 0..20_000 {
+            let key = (i * 13 + t) % 256;
+            match t {
+                0 if i % 200 == 0 => c.resize(if i % 400 == 0 { 16 } else { 128 }),
+                0 | 1 => {
+                    c.put(key, key * 3);
+                }
+                _ => {
+                    if let Some(v) = c.get(key) {
+                        assert_eq!(v, key * 3, "value for {key} belongs to another key");
+                    }
+                }
+            }
+        }
+    });
+    cache.resize(32);
+    assert_eq!(cache.capacity(), 32);
+    assert!(cache.len() <= 32);
+}
+
+#[test]
+fn compact_under_load_keeps_every_key_once() {
+    let cache = Arc::new(Cache::new(128, 4, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(5, move |t| {
+        for i in 0..20_000 {
+            let key = (i * 13 + t) % 192;
+            match t {
+                0 if i % 100 == 0 => {
+                    c.compact();
+                }
+                0 | 1 => {
+                    c.put(key, key * 3);
+                }
+                2 => {
+                    c.remove(key);
+                }
+                _ => {
+                    if let Some(v) = c.get(key) {
+                        assert_eq!(v, key * 3, "value for {key} belongs to another key");
+                    }
+                }
+            }
+        }
+    });
+    let mut keys = cache.keys();
+    keys.sort_unstable();
+    keys.dedup();
+    assert_eq!(keys.len(), cache.len());
+    cache.compact();
+    assert!(keys.iter().all(|&k| cache.get(k) == Some(k * 3)));
+}
+
+#[test]
+fn snapshot_only_reports_inserted_pairs() {
+    // Identity hashing over 4 folds: fold 0 holds the fixed keys, the
+    // writers churn the other three.
+    let cache = Arc::new(Cache::new(64, 4, identity));
+    for k in (0..64).step_by(4) {
+        cache.put(k, k + 1);
+    }
+    let c = Arc::clone(&cache);
+    spawn_all(4, move |t| {
+        for i in 0..5_000 {
+            if t < 3 {
+                let key = 4 * ((i * 7) % 64) + 1 + t;
+                c.put(key, key * 3 + i % 2);
+            } else if i % 50 == 0 {
+                for (k, v, _) in c.snapshot() {
+                    if k % 4 == 0 {
+                        assert_eq!(v, k + 1, "fixed key {k} changed");
+                    } else {
+                        assert!(v == k * 3 || v == k * 3 + 1, "{k} => {v} was never put");
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn racing_inserts_of_one_key_fill_one_slot() {
+    const THREADS: usize = 8;
+    // One fold with room to spare, so every put takes the lock-free path.
+    let cache = Arc::new(Cache::new(64, 1, identity));
+    let barrier = Arc::new(Barrier::new(THREADS));
+    for round in 0..200 {
+        let key = round % 4;
+        let (c, b) = (Arc::clone(&cache), Arc::clone(&barrier));
+        spawn_all(THREADS, move |t| {
+            b.wait();
+            c.put(key, t);
+        });
+        let copies = cache.keys().into_iter().filter(|&k| k == key).count();
+        assert_eq!(copies, 1, "round {round} stored {key} {copies} times");
+        assert!(cache.get(key).is_some_and(|v| v < THREADS));
+        if round % 3 == 0 {
+            cache.remove(key);
+        }
+    }
+}
+
+#[test]
+fn byte_values_are_never_torn() {
+    const N: usize = 40;
+    /// The key in the first word, then one fill byte repeated.
+    fn encode(key: usize, fill: u8) -> [u8; N] {
+        let mut value = [fill; N];
+        value[..8].copy_from_slice(&(key as u64).to_le_bytes());
+        value
+    }
+    // Few keys in one fold, so updates, evictions and reads hit the same
+    // slots.
+    let cache: Arc<LRUCacheBytes<N>> = Arc::new(LRUCache::new(8, 1, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(6, move |t| {
+        for i in 0..20_000 {
+            let key = (i * 7 + t) % 12;
+            if t < 3 {
+                c.put(key, encode(key, (i + t) as u8));
+            } else if let Some(v) = c.get(key) {
+                assert_eq!(
+                    v[..8],
+                    (key as u64).to_le_bytes(),
+                    "{key} got another key's value"
+                );
+                assert!(
+                    v[8..].iter().all(|&b| b == v[8]),
+                    "torn value for {key}: {v:?}"
+                );
+            }
+        }
+    });
+}
+
+#[test]
+fn recycled_slot_never_mixes_writes() {
+    // One slot, recycled between two keys and between remove and put of the
+    // same key. Key 7 only ever holds odd values and key 8 non-zero even
+    // ones, so an empty slot's zero or the other key's value is caught.
+    let cache = Arc::new(Cache::new(1, 1, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(4, move |t| {
+        for i in 0..50_000 {
+            match t {
+                0 => {
+                    c.remove(7);
+                    c.put(7, 2 * i + 1);
+                }
+                1 => {
+                    c.put(8, 2 * i + 2);
+                }
+                _ => {
+                    if let Some(v) = c.get(7) {
+                        assert_eq!(v % 2, 1, "key 7 read {v}");
+                    }
+                    if let Some(v) = c.get(8) {
+                        assert!(v != 0 && v % 2 == 0, "key 8 read {v}");
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[test]
+fn put_returning_only_reports_inserted_pairs() {
+    const THREADS: usize = 6;
+    let cache = Arc::new(Cache::new(32, 2, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(THREADS, move |t| {
+        for i in 0..20_000 {
+            let key = 1 + (i * 13 + t * 3) % 128;
+            if let Some((k, v)) = c.put_returning(key, key * 1000 + t) {
+                assert_eq!(v / 1000, k, "evicted value for {k} belongs to another key");
+                assert!(v % 1000 < THREADS, "evicted value {v} was never put");
+            }
+        }
+    });
+    assert!(cache.len() <= cache.capacity());
+}
+
+#[test]
+fn len_counter_matches_a_scan_after_churn() {
+    let cache = Arc::new(Cache::new(64, 4, identity).with_max_probe(8));
+    let c = Arc::clone(&cache);
+    spawn_all(6, move |t| {
+        for i in 0..20_000 {
+            let key = (i * 7 + t * 13) % 160;
+            match t % 3 {
+                0 | 1 => {
+                    c.put(key, key);
+                }
+                _ if i % 500 == 0 => c.clear(),
+                _ if i % 2 == 0 => {
+                    c.remove(key);
+                }
+                _ => {
+                    c.pop_least_used();
+                }
+            }
+            assert!(c.len() <= c.capacity());
+        }
+    });
+    assert_eq!(cache.len(), cache.snapshot().len());
+    cache.clear();
+    assert_eq!(cache.len(), 0);
+}
+
+#[test]
+fn fold_counts_match_a_scan_after_lock_free_churn() {
+    // Lock-free puts claim and release slots; folds straddle bitmap words.
+    let cache = Arc::new(Cache::new(200, 3, identity));
+    let c = Arc::clone(&cache);
+    spawn_all(6, move |t| {
+        for i in 0..20_000 {
+            let key = (i * 7 + t * 13) % 300;
+            if t % 2 == 0 {
+                c.put(key, key);
+            } else {
+                c.remove(key);
+            }
+        }
+    });
+    let per_fold: usize = cache.fold_utilization().iter().map(|&(n, _)| n).sum();
+    assert_eq!(per_fold, cache.snapshot().len());
+    assert_eq!(per_fold, cache.len());
+    for key in 300..600 {
+        cache.put(key, key);
+    }
+    assert_eq!(cache.fold_utilization(), [(67, 67), (67, 67), (66, 66)]);
+}
+
+#[test]
+fn rebalanced_folds_never_duplicate_or_mix_keys() {
+    // Keys only hash to even folds, which borrow from the odd ones.
+    let cache = Arc::new(Cache::new(64, 4, |k| 2 * k).with_rebalancing(25));
+    let c = Arc::clone(&cache);
+    spawn_all(8, move |t| {
+        for i in 0..20_000 {
+            let key = 1 + (i * 31 + t * 7) % 128;
+            match (t + i) % 8 {
+                0 => {
+                    c.remove(key);
+                }
+                1 => {
+                    c.modify(key, |v| v);
+                }
+                2..=4 => {
+                    c.put(key, key * 3);
+                }
+                _ => {
+                    if let Some(v) = c.get(key) {
+                        assert_eq!(v, key * 3);
+                    }
+                }
+            }
+        }
+    });
+    assert!(cache.stats().rebalanced > 0);
+    let mut keys = cache.keys();
+    assert_eq!(keys.len(), cache.len());
+    keys.sort_unstable();
+    keys.dedup();
+    assert_eq!(keys.len(), cache.len(), "a key was cached twice");
+    for (key, value, _) in cache.snapshot() {
+        assert_eq!(value, key * 3);
+    }
+}
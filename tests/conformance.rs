@@ -0,0 +1,221 @@
+//! Behaviour every cache in the workspace must share, run against each
+//! implementation through a thin adapter trait.
+
+use std::sync::Arc;
+use std::thread;
+
+use lru_rs::FastUsizeCache;
+use solution_1::LruCache;
+use solution_2::ShardedLruCache;
+
+trait Conformance: Send + Sync + 'static {
+    fn with_capacity(capacity: usize) -> Self;
+    /// A cache whose entries all compete for the same slots.
+    fn unpartitioned(capacity: usize) -> Self
+    where
+        Self: Sized,
+    {
+        Self::with_capacity(capacity)
+    }
+    fn get(&self, key: usize) -> Option<usize>;
+    fn put(&self, key: usize, value: usize);
+    /// Reads `key` without marking it used.
+    fn peek(&self, key: usize) -> Option<usize>;
+    fn entries(&self) -> Vec<(usize, usize)>;
+    fn len(&self) -> usize;
+}
+
+impl Conformance for FastUsizeCache {
+    fn with_capacity(capacity: usize) -> Self {
+        FastUsizeCache::new(capacity)
+    }
+    fn get(&self, key: usize) -> Option<usize> {
+        FastUsizeCache::get(self, &key)
+    }
+    fn put(&self, key: usize, value: usize) {
+        FastUsizeCache::put(self, key, value);
+    }
+    fn peek(&self, key: usize) -> Option<usize> {
+        FastUsizeCache::peek(self, &key)
+    }
+    fn entries(&self) -> Vec<(usize, usize)> {
+        FastUsizeCache::entries(self)
+    }
+    fn len(&self) -> usize {
+        FastUsizeCache::len(self)
+    }
+}
+
+impl Conformance for LruCache<usize, usize> {
+    fn with_capacity(capacity: usize) -> Self {
+        LruCache::new(capacity)
+    }
+    fn get(&self, key: usize) -> Option<usize> {
+        LruCache::get(self, &key)
+    }
+    fn put(&self, key: usize, value: usize) {
+        LruCache::put(self, key, value);
+    }
+    fn peek(&self, key: usize) -> Option<usize> {
+        LruCache::peek(self, &key)
+    }
+    fn entries(&self) -> Vec<(usize, usize)> {
+        LruCache::iter(self)
+    }
+    fn len(&self) -> usize {
+        LruCache::len(self)
+    }
+}
+
+impl Conformance for ShardedLruCache<usize, usize> {
+    fn with_capacity(capacity: usize) -> Self {
+        ShardedLruCache::new(capacity, 4)
+    }
+    fn unpartitioned(capacity: usize) -> Self {
+        ShardedLruCache::new(capacity, 1)
+    }
+    fn get(&self, key: usize) -> Option<usize> {
+        ShardedLruCache::get(self, &key)
+    }
+    fn put(&self, key: usize, value: usize) {
+        ShardedLruCache::put(self, key, value);
+    }
+    fn peek(&self, key: usize) -> Option<usize> {
+        // No keyed peek; `iter` reads every entry without touching any.
+        let entries = ShardedLruCache::iter(self);
+        entries.into_iter().find(|&(k, _)| k == key).map(|(_, v)| v)
+    }
+    fn entries(&self) -> Vec<(usize, usize)> {
+        ShardedLruCache::iter(self)
+    }
+    fn len(&self) -> usize {
+        ShardedLruCache::len(self)
+    }
+}
+
+fn starts_empty<C: Conformance>() {
+    let cache = C::with_capacity(16);
+    assert_eq!(cache.len(), 0);
+    assert_eq!(cache.get(1), None);
+}
+
+fn put_then_get<C: Conformance>() {
+    let cache = C::with_capacity(16);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.get(1), Some(10));
+    assert_eq!(cache.get(2), Some(20));
+    cache.put(1, 11);
+    assert_eq!(cache.get(1), Some(11));
+    assert_eq!(cache.len(), 2);
+}
+
+fn latest_insert_survives<C: Conformance>() {
+    let cache = C::with_capacity(8);
+    for k in 1..=100 {
+        cache.put(k, k * 2);
+        assert_eq!(cache.get(k), Some(k * 2));
+        assert!(cache.len() <= 8);
+    }
+}
+
+fn single_slot<C: Conformance>() {
+    let cache = C::with_capacity(1);
+    cache.put(1, 1);
+    cache.put(2, 2);
+    assert_eq!(cache.get(1), None);
+    assert_eq!(cache.get(2), Some(2));
+    assert_eq!(cache.len(), 1);
+}
+
+fn peek_does_not_refresh<C: Conformance>() {
+    let cache = C::unpartitioned(2);
+    cache.put(1, 10);
+    cache.put(2, 20);
+    assert_eq!(cache.peek(1), Some(10));
+    assert_eq!(cache.peek(3), None);
+    cache.put(3, 30);
+    assert_eq!(cache.peek(1), None);
+    assert_eq!(cache.get(3), Some(30));
+}
+
+fn entries_match_contents<C: Conformance>() {
+    let cache = C::with_capacity(16);
+    for k in 1..=5 {
+        cache.put(k, k * 10);
+    }
+    let mut entries = cache.entries();
+    entries.sort_unstable();
+    assert_eq!(entries, (1..=5).map(|k| (k, k * 10)).collect::<Vec<_>>());
+}
+
+fn concurrent_bound<C: Conformance>() {
+    let cache = Arc::new(C::with_capacity(64));
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let cache = Arc::clone(&cache);
+            thread::spawn(move || {
+                for i in 1..=2_000 {
+                    let key = t * 10_000 + i;
+                    cache.put(key, key);
+                    if let Some(v) = cache.get(key) {
+                        assert_eq!(v, key);
+                    }
+                }
+            })
+        })
+        .collect();
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert!(cache.len() <= 64);
+}
+
+macro_rules! conformance {
+    ($($name:ident => $ty:ty),* $(,)?) => {$(
+        mod $name {
+            use super::*;
+
+            #[test]
+            fn starts_empty() {
+                super::starts_empty::<$ty>();
+            }
+
+            #[test]
+            fn put_then_get() {
+                super::put_then_get::<$ty>();
+            }
+
+            #[test]
+            fn latest_insert_survives() {
+                super::latest_insert_survives::<$ty>();
+            }
+
+            #[test]
+            fn single_slot() {
+                super::single_slot::<$ty>();
+            }
+
+            #[test]
+            fn peek_does_not_refresh() {
+                super::peek_does_not_refresh::<$ty>();
+            }
+
+            #[test]
+            fn entries_match_contents() {
+                super::entries_match_contents::<$ty>();
+            }
+
+            #[test]
+            fn concurrent_bound() {
+                super::concurrent_bound::<$ty>();
+            }
+        }
+    )*};
+}
+
+conformance! {
+    fast_usize => FastUsizeCache,
+    single_lock => LruCache<usize, usize>,
+    sharded => ShardedLruCache<usize, usize>,
+}
@@ -0,0 +1,14 @@
+#!/bin/sh
+# Builds, lints and tests the root crate under every feature combination,
+# then solution_2 without std.
+set -eu
+cd "$(dirname "$0")/.."
+for features in "" "sharded" "single-lock" "sharded single-lock"; do
+    echo "== features: [${features}]"
+    cargo clippy --all-targets --no-default-features --features "$features" -- -D warnings
+    cargo test --no-default-features --features "$features"
+done
+echo "== solution_2 features: [no_std]"
+cd solution_2
+cargo clippy --all-targets --no-default-features --features no_std -- -D warnings
+cargo test --no-default-features --features no_std
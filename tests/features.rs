@@ -0,0 +1,23 @@
+//! The feature-gated re-exports. Run every combination with
+//! `tests/feature_matrix.sh`.
+
+#[cfg(feature = "sharded")]
+#[test]
+fn sharded_is_reexported() {
+    use lru_rs::sharded::{CacheStats, ShardedLruCache};
+    let cache = ShardedLruCache::new(4, 2);
+    cache.put(1, 10);
+    assert_eq!(cache.get(&1), Some(10));
+    // The sharded stats type lives beside the root one without clashing.
+    let sharded: CacheStats = cache.stats();
+    let root: lru_rs::CacheStats = lru_rs::FastUsizeCache::new(4).stats();
+    assert_eq!((sharded.hits, root.hits), (1, 0));
+}
+
+#[cfg(feature = "single-lock")]
+#[test]
+fn single_lock_is_reexported() {
+    let cache = lru_rs::single_lock::LruCache::new(2);
+    cache.put(1, 10);
+    assert_eq!(cache.get(&1), Some(10));
+}
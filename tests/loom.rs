@@ -0,0 +1,101 @@
+//! Model checks of `LRUCache`'s slot protocol. Run with
+//! `RUSTFLAGS="--cfg loom" cargo test --release --test loom`.
+#![cfg(loom)]
+
+use loom::sync::atomic::AtomicUsize;
+use loom::sync::Arc;
+use loom::thread;
+
+use lru_rs::LRUCache;
+
+type Cache = LRUCache<AtomicUsize, AtomicUsize>;
+
+/// Runs `f` under every interleaving with up to three preemptions, unless
+/// `LOOM_MAX_PREEMPTIONS` says otherwise; unbounded runs take many minutes.
+fn model<F: Fn() + Sync + Send + 'static>(f: F) {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound.get_or_insert(3);
+    builder.check(f);
+}
+
+fn cache(capacity: usize) -> Arc<Cache> {
+    Arc::new(Cache::new(capacity, 1, |k| k))
+}
+
+#[test]
+fn get_never_sees_the_value_an_eviction_stores() {
+    model(|| {
+        let cache = cache(1);
+        cache.put(1, 10);
+        let c = Arc::clone(&cache);
+        let writer = thread::spawn(move || {
+            c.put(2, 20);
+        });
+        assert!(matches!(cache.get(1), None | Some(10)));
+        assert!(matches!(cache.get(2), None | Some(20)));
+        writer.join().unwrap();
+        assert_eq!((cache.get(1), cache.get(2)), (None, Some(20)));
+    });
+}
+
+#[test]
+fn get_sees_the_old_or_new_value_of_an_update() {
+    model(|| {
+        let cache = cache(1);
+        cache.put(1, 10);
+        let c = Arc::clone(&cache);
+        let writer = thread::spawn(move || {
+            c.put(1, 11);
+        });
+        assert!(matches!(cache.get(1), Some(10 | 11)));
+        writer.join().unwrap();
+        assert_eq!(cache.get(1), Some(11));
+    });
+}
+
+#[test]
+fn racing_inserts_of_one_key_publish_it_once() {
+    model(|| {
+        let cache = cache(2);
+        let c = Arc::clone(&cache);
+        let writer = thread::spawn(move || {
+            c.put(1, 10);
+        });
+        cache.put(1, 11);
+        writer.join().unwrap();
+        assert_eq!(cache.keys(), [1]);
+        assert_eq!(cache.len(), 1);
+    });
+}
+
+#[test]
+fn remove_and_put_serialize() {
+    model(|| {
+        let cache = cache(1);
+        cache.put(1, 10);
+        let c = Arc::clone(&cache);
+        let remover = thread::spawn(move || c.remove(1));
+        cache.put(1, 11);
+        let removed = remover.join().unwrap();
+        let outcome = (removed, cache.get(1));
+        assert!(
+            matches!(outcome, (Some(10), Some(11)) | (Some(11), None)),
+            "{outcome:?}"
+        );
+        assert_eq!(cache.len(), usize::from(outcome.1.is_some()));
+    });
+}
+
+#[test]
+fn get_racing_clear_sees_the_entry_or_nothing() {
+    model(|| {
+        let cache = cache(1);
+        cache.put(1, 10);
+        let c = Arc::clone(&cache);
+        let clearer = thread::spawn(move || c.clear());
+        assert!(matches!(cache.get(1), None | Some(10)));
+        clearer.join().unwrap();
+        assert_eq!(cache.get(1), None);
+        assert!(cache.is_empty());
+    });
+}